@@ -0,0 +1,41 @@
+#![no_main]
+use inkwell::context::Context;
+use libfuzzer_sys::fuzz_target;
+use nyaan::compiler::*;
+use std::fs;
+use std::process::{Command, Stdio};
+
+// 生成したプログラムをインタプリタとLLVMで実行し, 標準出力と終了コードが同じことを確かめる.
+// LLVM側はpanicでプロセスが終わるので, JITではなく実行ファイルにして別のプロセスで動かす
+fuzz_target!(|data: &[u8]| {
+    let source = format::format(&nyaan_fuzz::generate::program(data), &[], &[]);
+    let tokens = tokenizer::tokenize(&mut source.chars()).unwrap_or_else(|e| panic!("tokenize failed: {}\n{}", e, source));
+    let ast = parser::token_to_expr(&tokens).unwrap_or_else(|e| panic!("parse failed: {}\n{}", e, source));
+    types::check(&ast).unwrap_or_else(|e| panic!("type check failed: {}\n{}", e, source));
+    // 定数式の0での割り算やあふれはどちらもコンパイルエラーなので比べない
+    let ast = match fold::fold(&ast) {
+        Ok(a) => a,
+        Err(_) => return,
+    };
+
+    let mut stdout = Vec::new();
+    let code = match interp::run(&ast, "fuzz.ny", &mut &b""[..], &mut stdout) {
+        Ok(c) => c,
+        Err(err) => panic!("the interpreter rejected a generated program: {}\n{}", err, source),
+    };
+
+    let exe = std::env::temp_dir().join(format!("nyaan-fuzz-{}", std::process::id()));
+    let mut config = emit::Config::new();
+    config.input = "fuzz.ny".to_string();
+    config.output = Some(exe.to_string_lossy().to_string());
+    let context = Context::create();
+    let module = generate(&context, &ast, &config).unwrap_or_else(|e| panic!("codegen failed: {}\n{}", e, source));
+    optimize::verify(&module).unwrap_or_else(|e| panic!("invalid LLVM IR: {}\n{}", e, source));
+    emit::emit(&module, &config);
+    let output = Command::new(&exe).stdin(Stdio::null()).output().expect("cannot run the compiled program");
+    let _ = fs::remove_file(&exe);
+
+    assert_eq!(String::from_utf8_lossy(&stdout), String::from_utf8_lossy(&output.stdout), "stdout differs for\n{}", source);
+    // 終了コードはOSが下位8ビットにする
+    assert_eq!(Some(code & 0xff), output.status.code(), "exit status differs for\n{}", source);
+});
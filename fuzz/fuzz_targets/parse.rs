@@ -0,0 +1,24 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use nyaan::compiler::*;
+
+// どんな入力でもpanicやスタックの溢れを起こさずにエラーを返すこと.
+// 落ちた入力は fuzz/regressions/parse/ に置くと tests/fuzz.rs が同じ手順で流す
+fuzz_target!(|data: &[u8]| {
+    let text = String::from_utf8_lossy(data);
+    let tokens = match tokenizer::tokenize(&mut text.chars()) {
+        Ok(t) => t,
+        Err(_) => return,
+    };
+    let ast = match parser::token_to_expr(&tokens) {
+        Ok(a) => a,
+        Err(_) => return,
+    };
+    // 読めるソースはnyaan fmtで整形できる
+    if let Err(err) = format::format_source(&text) {
+        panic!("{}\n{}", err, text);
+    }
+    if types::check(&ast).is_ok() {
+        let _ = fold::fold(&ast);
+    }
+});
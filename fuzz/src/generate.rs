@@ -0,0 +1,291 @@
+use nyaan::compiler::*;
+
+// fuzzの入力を選択肢の列として読み, どちらのバックエンドでも実行できる型の合ったプログラムを作る.
+// 入力を読み切ると選択肢はすべて0になり, 0は式を葉で終える方を選ぶので生成は必ず止まる
+
+const MAX_STATEMENTS: usize = 40;
+const MAX_DEPTH: usize = 3;
+
+struct Choices<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> Choices<'a> {
+    fn byte(&mut self) -> u8 {
+        let b = self.data.get(self.pos).copied().unwrap_or(0);
+        self.pos += 1;
+        b
+    }
+    fn below(&mut self, n: usize) -> usize {
+        self.byte() as usize % n
+    }
+    fn done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+// 今読める変数. ifの中で作った変数はifの外では使わない
+#[derive(Clone, Default)]
+struct Scope {
+    ints: Vec<String>,
+    strs: Vec<String>,
+    vecs: Vec<String>,
+}
+
+struct Generator<'a> {
+    choices: Choices<'a>,
+    names: usize,
+}
+
+pub fn program(data: &[u8]) -> Vec<Expr> {
+    let mut g = Generator { choices: Choices { data, pos: 0 }, names: 0 };
+    let mut scope = Scope::default();
+    let mut list = Vec::new();
+    while !g.choices.done() && list.len() < MAX_STATEMENTS {
+        g.statement(&mut scope, 0, &mut list);
+    }
+    if g.choices.below(4) == 0 {
+        let code = Expr::Int(Int::new(g.choices.below(4) as i32));
+        list.push(Expr::Return(Box::new(Return::new(code, true, Pos::default()))));
+    }
+    list.push(Expr::Nope);
+    list
+}
+
+fn ident(name: &str) -> Expr {
+    Expr::Ident(Box::new(Ident::new(name.to_string(), Kind::Ident)))
+}
+
+fn binary(kind: Kind, left: Expr, right: Expr) -> Expr {
+    Expr::BinaryOp(Box::new(BinaryOp::new(kind, left, right, Pos::default())))
+}
+
+fn assign(name: &str, value: Expr) -> Expr {
+    Expr::Assign(Box::new(Assign::new(ident(name), value, Pos::default())))
+}
+
+impl<'a> Generator<'a> {
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    fn pick(&mut self, list: &[String]) -> String {
+        list[self.choices.below(list.len())].clone()
+    }
+
+    fn statement(&mut self, scope: &mut Scope, depth: usize, list: &mut Vec<Expr>) {
+        match self.choices.below(12) {
+            0 | 1 => {
+                let value = self.int(scope, depth);
+                let name = self.fresh("i");
+                list.push(assign(&name, value));
+                scope.ints.push(name);
+            },
+            2 => {
+                let value = self.string(scope, depth);
+                let name = self.fresh("s");
+                list.push(assign(&name, value));
+                scope.strs.push(name);
+            },
+            3 if !scope.ints.is_empty() => {
+                let name = self.pick(&scope.ints);
+                let value = self.int(scope, depth);
+                list.push(assign(&name, value));
+            },
+            4 | 5 => {
+                let mut items = Vec::new();
+                for _ in 0..1 + self.choices.below(3) {
+                    if self.choices.below(3) == 0 {
+                        items.push(Expr::Str(Str::new(self.text())));
+                    } else {
+                        items.push(self.printable(scope, depth));
+                    }
+                }
+                list.push(Expr::Print(Box::new(Print::new(items, self.choices.below(4) != 0, Pos::default()))));
+            },
+            6 if depth < MAX_DEPTH => self.if_chain(scope, depth, list),
+            // vecはifの中では作らない (mainの最後でまとめて解放する)
+            7 if depth == 0 => {
+                let mut items = Vec::new();
+                for _ in 0..self.choices.below(4) {
+                    items.push(self.int(scope, depth + 1));
+                }
+                let name = self.fresh("v");
+                list.push(assign(&name, Expr::Vector(Box::new(Vector::new(items)))));
+                scope.vecs.push(name);
+            },
+            8 if !scope.vecs.is_empty() => {
+                let name = self.pick(&scope.vecs);
+                let value = self.int(scope, depth);
+                list.push(Expr::Push(Box::new(Push::new(ident(&name), value, Pos::default()))));
+            },
+            9 if !scope.vecs.is_empty() && !scope.ints.is_empty() => {
+                let name = self.pick(&scope.ints);
+                let vector = self.pick(&scope.vecs);
+                list.push(assign(&name, Expr::Pop(Box::new(Pop::new(ident(&vector), Pos::default())))));
+            },
+            // 失敗すると残りが実行されないので少なめにする
+            10 if self.choices.below(4) == 0 => {
+                let left = self.int(scope, depth);
+                let right = self.int(scope, depth);
+                let condition = Expr::Equal(Box::new(Equal::new(left, right)));
+                list.push(Expr::Assert(Box::new(Assert::new(condition, String::new(), Pos::default()))));
+            },
+            _ => {
+                let value = self.int(scope, depth);
+                let items = vec![value];
+                list.push(Expr::Print(Box::new(Print::new(items, true, Pos::default()))));
+            },
+        }
+    }
+
+    fn if_chain(&mut self, scope: &mut Scope, depth: usize, list: &mut Vec<Expr>) {
+        let condition = self.condition(scope, depth);
+        let block = self.block(scope, depth);
+        list.push(Expr::If(Box::new(If::new(condition, block, Pos::default()))));
+        for _ in 0..self.choices.below(3) {
+            let condition = self.condition(scope, depth);
+            let block = self.block(scope, depth);
+            list.push(Expr::ElseIf(Box::new(ElseIf::new(condition, block, Pos::default()))));
+        }
+        if self.choices.below(2) == 0 {
+            let block = self.block(scope, depth);
+            list.push(Expr::Else(Box::new(Else::new(block, Pos::default()))));
+        }
+    }
+
+    // パーサと同じく, ブロックの最後はNopeで閉じる
+    fn block(&mut self, scope: &Scope, depth: usize) -> Vec<Expr> {
+        let mut inner = scope.clone();
+        let mut list = Vec::new();
+        for _ in 0..self.choices.below(4) {
+            self.statement(&mut inner, depth + 1, &mut list);
+        }
+        list.push(Expr::Nope);
+        list
+    }
+
+    // `if x {` のように数だけの条件は0でなければ真
+    fn condition(&mut self, scope: &Scope, depth: usize) -> Expr {
+        let (left, right) = match self.choices.below(4) {
+            0 => (self.string(scope, depth + 1), self.string(scope, depth + 1)),
+            1 if !scope.strs.is_empty() => (self.character(scope, depth + 1), self.character(scope, depth + 1)),
+            2 => return self.int(scope, depth + 1),
+            _ => (self.int(scope, depth + 1), self.int(scope, depth + 1)),
+        };
+        Expr::Equal(Box::new(Equal::new(left, right)))
+    }
+
+    fn printable(&mut self, scope: &Scope, depth: usize) -> Expr {
+        match self.choices.below(5) {
+            0 => self.string(scope, depth),
+            1 if !scope.strs.is_empty() => self.character(scope, depth),
+            2 if !scope.vecs.is_empty() => ident(&self.pick(&scope.vecs)),
+            _ => self.int(scope, depth),
+        }
+    }
+
+    fn int(&mut self, scope: &Scope, depth: usize) -> Expr {
+        let leaf = depth >= MAX_DEPTH;
+        match self.choices.below(if leaf { 2 } else { 9 }) {
+            0 => self.literal(),
+            1 if !scope.ints.is_empty() => ident(&self.pick(&scope.ints)),
+            1 => self.literal(),
+            2..=4 => {
+                let kind = [Kind::Plus, Kind::Minus, Kind::Multi, Kind::Divi][self.choices.below(4)];
+                let left = self.int(scope, depth + 1);
+                let right = self.int(scope, depth + 1);
+                binary(kind, left, right)
+            },
+            5 => {
+                let value = self.string(scope, depth + 1);
+                Expr::Len(Box::new(Len::new(value)))
+            },
+            6 if !scope.vecs.is_empty() => {
+                let vector = ident(&self.pick(&scope.vecs));
+                match self.choices.below(2) {
+                    0 => Expr::Len(Box::new(Len::new(vector))),
+                    _ => {
+                        let index = self.index(scope, depth + 1);
+                        Expr::Index(Box::new(Index::new(vector, index, Pos::default())))
+                    },
+                }
+            },
+            7 => {
+                let value = self.int(scope, depth + 1);
+                let text = Expr::ToStr(Box::new(ToStr::new(value)));
+                Expr::ToInt(Box::new(ToInt::new(text, Pos::default())))
+            },
+            _ => self.literal(),
+        }
+    }
+
+    // 範囲の外を指すとpanicで止まるので, 添字はたいてい小さい数にする
+    fn index(&mut self, scope: &Scope, depth: usize) -> Expr {
+        match self.choices.below(4) {
+            0 => self.int(scope, depth),
+            _ => Expr::Int(Int::new(self.choices.below(4) as i32)),
+        }
+    }
+
+    // 小さい数が多く, ときどきあふれやすい数を選ぶ
+    fn literal(&mut self) -> Expr {
+        let val = match self.choices.below(8) {
+            7 => [i32::MAX, i32::MIN, -1, 46341, 65536][self.choices.below(5)],
+            _ => self.choices.below(21) as i32,
+        };
+        Expr::Int(Int::new(val))
+    }
+
+    fn string(&mut self, scope: &Scope, depth: usize) -> Expr {
+        let leaf = depth >= MAX_DEPTH;
+        match self.choices.below(if leaf { 2 } else { 6 }) {
+            0 => Expr::Str(Str::new(self.text())),
+            1 if !scope.strs.is_empty() => ident(&self.pick(&scope.strs)),
+            1 => Expr::Str(Str::new(self.text())),
+            2 => {
+                let left = self.string(scope, depth + 1);
+                let right = self.string(scope, depth + 1);
+                binary(Kind::Plus, left, right)
+            },
+            3 => {
+                let value = self.int(scope, depth + 1);
+                Expr::ToStr(Box::new(ToStr::new(value)))
+            },
+            _ => {
+                let value = self.string(scope, depth + 1);
+                // たいていは文字列の終わりまでを取り出す
+                let (start, end) = match self.choices.below(3) {
+                    0 => (self.index(scope, depth + 1), self.index(scope, depth + 1)),
+                    _ => (Expr::Int(Int::new(self.choices.below(2) as i32)), Expr::Len(Box::new(Len::new(value.clone())))),
+                };
+                Expr::Substr(Box::new(Substr::new(value, start, end, Pos::default())))
+            },
+        }
+    }
+
+    fn character(&mut self, scope: &Scope, depth: usize) -> Expr {
+        match self.choices.below(2) {
+            0 => Expr::Char(Char::new(self.ascii())),
+            _ => {
+                let value = ident(&self.pick(&scope.strs));
+                let index = self.index(scope, depth + 1);
+                Expr::Index(Box::new(Index::new(value, index, Pos::default())))
+            },
+        }
+    }
+
+    // 文字列リテラルには引用符と改行を入れない. 添字やsubstrがUTF-8の途中を指すようにASCIIでない文字も混ぜる
+    fn text(&mut self) -> String {
+        (0..self.choices.below(5)).map(|_| match self.choices.below(4) {
+            0 => ['é', 'あ', '😺'][self.choices.below(3)],
+            _ => self.ascii(),
+        }).collect()
+    }
+
+    // charリテラルは1バイトなのでASCIIだけ
+    fn ascii(&mut self) -> char {
+        b"abnyz {}%,."[self.choices.below(11)] as char
+    }
+}
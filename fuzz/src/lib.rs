@@ -0,0 +1,2 @@
+// fuzz_targets/differential.rs で使う. バイト列から型の合ったプログラムを作る
+pub mod generate;
@@ -0,0 +1,191 @@
+use crate::compiler::emit::{Config, Emit};
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+nyaan - the nyaan language compiler
+
+USAGE:
+    nyaan <COMMAND> [OPTIONS] <FILE>...
+
+COMMANDS:
+    build    compile each FILE to an executable (or what --emit asks for)
+    run      compile FILE and run it with the JIT
+    check    parse and compile each FILE without writing anything
+    test     run the test blocks in each FILE
+    fmt      format each FILE in place
+    repl     start an interactive session
+    lsp      start a language server on stdin and stdout
+
+OPTIONS:
+    -o <FILE>           write the output to FILE (default: next to the source)
+    -O<n>               optimization level (0-3, or s for size)
+    -g                  emit DWARF debug info
+    --print-passes      print the LLVM passes that ran
+    --emit=<KIND>       obj, asm, llvm-ir, bc or exe (default: exe)
+    --target <TRIPLE>   target triple for cross compilation
+    --backend=<NAME>    llvm or interp (default: llvm)
+    --filter <TEXT>     test: run only the tests whose name contains TEXT
+    --junit <FILE>      test: also write the results to FILE as JUnit XML
+    --timeout <SECS>    test: stop and fail a test that runs longer than SECS (default: 60)
+    --test <NAME>       run/build: run the test block NAME instead of the program
+    --check             fmt: list the files that are not formatted instead of rewriting them
+    --dump-tokens       print the token list
+    --dump-ast          print the syntax tree
+    --dump-ir           print the generated LLVM IR
+    -h, --help          print this help
+";
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Command {
+    Build, Run, Check, Test, Fmt, Repl, Lsp, Help
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Backend {
+    Llvm, Interp
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub command: Command,
+    pub inputs: Vec<String>,
+    pub backend: Backend,
+    pub config: Config,
+    pub dump_tokens: bool,
+    pub dump_ast: bool,
+    pub dump_ir: bool,
+    pub print_passes: bool,
+    pub filter: Option<String>,
+    pub junit: Option<String>,
+    pub timeout: Duration,
+    pub test: Option<String>,
+    pub check: bool,
+}
+
+// 終了コード: 0 成功, 1 コンパイルエラー, 2 使い方の誤り, 101 コンパイラのバグか実行時のpanic
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+#[cfg(feature = "llvm")]
+pub const EXIT_ICE: i32 = 101;
+
+pub fn parse_args(args: &[String]) -> Options {
+    let mut options = Options {
+        command: Command::Help,
+        inputs: Vec::new(),
+        backend: if cfg!(feature = "llvm") { Backend::Llvm } else { Backend::Interp },
+        config: Config::new(),
+        dump_tokens: false,
+        dump_ast: false,
+        dump_ir: false,
+        print_passes: false,
+        filter: None,
+        junit: None,
+        timeout: Duration::from_secs(60),
+        test: None,
+        check: false,
+    };
+    let mut i = 1;
+    match args.get(1).map(|s| s.as_str()) {
+        None => usage_error("no command given".to_string()),
+        Some("build") => options.command = Command::Build,
+        Some("run") => options.command = Command::Run,
+        Some("check") => options.command = Command::Check,
+        Some("test") => options.command = Command::Test,
+        Some("fmt") => options.command = Command::Fmt,
+        Some("repl") => options.command = Command::Repl,
+        Some("lsp") => options.command = Command::Lsp,
+        Some("help") | Some("-h") | Some("--help") => return options,
+        // `nyaan file.ny` は `nyaan build file.ny` として扱う
+        Some(s) if !s.starts_with('-') && s.ends_with(".ny") => {
+            options.command = Command::Build;
+            i = 0;
+        },
+        Some(s) => usage_error(format!("unknown command `{}`", s)),
+    }
+    i += 1;
+
+    while i < args.len() {
+        let arg = &args[i];
+        if arg == "-h" || arg == "--help" {
+            options.command = Command::Help;
+            return options;
+        } else if ["-o", "--target", "--filter", "--junit", "--timeout", "--test"].contains(&arg.as_str()) {
+            i += 1;
+            let val = match args.get(i) {
+                None => usage_error(format!("{} requires an argument", arg)),
+                Some(v) => v.clone()
+            };
+            match arg.as_str() {
+                "-o" => options.config.output = Some(val),
+                "--target" => options.config.target = Some(val),
+                "--filter" => options.filter = Some(val),
+                "--junit" => options.junit = Some(val),
+                "--timeout" => options.timeout = match val.parse::<f64>().ok().filter(|s| *s > 0.0).and_then(|s| Duration::try_from_secs_f64(s).ok()) {
+                    Some(t) => t,
+                    None => usage_error(format!("invalid timeout `{}` (expected a positive number of seconds)", val)),
+                },
+                _ => options.test = Some(val),
+            }
+        } else if let Some(kind) = arg.strip_prefix("--emit=") {
+            options.config.emit = match Emit::parse(kind) {
+                None => usage_error(format!("unknown emit kind `{}` (expected obj, asm, llvm-ir, bc or exe)", kind)),
+                Some(e) => e
+            };
+        } else if let Some(name) = arg.strip_prefix("--backend=") {
+            options.backend = match name {
+                "llvm" => Backend::Llvm,
+                "interp" => Backend::Interp,
+                _ => usage_error(format!("unknown backend `{}` (expected llvm or interp)", name)),
+            };
+        } else if let Some(level) = arg.strip_prefix("-O") {
+            options.config.opt_level = match level {
+                "0" => 0,
+                "1" => 1,
+                "2" => 2,
+                "3" => 3,
+                "s" => 2,
+                s => usage_error(format!("unknown optimization level `{}`", s)),
+            };
+            options.config.size_level = if level == "s" { 1 } else { 0 };
+        } else if arg == "-g" {
+            options.config.debug = true;
+        } else if arg == "--dump-tokens" {
+            options.dump_tokens = true;
+        } else if arg == "--dump-ast" {
+            options.dump_ast = true;
+        } else if arg == "--dump-ir" {
+            options.dump_ir = true;
+        } else if arg == "--print-passes" {
+            options.print_passes = true;
+        } else if arg == "--check" {
+            options.check = true;
+        } else if arg.starts_with('-') {
+            usage_error(format!("unknown option `{}`", arg));
+        } else {
+            options.inputs.push(arg.clone());
+        }
+        i += 1;
+    }
+
+    if options.command == Command::Repl || options.command == Command::Lsp {
+        return options;
+    }
+    if options.inputs.is_empty() {
+        usage_error("no input file".to_string());
+    }
+    if options.inputs.len() > 1 {
+        if options.command == Command::Run {
+            usage_error("`run` takes a single input file".to_string());
+        }
+        if options.config.output.is_some() {
+            usage_error("`-o` cannot be used with several input files".to_string());
+        }
+    }
+    options
+}
+
+fn usage_error(message: String) -> ! {
+    eprintln!("error: {}", message);
+    eprintln!("run `nyaan --help` for usage");
+    std::process::exit(EXIT_USAGE);
+}
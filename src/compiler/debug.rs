@@ -0,0 +1,214 @@
+use super::emit::Config;
+use super::types::{kind_of_type, Registry};
+use super::{Kind, Pos};
+use inkwell::basic_block::BasicBlock;
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::debug_info::{AsDIScope, DIFile, DIFlags, DIFlagsConstants, DIScope, DIType, DWARFEmissionKind, DWARFSourceLanguage, DebugInfoBuilder};
+use inkwell::module::{FlagBehavior, Module};
+use inkwell::values::{FunctionValue, PointerValue};
+use inkwell::AddressSpace;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+
+// DWARFのエンコーディング (DW_ATE_*)
+const DW_ATE_SIGNED: u32 = 0x05;
+const DW_ATE_SIGNED_CHAR: u32 = 0x06;
+const DW_ATE_UNSIGNED_CHAR: u32 = 0x08;
+// ポインタは64ビットのターゲットを前提にする
+const POINTER_BITS: u64 = 64;
+
+// 型と, その大きさと揃え方 (ビット)
+type Layout<'ctx> = (DIType<'ctx>, u64, u32);
+
+// -gのときにgenerateが使うデバッグ情報
+pub struct DebugInfo<'ctx> {
+    context: &'ctx Context,
+    builder: DebugInfoBuilder<'ctx>,
+    file: DIFile<'ctx>,
+    scope: DIScope<'ctx>,
+    int_type: DIType<'ctx>,
+    char_type: DIType<'ctx>,
+    byte_type: DIType<'ctx>,
+    // 作った型を名前で引く
+    types: RefCell<HashMap<String, Layout<'ctx>>>,
+}
+
+impl<'ctx> DebugInfo<'ctx> {
+    pub fn new(context: &'ctx Context, module: &Module<'ctx>, function: FunctionValue<'ctx>, config: &Config) -> DebugInfo<'ctx> {
+        module.add_basic_value_flag("Debug Info Version", FlagBehavior::Warning, context.i32_type().const_int(3, false));
+        module.add_basic_value_flag("Dwarf Version", FlagBehavior::Warning, context.i32_type().const_int(4, false));
+
+        let path = Path::new(&config.input);
+        let filename = match path.file_name() {
+            Some(f) => f.to_string_lossy().to_string(),
+            None => config.input.clone(),
+        };
+        // gdbがソースを見つけられるように絶対パスにしておく
+        let directory = match path.parent().and_then(|p| p.canonicalize().ok()).or_else(|| std::env::current_dir().ok()) {
+            Some(d) => d.to_string_lossy().to_string(),
+            None => ".".to_string(),
+        };
+
+        let (builder, compile_unit) = module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            &filename,
+            &directory,
+            "nyaan",
+            config.opt_level > 0,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+        );
+        let file = compile_unit.get_file();
+        let int_type = builder.create_basic_type("int", 32, DW_ATE_SIGNED, DIFlags::PUBLIC).unwrap().as_type();
+        let char_type = builder.create_basic_type("char", 32, DW_ATE_SIGNED_CHAR, DIFlags::PUBLIC).unwrap().as_type();
+        let byte_type = builder.create_basic_type("byte", 8, DW_ATE_UNSIGNED_CHAR, DIFlags::PUBLIC).unwrap().as_type();
+        let subroutine_type = builder.create_subroutine_type(file, Some(int_type), &[], DIFlags::PUBLIC);
+        let subprogram = builder.create_function(
+            compile_unit.as_debug_info_scope(),
+            "main",
+            None,
+            file,
+            1,
+            subroutine_type,
+            false,
+            true,
+            1,
+            DIFlags::PUBLIC,
+            config.opt_level > 0,
+        );
+        function.set_subprogram(subprogram);
+
+        DebugInfo {
+            context,
+            builder,
+            file,
+            scope: subprogram.as_debug_info_scope(),
+            int_type,
+            char_type,
+            byte_type,
+            types: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // これ以降に作る命令をposの行に対応させる
+    pub fn set_location(&self, builder: &Builder<'ctx>, pos: Pos) {
+        let location = self.builder.create_debug_location(self.context, pos.line, pos.col, self.scope, None);
+        builder.set_current_debug_location(self.context, location);
+    }
+
+    // 変数の場所をデバッガに教える. tyはint_typeなどで作る
+    pub fn declare_variable(&self, name: &str, ty: DIType<'ctx>, ptr: PointerValue<'ctx>, pos: Pos, block: BasicBlock<'ctx>) {
+        let variable = self.builder.create_auto_variable(self.scope, name, self.file, pos.line, ty, true, DIFlags::ZERO, 0);
+        let location = self.builder.create_debug_location(self.context, pos.line, pos.col, self.scope, None);
+        self.builder.insert_declare_at_end(ptr, Some(variable), None, location, block);
+    }
+
+    // intとchar
+    pub fn int_type(&self, kind: Kind) -> DIType<'ctx> {
+        match kind {
+            Kind::Char => self.char_type,
+            _ => self.int_type,
+        }
+    }
+
+    // 文字列は {i8* ptr, i32 len}
+    pub fn str_type(&self) -> DIType<'ctx> {
+        self.str_layout().0
+    }
+
+    // vecは {i32* data, i32 len, i32 cap}
+    pub fn vec_type(&self) -> DIType<'ctx> {
+        self.cached("vec", || {
+            let data = self.pointer(self.int_type, "int*");
+            let int = (self.int_type, 32, 32);
+            self.composite("vec", &[member("data", data), member("len", int), member("cap", int)])
+        }).0
+    }
+
+    // [int; len]
+    pub fn array_type(&self, len: u32) -> DIType<'ctx> {
+        self.builder.create_array_type(self.int_type, 32 * len as u64, 32, std::slice::from_ref(&(0..len as i64))).as_type()
+    }
+
+    // runtime::struct_typeと同じ並べ方にする. enumはtagの後にvariantごとの値を並べたもの
+    pub fn struct_type(&self, registry: &Registry, name: &str) -> DIType<'ctx> {
+        self.layout(registry, name).0
+    }
+
+    fn str_layout(&self) -> Layout<'ctx> {
+        self.cached("str", || {
+            let ptr = self.pointer(self.byte_type, "byte*");
+            self.composite("str", &[member("ptr", ptr), member("len", (self.int_type, 32, 32))])
+        })
+    }
+
+    fn pointer(&self, pointee: DIType<'ctx>, name: &str) -> Layout<'ctx> {
+        let ty = self.builder.create_pointer_type(name, pointee, POINTER_BITS, POINTER_BITS as u32, AddressSpace::Generic).as_type();
+        (ty, POINTER_BITS, POINTER_BITS as u32)
+    }
+
+    fn layout(&self, registry: &Registry, type_name: &str) -> Layout<'ctx> {
+        match kind_of_type(type_name) {
+            Kind::Int => return (self.int_type, 32, 32),
+            Kind::Char => return (self.char_type, 32, 32),
+            Kind::Str => return self.str_layout(),
+            _ => {},
+        }
+        self.cached(type_name, || {
+            let mut members = Vec::new();
+            if let Ok(def) = registry.get(type_name) {
+                members = def.fields.iter().map(|(field, t)| member(field, self.layout(registry, t))).collect();
+            }
+            if let Ok(def) = registry.get_enum(type_name) {
+                members.push(member("tag", (self.int_type, 32, 32)));
+                for (variant, types) in def.variants.iter() {
+                    let values: Vec<(String, Layout)> = types.iter().enumerate().map(|(i, t)| member(&i.to_string(), self.layout(registry, t))).collect();
+                    members.push(member(variant, self.composite(&format!("{}.{}", type_name, variant), &values)));
+                }
+            }
+            self.composite(type_name, &members)
+        })
+    }
+
+    fn cached(&self, name: &str, make: impl FnOnce() -> Layout<'ctx>) -> Layout<'ctx> {
+        if let Some(layout) = self.types.borrow().get(name) {
+            return *layout;
+        }
+        let layout = make();
+        self.types.borrow_mut().insert(name.to_string(), layout);
+        layout
+    }
+
+    // LLVMのstructと同じく, メンバーをそれぞれの揃え方に合わせて前から詰める
+    fn composite(&self, name: &str, members: &[(String, Layout<'ctx>)]) -> Layout<'ctx> {
+        let scope = self.file.as_debug_info_scope();
+        let mut offset: u64 = 0;
+        let mut align = 8;
+        let mut elements = Vec::new();
+        for (name, (ty, size, member_align)) in members.iter() {
+            offset = offset.next_multiple_of(*member_align as u64);
+            elements.push(self.builder.create_member_type(scope, name, self.file, 0, *size, *member_align, offset, DIFlags::PUBLIC, *ty).as_type());
+            offset += size;
+            align = align.max(*member_align);
+        }
+        let size = offset.next_multiple_of(align as u64);
+        let ty = self.builder.create_struct_type(scope, name, self.file, 0, size, align, DIFlags::PUBLIC, None, &elements, 0, None, name).as_type();
+        (ty, size, align)
+    }
+
+    pub fn finalize(&self) {
+        self.builder.finalize();
+    }
+}
+
+fn member<'ctx>(name: &str, layout: Layout<'ctx>) -> (String, Layout<'ctx>) {
+    (name.to_string(), layout)
+}
@@ -0,0 +1,209 @@
+#[cfg(feature = "llvm")]
+use inkwell::module::Module;
+#[cfg(feature = "llvm")]
+use inkwell::targets::{CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple};
+#[cfg(feature = "llvm")]
+use inkwell::OptimizationLevel;
+#[cfg(feature = "llvm")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "llvm")]
+use std::process::Command;
+#[cfg(feature = "llvm")]
+use std::{fs, io};
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Emit {
+    Obj, Asm, LlvmIr, Bc, Exe
+}
+impl Emit {
+    pub fn parse(s: &str) -> Option<Emit> {
+        match s {
+            "obj" => Some(Emit::Obj),
+            "asm" => Some(Emit::Asm),
+            "llvm-ir" => Some(Emit::LlvmIr),
+            "bc" => Some(Emit::Bc),
+            "exe" => Some(Emit::Exe),
+            _ => None,
+        }
+    }
+    // 出力ファイル名が指定されなかったときの拡張子
+    #[cfg(feature = "llvm")]
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Emit::Obj => "o",
+            Emit::Asm => "s",
+            Emit::LlvmIr => "ll",
+            Emit::Bc => "bc",
+            Emit::Exe => "",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub output: Option<String>,
+    pub emit: Emit,
+    pub target: Option<String>,
+    pub opt_level: u32,
+    // -Osのとき1
+    pub size_level: u32,
+    // -gのときDWARFのデバッグ情報を付ける
+    pub debug: bool,
+    // デバッグ情報に書くソースファイル
+    pub input: String,
+}
+impl Config {
+    pub fn new() -> Config {
+        Config { output: None, emit: Emit::Exe, target: None, opt_level: 0, size_level: 0, debug: false, input: String::new() }
+    }
+    #[cfg(feature = "llvm")]
+    pub fn optimization_level(&self) -> OptimizationLevel {
+        match self.opt_level {
+            0 => OptimizationLevel::None,
+            1 => OptimizationLevel::Less,
+            2 => OptimizationLevel::Default,
+            _ => OptimizationLevel::Aggressive,
+        }
+    }
+    // 入力ファイル名から拡張子を除いたもの. 入力がなければ"main"
+    #[cfg(feature = "llvm")]
+    pub fn module_name(&self) -> String {
+        match Path::new(&self.input).file_stem() {
+            Some(stem) => stem.to_string_lossy().to_string(),
+            None => "main".to_string(),
+        }
+    }
+    // -oがなければソースと同じ場所に拡張子を変えて書く
+    #[cfg(feature = "llvm")]
+    pub fn output_path(&self) -> PathBuf {
+        match &self.output {
+            Some(o) => PathBuf::from(o),
+            None if self.input.is_empty() => PathBuf::from("main").with_extension(self.emit.extension()),
+            None => PathBuf::from(&self.input).with_extension(self.emit.extension()),
+        }
+    }
+}
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
+
+#[cfg(feature = "llvm")]
+pub fn emit(module: &Module, config: &Config) {
+    let output = config.output_path();
+    match config.emit {
+        Emit::LlvmIr => {
+            if let Err(err) = module.print_to_file(&output) {
+                emit_error(format!("could not write {}: {}", output.display(), err.to_string()));
+            }
+            return;
+        },
+        Emit::Bc => {
+            if !module.write_bitcode_to_path(&output) {
+                emit_error(format!("could not write {}", output.display()));
+            }
+            return;
+        },
+        _ => {}
+    }
+
+    let machine = target_machine(config);
+    module.set_triple(&machine.get_triple());
+    module.set_data_layout(&machine.get_target_data().get_data_layout());
+
+    match config.emit {
+        Emit::Obj => write_to_file(&machine, module, FileType::Object, &output),
+        Emit::Asm => write_to_file(&machine, module, FileType::Assembly, &output),
+        Emit::Exe => {
+            if config.target.is_some() {
+                emit_error("linking is only supported for the host target; use --emit=obj with --target".to_string());
+            }
+            let dir = temp_dir();
+            let obj = dir.join(format!("{}.o", config.module_name()));
+            let result = machine.write_to_file(module, FileType::Object, &obj)
+                .map_err(|err| format!("could not write {}: {}", obj.display(), err.to_string()))
+                .and_then(|_| link(&obj, &output));
+            let _ = fs::remove_dir_all(&dir);
+            if let Err(err) = result {
+                emit_error(err);
+            }
+        },
+        _ => {}
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn target_machine(config: &Config) -> TargetMachine {
+    let init_config = InitializationConfig::default();
+    let triple = match &config.target {
+        Some(t) => {
+            Target::initialize_all(&init_config);
+            TargetTriple::create(t)
+        },
+        None => {
+            if let Err(err) = Target::initialize_native(&init_config) {
+                emit_error(format!("could not initialize native target: {}", err));
+            }
+            TargetMachine::get_default_triple()
+        }
+    };
+    let target = match Target::from_triple(&triple) {
+        Ok(t) => t,
+        Err(err) => emit_error(format!("unknown target {}: {}", triple.as_str().to_string_lossy(), err.to_string())),
+    };
+    match target.create_target_machine(
+        &triple,
+        "generic",
+        "",
+        config.optimization_level(),
+        RelocMode::PIC,
+        CodeModel::Default,
+    ) {
+        Some(m) => m,
+        None => emit_error(format!("could not create target machine for {}", triple.as_str().to_string_lossy())),
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn write_to_file(machine: &TargetMachine, module: &Module, file_type: FileType, path: &Path) {
+    if let Err(err) = machine.write_to_file(module, file_type, path) {
+        emit_error(format!("could not write {}: {}", path.display(), err.to_string()));
+    }
+}
+
+// リンクする前のオブジェクトファイルを置くディレクトリ. 決まった名前のファイルを
+// 他のユーザーが先に作ったりsymlinkにしたりできないように, 新しく作った自分だけのディレクトリにする
+#[cfg(feature = "llvm")]
+fn temp_dir() -> PathBuf {
+    let base = std::env::temp_dir();
+    for i in 0..100 {
+        let dir = base.join(format!("nyaan-{}-{}", std::process::id(), i));
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        match builder.create(&dir) {
+            Ok(()) => return dir,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => emit_error(format!("could not create a temporary directory in {}: {}", base.display(), err)),
+        }
+    }
+    emit_error(format!("could not create a temporary directory in {}", base.display()))
+}
+
+// システムのリンカ(cc)を呼び出して実行ファイルを作る
+#[cfg(feature = "llvm")]
+fn link(obj: &Path, output: &Path) -> Result<(), String> {
+    let cc = std::env::var("CC").unwrap_or("cc".to_string());
+    match Command::new(&cc).arg(obj).arg("-o").arg(output).status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("linker `{}` failed with {}", cc, status)),
+        Err(err) => Err(format!("could not run linker `{}`: {}", cc, err)),
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn emit_error(message: String) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1);
+}
@@ -0,0 +1,133 @@
+use super::*;
+
+// 定数だけでできた式をコンパイル時に計算しておく
+pub fn fold(ast: &[Expr]) -> Result<Vec<Expr>, String> {
+    let mut list = Vec::new();
+    for expr in ast.iter() {
+        list.push(fold_statement(expr)?);
+    }
+    Ok(list)
+}
+
+fn fold_statement(expr: &Expr) -> Result<Expr, String> {
+    let folded = match expr {
+        Expr::Assign(e) => Expr::Assign(Box::new(Assign::new(fold_expr(&e.left_expr)?, fold_expr(&e.right_expr)?, e.pos))),
+        Expr::Print(e) => {
+            let mut list = Vec::new();
+            for item in e.list.iter() {
+                list.push(fold_expr(item)?);
+            }
+            Expr::Print(Box::new(Print::new(list, e.newline, e.pos)))
+        },
+        Expr::If(e) => Expr::If(Box::new(If::new(fold_expr(&e.condition)?, fold(&e.list)?, e.pos))),
+        Expr::ElseIf(e) => Expr::ElseIf(Box::new(ElseIf::new(fold_expr(&e.condition)?, fold(&e.list)?, e.pos))),
+        Expr::Else(e) => Expr::Else(Box::new(Else::new(fold(&e.list)?, e.pos))),
+        Expr::Test(e) => Expr::Test(Box::new(Test::new(e.name.clone(), fold(&e.list)?, e.pos))),
+        // == の左右の値を出せるようにEqualは残す
+        Expr::Assert(e) => {
+            let condition = match &e.condition {
+                Expr::Equal(eq) => Expr::Equal(Box::new(Equal::new(fold_expr(&eq.left_expr)?, fold_expr(&eq.right_expr)?))),
+                condition => fold_expr(condition)?,
+            };
+            Expr::Assert(Box::new(Assert::new(condition, e.text.clone(), e.pos)))
+        },
+        Expr::Panic(e) => Expr::Panic(Box::new(Panic::new(fold_expr(&e.message)?, e.pos))),
+        Expr::Return(e) => Expr::Return(Box::new(Return::new(fold_expr(&e.val)?, e.exit, e.pos))),
+        Expr::Push(e) => Expr::Push(Box::new(Push::new(e.vector.clone(), fold_expr(&e.val)?, e.pos))),
+        Expr::Match(e) => {
+            let mut arms = Vec::new();
+            for arm in e.arms.iter() {
+                arms.push(Arm::new(arm.pattern.clone(), arm.bindings.clone(), fold(&arm.list)?, arm.pos));
+            }
+            Expr::Match(Box::new(Match::new(fold_expr(&e.value)?, arms, e.pos)))
+        },
+        _ => fold_expr(expr)?,
+    };
+    Ok(folded)
+}
+
+pub fn fold_expr(expr: &Expr) -> Result<Expr, String> {
+    match expr {
+        Expr::BinaryOp(e) => {
+            let left = fold_expr(&e.left_expr)?;
+            let right = fold_expr(&e.right_expr)?;
+            match (const_int(&left), const_int(&right)) {
+                (Some(l), Some(r)) => Ok(Expr::Int(Int::new(const_binary_op(e.kind, l, r, e.pos)?))),
+                _ => Ok(Expr::BinaryOp(Box::new(BinaryOp::new(e.kind, left, right, e.pos)))),
+            }
+        },
+        Expr::Equal(e) => {
+            let left = fold_expr(&e.left_expr)?;
+            let right = fold_expr(&e.right_expr)?;
+            match (&left, &right) {
+                (Expr::Bool(l), Expr::Bool(r)) => Ok(Expr::Bool(Bool::new(l.eval() == r.eval()))),
+                _ => match (const_int(&left), const_int(&right)) {
+                    (Some(l), Some(r)) => Ok(Expr::Bool(Bool::new(l == r))),
+                    _ => Ok(Expr::Equal(Box::new(Equal::new(left, right)))),
+                }
+            }
+        },
+        Expr::Array(e) => {
+            let mut list = Vec::new();
+            for item in e.list.iter() {
+                list.push(fold_expr(item)?);
+            }
+            Ok(Expr::Array(Box::new(Array { list, len: e.len })))
+        },
+        Expr::Index(e) => Ok(Expr::Index(Box::new(Index::new(e.array.clone(), fold_expr(&e.index)?, e.pos)))),
+        Expr::Len(e) => Ok(Expr::Len(Box::new(Len::new(fold_expr(&e.array)?)))),
+        Expr::Substr(e) => Ok(Expr::Substr(Box::new(Substr::new(fold_expr(&e.string)?, fold_expr(&e.start)?, fold_expr(&e.end)?, e.pos)))),
+        Expr::ToStr(e) => Ok(Expr::ToStr(Box::new(ToStr::new(fold_expr(&e.val)?)))),
+        Expr::ToInt(e) => Ok(Expr::ToInt(Box::new(ToInt::new(fold_expr(&e.string)?, e.pos)))),
+        Expr::Vector(e) => {
+            let mut list = Vec::new();
+            for item in e.list.iter() {
+                list.push(fold_expr(item)?);
+            }
+            Ok(Expr::Vector(Box::new(Vector::new(list))))
+        },
+        Expr::EnumLit(e) => {
+            let mut args = Vec::new();
+            for arg in e.args.iter() {
+                args.push(fold_expr(arg)?);
+            }
+            Ok(Expr::EnumLit(Box::new(EnumLit::new(e.name.clone(), e.variant.clone(), args, e.pos))))
+        },
+        Expr::StructLit(e) => {
+            let mut fields = Vec::new();
+            for (name, val) in e.fields.iter() {
+                fields.push((name.clone(), fold_expr(val)?));
+            }
+            Ok(Expr::StructLit(Box::new(StructLit::new(e.name.clone(), fields, e.pos))))
+        },
+        _ => Ok(expr.clone()),
+    }
+}
+
+// charは整数として計算に使える
+fn const_int(expr: &Expr) -> Option<i32> {
+    match expr {
+        Expr::Int(i) => Some(i.eval()),
+        Expr::Char(c) => Some(c.eval() as i32),
+        _ => None,
+    }
+}
+
+fn const_binary_op(kind: Kind, left: i32, right: i32, pos: Pos) -> Result<i32, String> {
+    let (result, op) = match kind {
+        Kind::Plus => (left.checked_add(right), "+"),
+        Kind::Minus => (left.checked_sub(right), "-"),
+        Kind::Multi => (left.checked_mul(right), "*"),
+        Kind::Divi => {
+            if right == 0 {
+                return Err(error_at(format!("division by zero in constant expression {} / {}", left, right), pos));
+            }
+            (left.checked_div(right), "/")
+        },
+        _ => return Err(error_at(format!("unsupported operator {:?}", kind), pos)),
+    };
+    match result {
+        None => Err(error_at(format!("overflow in constant expression {} {} {}", left, op, right), pos)),
+        Some(v) => Ok(v),
+    }
+}
@@ -0,0 +1,451 @@
+use super::*;
+use std::fmt;
+
+// nyaan fmt: 構文木をそろえた書き方のソースに戻す.
+// コメントと空行は構文木に残らないので, tokenizerのtriviaを文の位置と突き合わせて書き戻す.
+// 式の途中に書いたコメントはその文の後ろに移る
+
+const INDENT: &str = "    ";
+
+// ソースを整形する. 整形したものが読めないか, もう一度整形して変わるならnyaanのバグ
+pub fn format_source(text: &str) -> Result<String, String> {
+    let (tokens, trivia) = tokenizer::tokenize_with_trivia(&mut text.chars())?;
+    let ast = parser::token_to_expr(&tokens)?;
+    let formatted = format(&ast, &tokens, &trivia);
+
+    let (tokens, trivia) = tokenizer::tokenize_with_trivia(&mut formatted.chars())
+        .map_err(|err| format!("internal error: formatted source cannot be read: {}", err))?;
+    let ast = parser::token_to_expr(&tokens)
+        .map_err(|err| format!("internal error: formatted source cannot be parsed: {}", err))?;
+    if format(&ast, &tokens, &trivia) != formatted {
+        return Err("internal error: formatting is not stable".to_string());
+    }
+    Ok(formatted)
+}
+
+// tokensとtriviaは構文木を作ったときのもの. 空なら構文木だけから書く
+pub fn format(ast: &[Expr], tokens: &[Token], trivia: &[Trivia]) -> String {
+    let mut printer = Printer { out: String::new(), tokens, trivia, next: 0, indent: 0 };
+    let end = Pos::new(u32::MAX, u32::MAX);
+    printer.block(ast, end);
+    printer.close(end);
+    printer.out
+}
+
+// 文はブロックも含めて書き, 式は1行で書く
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match start(self) {
+            Some(_) => f.write_str(format(std::slice::from_ref(self), &[], &[]).trim_end()),
+            None => f.write_str(&expression(self)),
+        }
+    }
+}
+
+struct Printer<'a> {
+    out: String,
+    tokens: &'a [Token],
+    trivia: &'a [Trivia],
+    // 次に書くtrivia
+    next: usize,
+    indent: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // else ifとelseは前の `}` に続けて書く
+    fn chain(&mut self, text: &str) {
+        if self.out.ends_with("}\n\n") {
+            self.out.pop();
+        }
+        if self.out.ends_with("}\n") {
+            self.out.pop();
+            self.out.push(' ');
+            self.out.push_str(text);
+            self.out.push('\n');
+        } else {
+            self.line(text);
+        }
+    }
+
+    // posより前のコメントと空行を書く. 行末のコメントは直前の行の後ろに付ける
+    fn flush(&mut self, pos: Pos) {
+        while let Some(trivia) = self.trivia.get(self.next) {
+            if trivia.pos() >= pos {
+                break;
+            }
+            self.next += 1;
+            match trivia {
+                Trivia::Comment { text, trailing: true, .. } if self.out.ends_with('\n') && !self.out.ends_with("\n\n") => {
+                    self.out.pop();
+                    self.out.push_str(&format!(" //{}\n", text));
+                },
+                Trivia::Comment { text, .. } => self.line(&format!("//{}", text)),
+                // ファイルやブロックの始めの空行は書かない
+                Trivia::Blank(_) => {
+                    if !self.out.is_empty() && !self.out.ends_with("{\n") && !self.out.ends_with("\n\n") {
+                        self.out.push('\n');
+                    }
+                },
+            }
+        }
+    }
+
+    // ブロックの `}` の前まで書き, 最後の空行は消す
+    fn close(&mut self, pos: Pos) {
+        self.flush(pos);
+        if self.out.ends_with("\n\n") {
+            self.out.pop();
+        }
+    }
+
+    // posから始まるトークンとその後ろ
+    fn find(&self, pos: Pos) -> &[Token] {
+        match self.tokens.binary_search_by(|t| t.pos.cmp(&pos)) {
+            Ok(i) => &self.tokens[i..],
+            Err(_) => &[],
+        }
+    }
+
+    // endより前の最後の `}`. endはブロックの後ろにある文の位置
+    fn brace(&self, end: Pos) -> Pos {
+        let i = self.tokens.partition_point(|t| t.pos < end);
+        self.tokens[..i].iter().rev().find(|t| t.kind == Kind::Rbrace).map_or(end, |t| t.pos)
+    }
+
+    fn block(&mut self, list: &[Expr], end: Pos) {
+        for (i, expr) in list.iter().enumerate() {
+            // 次の文の始め. 最後の文ならブロックの終わり
+            let next = list[i + 1..].iter().find_map(start).unwrap_or(end);
+            self.statement(expr, next);
+        }
+    }
+
+    // `{` の後の文と `}`
+    fn body(&mut self, list: &[Expr], end: Pos) {
+        let close = self.brace(end);
+        self.indent += 1;
+        self.block(list, close);
+        self.close(close);
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn statement(&mut self, expr: &Expr, next: Pos) {
+        let pos = match start(expr) {
+            None => return,
+            Some(p) => p,
+        };
+        self.flush(pos);
+        match expr {
+            Expr::If(e) => {
+                self.line(&format!("if {} {{", e.condition));
+                self.body(&e.list, next);
+            },
+            Expr::ElseIf(e) => {
+                self.chain(&format!("else if {} {{", e.condition));
+                self.body(&e.list, next);
+            },
+            Expr::Else(e) => {
+                self.chain("else {");
+                self.body(&e.list, next);
+            },
+            Expr::Test(e) => {
+                self.line(&format!("test \"{}\" {{", e.name));
+                self.body(&e.list, next);
+            },
+            Expr::Match(e) => {
+                self.line(&format!("match {} {{", e.value));
+                let close = self.brace(next);
+                self.indent += 1;
+                for (i, arm) in e.arms.iter().enumerate() {
+                    self.flush(arm.pos);
+                    let pattern = match &arm.pattern {
+                        None => "_".to_string(),
+                        Some((name, variant)) if arm.bindings.is_empty() => format!("{}.{}", name, variant),
+                        Some((name, variant)) => format!("{}.{}({})", name, variant, arm.bindings.join(", ")),
+                    };
+                    self.line(&format!("{} => {{", pattern));
+                    self.body(&arm.list, e.arms.get(i + 1).map_or(close, |a| a.pos));
+                }
+                self.close(close);
+                self.indent -= 1;
+                self.line("}");
+            },
+            Expr::Struct(e) => {
+                let fields: Vec<String> = e.fields.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect();
+                self.members(&format!("struct {}", e.name), &fields, pos, next);
+            },
+            Expr::Enum(e) => {
+                let variants: Vec<String> = e.variants.iter().map(|(name, types)| match types.is_empty() {
+                    true => name.clone(),
+                    false => format!("{}({})", name, types.join(", ")),
+                }).collect();
+                self.members(&format!("enum {}", e.name), &variants, pos, next);
+            },
+            _ => {
+                let text = self.simple(expr);
+                self.line(&text);
+            },
+        }
+    }
+
+    // structとenumの定義. 中にコメントがなければ1行にする
+    fn members(&mut self, head: &str, members: &[String], pos: Pos, end: Pos) {
+        let close = self.brace(end);
+        let commented = self.trivia[self.next..].iter()
+            .take_while(|t| t.pos() < close)
+            .any(|t| matches!(t, Trivia::Comment { .. }));
+        if !commented {
+            // 中の空行は捨てる
+            while self.trivia.get(self.next).is_some_and(|t| t.pos() < close) {
+                self.next += 1;
+            }
+            match members.is_empty() {
+                true => self.line(&format!("{} {{}}", head)),
+                false => self.line(&format!("{} {{ {} }}", head, members.join(", "))),
+            }
+            return;
+        }
+        let starts = self.member_starts(pos);
+        self.line(&format!("{} {{", head));
+        self.indent += 1;
+        for (i, member) in members.iter().enumerate() {
+            if let Some(p) = starts.get(i) {
+                self.flush(*p);
+            }
+            self.line(&format!("{},", member));
+        }
+        self.close(close);
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    // `{` の直後と `,` の後ろの名前がフィールドやvariantの始まり
+    fn member_starts(&self, pos: Pos) -> Vec<Pos> {
+        let mut list = Vec::new();
+        let i = self.tokens.partition_point(|t| t.pos < pos);
+        let mut depth = 0;
+        let mut expect = false;
+        for token in self.tokens[i..].iter() {
+            match token.kind {
+                Kind::Lbrace => expect = true,
+                Kind::Rbrace | Kind::Endlist => break,
+                Kind::Lparen => depth += 1,
+                Kind::Rparen => depth -= 1,
+                Kind::Comma if depth == 0 => expect = true,
+                Kind::Ident if expect => {
+                    list.push(token.pos);
+                    expect = false;
+                },
+                _ => {},
+            }
+        }
+        list
+    }
+
+    // ブロックを持たない1行の文
+    fn simple(&self, expr: &Expr) -> String {
+        let keyword = |pos: Pos| self.find(pos).first().map(|t| (t.kind, t.text.as_str()));
+        match expr {
+            Expr::Assign(e) => match &e.right_expr {
+                // let a: [int; 10]; は0で埋めた配列になっている
+                Expr::Array(a) if keyword(e.pos).map(|k| k.0) == Some(Kind::Let) => {
+                    format!("let {}: [int; {}];", e.left_expr, a.len)
+                },
+                _ => format!("{} = {};", e.left_expr, e.right_expr),
+            },
+            Expr::Print(e) => self.print(e),
+            Expr::Import(e) => format!("import \"{}\";", e.path),
+            Expr::Push(e) => format!("push({}, {});", e.vector, e.val),
+            Expr::Return(e) if e.exit => format!("exit({});", e.val),
+            Expr::Return(e) => format!("return {};", e.val),
+            Expr::Panic(e) => format!("panic({});", e.message),
+            Expr::Assert(e) => match &e.condition {
+                Expr::Equal(eq) if keyword(e.pos).map(|k| k.1) == Some("assert_eq") => {
+                    format!("assert_eq({}, {});", eq.left_expr, eq.right_expr)
+                },
+                condition => format!("assert({});", condition),
+            },
+            _ => format!("{};", expression(expr)),
+        }
+    }
+
+    // 書式の文字列リテラルは書いたとおりに残す. 構文木にはない書き方なのでトークンから読む
+    fn print(&self, e: &Print) -> String {
+        let name = if e.newline { "println" } else { "print" };
+        // print ( の後ろ
+        let tokens = self.find(e.pos);
+        let kind = |i: usize| tokens.get(i).map(|t| t.kind);
+        let args = match tokens.get(2) {
+            Some(t) if t.kind == Kind::Str && matches!(kind(3), Some(Kind::Comma) | Some(Kind::Rparen)) => {
+                written_format(&t.text, &e.list)
+            },
+            Some(t) if t.kind == Kind::Rparen => Some(Vec::new()),
+            // `println(a, b)` は値の間に " " が入っている
+            Some(_) if e.list.iter().skip(1).step_by(2).all(|s| *s == Expr::Str(Str::new(" ".to_string()))) => {
+                Some(e.list.iter().step_by(2).map(expression).collect())
+            },
+            _ => None,
+        };
+        let args = args.unwrap_or_else(|| built_format(&e.list));
+        format!("{}({});", name, args.join(", "))
+    }
+}
+
+// 元の書式に合わせて, `{}` に入る値だけを引数に並べる
+fn written_format(format: &str, list: &[Expr]) -> Option<Vec<String>> {
+    let mut items = list.iter();
+    let mut args = vec![format!("\"{}\"", format)];
+    let mut text = false;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); text = true; },
+            '}' if chars.peek() == Some(&'}') => { chars.next(); text = true; },
+            '{' => {
+                let mut name = String::new();
+                for ch in chars.by_ref() {
+                    if ch == '}' {
+                        break;
+                    }
+                    name.push(ch);
+                }
+                // 前の文字は1つの文字列にまとまっている
+                if text {
+                    items.next()?;
+                }
+                let value = items.next()?;
+                if name.is_empty() {
+                    args.push(expression(value));
+                }
+                text = false;
+            },
+            _ => text = true,
+        }
+    }
+    if text {
+        items.next()?;
+    }
+    match items.next() {
+        None => Some(args),
+        Some(_) => None,
+    }
+}
+
+// トークンがないときは文字列を書式に入れ, それ以外を `{}` にする
+fn built_format(list: &[Expr]) -> Vec<String> {
+    if list.is_empty() {
+        return Vec::new();
+    }
+    let mut format = String::new();
+    let mut args = Vec::new();
+    for item in list.iter() {
+        match item {
+            Expr::Str(s) => format.push_str(&s.eval().replace('{', "{{").replace('}', "}}")),
+            _ => {
+                format.push_str("{}");
+                args.push(expression(item));
+            },
+        }
+    }
+    args.insert(0, format!("\"{}\"", format));
+    args
+}
+
+// 文の始まりの位置. 式ならNone
+fn start(expr: &Expr) -> Option<Pos> {
+    match expr {
+        Expr::Assign(e) => Some(e.pos),
+        Expr::Print(e) => Some(e.pos),
+        Expr::If(e) => Some(e.pos),
+        Expr::ElseIf(e) => Some(e.pos),
+        Expr::Else(e) => Some(e.pos),
+        Expr::Import(e) => Some(e.pos),
+        Expr::Push(e) => Some(e.pos),
+        Expr::Return(e) => Some(e.pos),
+        Expr::Panic(e) => Some(e.pos),
+        Expr::Assert(e) => Some(e.pos),
+        Expr::Test(e) => Some(e.pos),
+        Expr::Struct(e) => Some(e.pos),
+        Expr::Enum(e) => Some(e.pos),
+        Expr::Match(e) => Some(e.pos),
+        _ => None,
+    }
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Equal(_) | Expr::Bool(_) => 1,
+        Expr::BinaryOp(e) if e.kind == Kind::Plus || e.kind == Kind::Minus => 2,
+        Expr::Int(i) if i.eval() < 0 => 2,
+        Expr::BinaryOp(_) => 3,
+        _ => 4,
+    }
+}
+
+// 左結合なので, 右側は同じ強さでも括弧を付ける
+fn operand(expr: &Expr, parent: u8, right: bool) -> String {
+    let p = precedence(expr);
+    if p < parent || (right && p == parent) {
+        format!("({})", expression(expr))
+    } else {
+        expression(expr)
+    }
+}
+
+fn list(items: &[Expr]) -> String {
+    items.iter().map(expression).collect::<Vec<String>>().join(", ")
+}
+
+fn expression(expr: &Expr) -> String {
+    match expr {
+        // 負の数のリテラルはないので引き算にする (定数畳み込みの後にだけ現れる)
+        Expr::Int(i) if i.eval() == i32::MIN => format!("0 - {} - 1", i32::MAX),
+        Expr::Int(i) if i.eval() < 0 => format!("0 - {}", -i.eval()),
+        Expr::Int(i) => i.eval().to_string(),
+        Expr::Str(s) => format!("\"{}\"", s.eval()),
+        Expr::Char(c) => format!("'{}'", c.eval()),
+        Expr::Bool(b) => if b.eval() { "1 == 1" } else { "1 == 0" }.to_string(),
+        Expr::Ident(_) => expr.name(),
+        Expr::BinaryOp(e) => {
+            let op = match e.kind {
+                Kind::Plus => "+",
+                Kind::Minus => "-",
+                Kind::Multi => "*",
+                _ => "/",
+            };
+            let p = precedence(expr);
+            format!("{} {} {}", operand(&e.left_expr, p, false), op, operand(&e.right_expr, p, true))
+        },
+        Expr::Equal(e) => format!("{} == {}", operand(&e.left_expr, 2, false), operand(&e.right_expr, 2, false)),
+        Expr::Index(e) => format!("{}[{}]", expression(&e.array), expression(&e.index)),
+        Expr::Len(e) => format!("len({})", expression(&e.array)),
+        Expr::Array(e) => format!("[{}]", list(&e.list)),
+        Expr::Vector(e) => format!("vec({})", list(&e.list)),
+        Expr::Pop(e) => format!("pop({})", expression(&e.vector)),
+        Expr::Substr(e) => format!("substr({}, {}, {})", expression(&e.string), expression(&e.start), expression(&e.end)),
+        Expr::ToStr(e) => format!("str({})", expression(&e.val)),
+        Expr::ToInt(e) => format!("int({})", expression(&e.string)),
+        Expr::Input(e) => match e.kind {
+            Kind::Int => "read_int()",
+            Kind::Char => "read_char()",
+            Kind::EofTkn => "eof()",
+            _ => "input()",
+        }.to_string(),
+        Expr::StructLit(e) => {
+            let fields: Vec<String> = e.fields.iter().map(|(name, val)| format!("{}: {}", name, expression(val))).collect();
+            format!("{} {{ {} }}", e.name, fields.join(", "))
+        },
+        Expr::Field(e) => format!("{}.{}", expression(&e.object), e.name),
+        Expr::EnumLit(e) => format!("{}.{}({})", e.name, e.variant, list(&e.args)),
+        _ => String::new(),
+    }
+}
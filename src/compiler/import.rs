@@ -0,0 +1,233 @@
+use super::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// importされたファイルを読み込んで1つのプログラムにまとめる.
+// importしたファイルのトップレベルの変数は `ファイル名.変数名` で参照する.
+// structとenumの名前は修飾せずにすべてのファイルで共有するので, 別のファイルで同じ名前を定義するとエラーになる
+pub fn resolve(input: &str, ast: &[Expr]) -> Result<Vec<Expr>, String> {
+    let mut loader = Loader { stack: Vec::new(), loaded: HashSet::new(), namespaces: HashMap::new(), enums: HashSet::new(), types: HashMap::new() };
+    if let Ok(path) = Path::new(input).canonicalize() {
+        loader.stack.push(path.clone());
+        loader.loaded.insert(path);
+    }
+    loader.expand(Path::new(input), ast)
+}
+
+struct Loader {
+    // 読み込み中のファイル. 循環の検出に使う
+    stack: Vec<PathBuf>,
+    // 一度読んだファイルは二度目のimportでは何もしない
+    loaded: HashSet<PathBuf>,
+    namespaces: HashMap<String, PathBuf>,
+    // enumの名前はファイルをまたいで共通なので `Shape.Empty` は修飾しない
+    enums: HashSet<String>,
+    // structとenumを定義したファイル
+    types: HashMap<String, PathBuf>,
+}
+
+impl Loader {
+    fn expand(&mut self, file: &Path, ast: &[Expr]) -> Result<Vec<Expr>, String> {
+        let mut list = Vec::new();
+        for expr in ast.iter() {
+            match expr {
+                // importしたファイルのトップレベルの文はimportした場所で実行する
+                Expr::Import(e) => list.extend(self.load(file, e)?),
+                _ => {
+                    if let Expr::Enum(e) = expr {
+                        self.enums.insert(e.name.clone());
+                    }
+                    let defined = match expr {
+                        Expr::Struct(e) => Some(("struct", &e.name, e.pos)),
+                        Expr::Enum(e) => Some(("enum", &e.name, e.pos)),
+                        _ => None,
+                    };
+                    if let Some((kind, name, pos)) = defined {
+                        // 同じファイルの中での二重定義はtypes::checkが見つける
+                        match self.types.get(name) {
+                            Some(other) if other != file => {
+                                return Err(format!("{} {} in {} (line {}) is already defined in {}; struct and enum names are shared by all imported files", kind, name, file.display(), pos.line, other.display()));
+                            },
+                            _ => { self.types.insert(name.clone(), file.to_path_buf()); },
+                        }
+                    }
+                    let expr = self.qualify("", expr);
+                    check_statement(&expr)?;
+                    list.push(expr);
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    fn load(&mut self, from: &Path, import: &Import) -> Result<Vec<Expr>, String> {
+        let dir = from.parent().unwrap_or_else(|| Path::new(""));
+        let path = dir.join(&import.path);
+        let canonical = match path.canonicalize() {
+            Ok(p) => p,
+            Err(err) => return Err(format!("cannot import {} (line {}): {}", path.display(), import.pos.line, err)),
+        };
+        if let Some(i) = self.stack.iter().position(|p| *p == canonical) {
+            let mut cycle: Vec<String> = self.stack[i..].iter().map(|p| file_name(p)).collect();
+            cycle.push(file_name(&canonical));
+            return Err(format!("import cycle: {}", cycle.join(" -> ")));
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(Vec::new());
+        }
+
+        let namespace = match path.file_stem() {
+            Some(s) => s.to_string_lossy().to_string(),
+            None => String::new(),
+        };
+        if !is_ident(&namespace) {
+            return Err(format!("cannot import {}: the file name must be usable as a variable prefix", path.display()));
+        }
+        if let Some(other) = self.namespaces.insert(namespace.clone(), canonical.clone()) {
+            return Err(format!("cannot import {}: {} is already imported as `{}`", path.display(), other.display(), namespace));
+        }
+
+        let text = match fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(err) => return Err(format!("{}: {}", path.display(), err)),
+        };
+        let token_list = match tokenizer::tokenize(&mut text.chars()) {
+            Ok(t) => t,
+            Err(err) => return Err(format!("{}: {}", path.display(), err)),
+        };
+        let ast = match parser::token_to_expr(&token_list) {
+            Ok(a) => a,
+            Err(err) => return Err(format!("{}: {}", path.display(), err)),
+        };
+        // importしたファイルがプログラムを終えてしまわないようにする
+        if let Some(pos) = parser::find_return(&ast) {
+            return Err(format!("{}: return and exit can only be used in the main file (line {})", path.display(), pos.line));
+        }
+
+        // 実行するのはnyaan testに渡したファイルのtestだけ
+        let ast: Vec<Expr> = ast.into_iter().filter(|e| !matches!(e, Expr::Test(_))).collect();
+
+        self.stack.push(canonical);
+        let ast = self.expand(&path, &ast);
+        self.stack.pop();
+        Ok(ast?.iter().map(|e| self.qualify(&namespace, e)).collect())
+    }
+
+    // まだ修飾されていない変数名にnamespaceを付ける. namespaceが空なら付けない.
+    // パーサは `math.x` をフィールドとして読むので, importしたファイルの名前ならIdentに戻す
+    fn qualify(&self, namespace: &str, expr: &Expr) -> Expr {
+        let list = |list: &Vec<Expr>| -> Vec<Expr> { list.iter().map(|e| self.qualify(namespace, e)).collect() };
+        match expr {
+            Expr::Ident(e) => {
+                if e.name.contains('.') || namespace.is_empty() {
+                    expr.clone()
+                } else {
+                    Expr::Ident(Box::new(Ident::new(format!("{}.{}", namespace, e.name), e.kind)))
+                }
+            },
+            Expr::Field(e) => match &e.object {
+                Expr::Ident(object) if self.namespaces.contains_key(&object.name) => {
+                    Expr::Ident(Box::new(Ident::new(format!("{}.{}", object.name, e.name), object.kind)))
+                },
+                Expr::Ident(object) if self.enums.contains(&object.name) => expr.clone(),
+                object => Expr::Field(Box::new(Field::new(self.qualify(namespace, object), e.name.clone(), e.pos))),
+            },
+            Expr::EnumLit(e) => Expr::EnumLit(Box::new(EnumLit::new(e.name.clone(), e.variant.clone(), list(&e.args), e.pos))),
+            Expr::Match(e) => {
+                let arms = e.arms.iter().map(|arm| {
+                    let bindings = arm.bindings.iter().map(|b| if namespace.is_empty() { b.clone() } else { format!("{}.{}", namespace, b) }).collect();
+                    Arm::new(arm.pattern.clone(), bindings, list(&arm.list), arm.pos)
+                }).collect();
+                Expr::Match(Box::new(Match::new(self.qualify(namespace, &e.value), arms, e.pos)))
+            },
+            Expr::StructLit(e) => {
+                let fields = e.fields.iter().map(|(n, v)| (n.clone(), self.qualify(namespace, v))).collect();
+                Expr::StructLit(Box::new(StructLit::new(e.name.clone(), fields, e.pos)))
+            },
+            Expr::BinaryOp(e) => Expr::BinaryOp(Box::new(BinaryOp::new(e.kind, self.qualify(namespace, &e.left_expr), self.qualify(namespace, &e.right_expr), e.pos))),
+            Expr::Equal(e) => Expr::Equal(Box::new(Equal::new(self.qualify(namespace, &e.left_expr), self.qualify(namespace, &e.right_expr)))),
+            Expr::Assign(e) => Expr::Assign(Box::new(Assign::new(self.qualify(namespace, &e.left_expr), self.qualify(namespace, &e.right_expr), e.pos))),
+            Expr::Test(e) => Expr::Test(Box::new(Test::new(e.name.clone(), list(&e.list), e.pos))),
+            Expr::Assert(e) => Expr::Assert(Box::new(Assert::new(self.qualify(namespace, &e.condition), e.text.clone(), e.pos))),
+            Expr::Panic(e) => Expr::Panic(Box::new(Panic::new(self.qualify(namespace, &e.message), e.pos))),
+            Expr::Return(e) => Expr::Return(Box::new(Return::new(self.qualify(namespace, &e.val), e.exit, e.pos))),
+            Expr::Print(e) => Expr::Print(Box::new(Print::new(e.list.iter().map(|x| self.qualify(namespace, x)).collect(), e.newline, e.pos))),
+            Expr::If(e) => Expr::If(Box::new(If::new(self.qualify(namespace, &e.condition), list(&e.list), e.pos))),
+            Expr::ElseIf(e) => Expr::ElseIf(Box::new(ElseIf::new(self.qualify(namespace, &e.condition), list(&e.list), e.pos))),
+            Expr::Else(e) => Expr::Else(Box::new(Else::new(list(&e.list), e.pos))),
+            Expr::Array(e) => Expr::Array(Box::new(Array { list: list(&e.list), len: e.len })),
+            Expr::Index(e) => Expr::Index(Box::new(Index::new(self.qualify(namespace, &e.array), self.qualify(namespace, &e.index), e.pos))),
+            Expr::Len(e) => Expr::Len(Box::new(Len::new(self.qualify(namespace, &e.array)))),
+            Expr::Vector(e) => Expr::Vector(Box::new(Vector::new(list(&e.list)))),
+            Expr::Push(e) => Expr::Push(Box::new(Push::new(self.qualify(namespace, &e.vector), self.qualify(namespace, &e.val), e.pos))),
+            Expr::Pop(e) => Expr::Pop(Box::new(Pop::new(self.qualify(namespace, &e.vector), e.pos))),
+            Expr::Substr(e) => Expr::Substr(Box::new(Substr::new(self.qualify(namespace, &e.string), self.qualify(namespace, &e.start), self.qualify(namespace, &e.end), e.pos))),
+            Expr::ToStr(e) => Expr::ToStr(Box::new(ToStr::new(self.qualify(namespace, &e.val)))),
+            Expr::ToInt(e) => Expr::ToInt(Box::new(ToInt::new(self.qualify(namespace, &e.string), e.pos))),
+            _ => expr.clone(),
+        }
+    }
+}
+
+// importはトップレベルにだけ書ける. 他のファイルの変数には代入できない
+fn check_statement(expr: &Expr) -> Result<(), String> {
+    let list = match expr {
+        Expr::Import(e) => return Err(format!("import must be at the top level (line {})", e.pos.line)),
+        Expr::Assign(e) => {
+            let name = root_name(&e.left_expr);
+            if name.contains('.') {
+                return Err(format!("cannot assign to `{}` (line {}): it belongs to an imported file", name, e.pos.line));
+            }
+            return Ok(());
+        },
+        Expr::Push(e) => {
+            let name = root_name(&e.vector);
+            if name.contains('.') {
+                return Err(format!("cannot push to `{}` (line {}): it belongs to an imported file", name, e.pos.line));
+            }
+            return Ok(());
+        },
+        Expr::If(e) => &e.list,
+        Expr::ElseIf(e) => &e.list,
+        Expr::Else(e) => &e.list,
+        Expr::Test(e) => &e.list,
+        Expr::Match(e) => {
+            for arm in e.arms.iter() {
+                for s in arm.list.iter() {
+                    check_statement(s)?;
+                }
+            }
+            return Ok(());
+        },
+        _ => return Ok(()),
+    };
+    for e in list.iter() {
+        check_statement(e)?;
+    }
+    Ok(())
+}
+
+// `a[i]` や `p.x` に代入するときに書き換わる変数
+fn root_name(expr: &Expr) -> String {
+    match expr {
+        Expr::Index(e) => root_name(&e.array),
+        Expr::Field(e) => root_name(&e.object),
+        _ => expr.name(),
+    }
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(f) => f.to_string_lossy().to_string(),
+        None => path.display().to_string(),
+    }
+}
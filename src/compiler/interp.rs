@@ -0,0 +1,561 @@
+use super::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i32),
+    // charとstrはLLVM側と同じくバイト. UTF-8の途中で切った文字列もそのまま持つ
+    Char(u8),
+    Str(Vec<u8>),
+    Bool(bool),
+    Array(Vec<i32>),
+    Vector(Vec<i32>),
+    // structの名前とフィールド. フィールドは定義の順
+    Struct(String, Vec<(String, Value)>),
+    // enumの名前, variant, 持っている値
+    Enum(String, String, Vec<Value>),
+}
+impl Value {
+    // printで出すバイト列. 文字列とcharはLLVM側のprintfと同じくバイトのまま出す
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Int(i) => i.to_string().into_bytes(),
+            Value::Char(c) => vec![*c],
+            Value::Str(s) => s.clone(),
+            Value::Bool(b) => b.to_string().into_bytes(),
+            Value::Array(list) | Value::Vector(list) => {
+                let items: Vec<String> = list.iter().map(|i| i.to_string()).collect();
+                format!("[{}]", items.join(", ")).into_bytes()
+            },
+            Value::Struct(name, fields) => {
+                let items: Vec<Vec<u8>> = fields.iter().map(|(n, v)| [format!("{}: ", n).into_bytes(), inner(v)].concat()).collect();
+                [format!("{} {{ ", name).into_bytes(), items.join(&b", "[..]), b" }".to_vec()].concat()
+            },
+            Value::Enum(_, variant, values) => {
+                if values.is_empty() {
+                    return variant.clone().into_bytes();
+                }
+                let items: Vec<Vec<u8>> = values.iter().map(inner).collect();
+                [format!("{}(", variant).into_bytes(), items.join(&b", "[..]), b")".to_vec()].concat()
+            },
+        }
+    }
+}
+
+// エラーメッセージ用. UTF-8でないバイトは置き換える
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.bytes()))
+    }
+}
+
+// structやenumの中の文字列とcharは引用符を付けて出す
+fn inner(value: &Value) -> Vec<u8> {
+    match value {
+        Value::Str(s) => [&b"\""[..], s, b"\""].concat(),
+        Value::Char(c) => vec![b'\'', *c, b'\''],
+        v => v.bytes(),
+    }
+}
+
+// LLVMを使わずにExprの木をそのまま評価する
+pub struct Interpreter<'w> {
+    vars: HashMap<String, Value>,
+    types: types::Registry,
+    // 直前のif/else if/elseの連なりで, すでにどれかの節を実行したか
+    chain: Option<bool>,
+    // 直前の読み込みで入力が終わっていたか
+    eof: bool,
+    // panicのメッセージに出すファイル名
+    source: String,
+    // returnかexitで決まった終了コード. 決まったら残りの文は実行しない
+    exit: Option<i32>,
+    input: &'w mut dyn BufRead,
+    out: &'w mut dyn Write,
+}
+
+// panicは標準エラーに出してPANIC_EXIT_CODEを返す. それ以外のエラーはErrで返す
+pub fn run(ast: &[Expr], source: &str, input: &mut dyn BufRead, out: &mut dyn Write) -> Result<i32, String> {
+    let mut interpreter = Interpreter::new(source, input, out);
+    match interpreter.exec(ast) {
+        Err(err) if is_panic(&err) => {
+            let _ = interpreter.out.flush();
+            eprintln!("{}", err);
+            Ok(PANIC_EXIT_CODE)
+        },
+        Err(err) => Err(err),
+        Ok(()) => Ok(interpreter.exit.unwrap_or(0)),
+    }
+}
+
+const PANIC: &str = "panicked at ";
+
+pub fn is_panic(err: &str) -> bool {
+    err.starts_with(PANIC)
+}
+
+// LLVM側のbuild_panicと同じ形にする
+fn panic(source: &str, pos: Pos, message: String) -> String {
+    format!("{}{}:{}:{}: {}", PANIC, source, pos.line, pos.col, message)
+}
+
+impl<'w> Interpreter<'w> {
+    pub fn new(source: &str, input: &'w mut dyn BufRead, out: &'w mut dyn Write) -> Interpreter<'w> {
+        let source = if source.is_empty() { "<input>".to_string() } else { source.to_string() };
+        Interpreter { vars: HashMap::new(), types: types::Registry::new(), chain: None, eof: false, source, exit: None, input, out }
+    }
+
+    // replは入力の行もここから読むので, プログラムのinput()と同じ流れになる
+    pub fn input(&mut self) -> &mut dyn BufRead {
+        self.input
+    }
+
+    pub fn reset(&mut self) {
+        self.vars.clear();
+        self.types = types::Registry::new();
+        self.chain = None;
+        self.exit = None;
+    }
+
+    pub fn exec(&mut self, list: &[Expr]) -> Result<(), String> {
+        for expr in list.iter() {
+            self.statement(expr)?;
+            if self.exit.is_some() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::ElseIf(_) | Expr::Else(_) => {},
+            _ => self.chain = None,
+        }
+        match expr {
+            Expr::Assign(e) => {
+                if let Expr::Index(target) = &e.left_expr {
+                    let value = self.eval_int(&e.right_expr)?;
+                    let i = self.eval_int(&target.index)?;
+                    let name = target.array.name();
+                    let list = match self.vars.get_mut(&name) {
+                        Some(Value::Array(list)) | Some(Value::Vector(list)) => list,
+                        Some(v) => return Err(format!("{} is not an array, found {}", name, v)),
+                        None => return Err(format!("{} not found", name)),
+                    };
+                    let slot = bounds_check(&self.source, list.len(), i, target.pos)?;
+                    list[slot] = value;
+                    return Ok(());
+                }
+                if let Expr::Field(target) = &e.left_expr {
+                    let value = self.eval(&e.right_expr)?;
+                    let type_name = match self.eval(&target.object)? {
+                        Value::Struct(name, _) => self.types.field(&name, &target.name)?.1.to_string(),
+                        v => return Err(format!("{} is not a struct, found {}", target.object.name(), v)),
+                    };
+                    let value = convert(value, &type_name)?;
+                    *field_mut(&mut self.vars, &e.left_expr)? = value;
+                    return Ok(());
+                }
+                let value = self.eval(&e.right_expr)?;
+                // vecのバッファは1つの変数だけが持つ (runtime.rsを参照)
+                if let (Value::Vector(_), Expr::Ident(_) | Expr::Field(_)) = (&value, &e.right_expr) {
+                    return Err(format!("a vec cannot be copied: {} = {}", e.left_expr.name(), e.right_expr.name()));
+                }
+                self.vars.insert(e.left_expr.name(), value);
+            },
+            Expr::Return(e) => self.exit = Some(self.eval_int(&e.val)?),
+            Expr::Assert(e) => {
+                let message = format!("assertion failed: {}", e.text);
+                // 値を出すために == の左右は1回だけ評価する
+                if let Expr::Equal(eq) = &e.condition {
+                    let left = self.eval(&eq.left_expr)?;
+                    let right = self.eval(&eq.right_expr)?;
+                    if !equal(&left, &right)? {
+                        return Err(panic(&self.source, e.pos, format!("{}\n  left: {}\n right: {}", message, String::from_utf8_lossy(&inner(&left)), String::from_utf8_lossy(&inner(&right)))));
+                    }
+                } else if !self.condition(&e.condition)? {
+                    return Err(panic(&self.source, e.pos, message));
+                }
+            },
+            Expr::Panic(e) => {
+                let message = self.eval_str(&e.message)?;
+                return Err(panic(&self.source, e.pos, String::from_utf8_lossy(&message).to_string()));
+            },
+            Expr::Print(e) => {
+                let mut text = Vec::new();
+                for item in e.list.iter() {
+                    text.extend(self.eval(item)?.bytes());
+                }
+                if e.newline {
+                    text.push(b'\n');
+                }
+                if let Err(err) = self.out.write_all(&text) {
+                    return Err(format!("could not write output: {}", err));
+                }
+            },
+            Expr::If(e) => {
+                let taken = self.condition(&e.condition)?;
+                if taken {
+                    self.block(&e.list)?;
+                }
+                self.chain = Some(taken);
+            },
+            Expr::ElseIf(e) => {
+                match self.chain {
+                    None => return Err("else if without if".to_string()),
+                    Some(true) => {},
+                    Some(false) => {
+                        let taken = self.condition(&e.condition)?;
+                        if taken {
+                            self.block(&e.list)?;
+                        }
+                        self.chain = Some(taken);
+                    }
+                }
+            },
+            Expr::Else(e) => {
+                match self.chain {
+                    None => return Err("else without if".to_string()),
+                    Some(true) => {},
+                    Some(false) => self.block(&e.list)?,
+                }
+                self.chain = None;
+            },
+            Expr::Push(e) => {
+                let value = self.eval_int(&e.val)?;
+                self.vector(&e.vector)?.push(value);
+            },
+            Expr::Struct(e) => self.types.define(e)?,
+            Expr::Enum(e) => {
+                self.types.define_enum(e)?;
+                // 値を持たないvariantは `Shape.Empty` という変数として読めるようにする
+                for (variant, types) in e.variants.iter() {
+                    if types.is_empty() {
+                        self.vars.insert(format!("{}.{}", e.name, variant), Value::Enum(e.name.clone(), variant.clone(), Vec::new()));
+                    }
+                }
+            },
+            Expr::Match(e) => {
+                self.types.check_match(e)?;
+                let (variant, values) = match self.eval(&e.value)? {
+                    Value::Enum(_, variant, values) => (variant, values),
+                    v => return Err(format!("match needs an enum, found {}", v)),
+                };
+                let arm = e.arms.iter().find(|arm| match &arm.pattern {
+                    None => true,
+                    Some((_, v)) => *v == variant,
+                });
+                if let Some(arm) = arm {
+                    for (name, value) in arm.bindings.iter().zip(values) {
+                        self.vars.insert(name.clone(), value);
+                    }
+                    self.block(&arm.list)?;
+                }
+            },
+            // testはnyaan testで選ばれたときだけ実行する (harness::selectを参照)
+            Expr::Test(_) | Expr::Nope => {},
+            _ => {
+                self.eval(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    // ブロックの中のif連鎖は外側の連鎖と別に扱う
+    fn block(&mut self, list: &[Expr]) -> Result<(), String> {
+        let chain = self.chain;
+        self.exec(list)?;
+        self.chain = chain;
+        Ok(())
+    }
+
+    fn condition(&mut self, expr: &Expr) -> Result<bool, String> {
+        match self.eval(expr)? {
+            Value::Bool(b) => Ok(b),
+            Value::Int(i) => Ok(i != 0),
+            v => Err(format!("condition must be a bool, found {}", v)),
+        }
+    }
+
+    pub fn eval(&mut self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Int(e) => Ok(Value::Int(e.eval())),
+            Expr::Char(e) => Ok(Value::Char(e.eval() as u8)),
+            Expr::Str(e) => Ok(Value::Str(e.eval().into_bytes())),
+            Expr::Bool(e) => Ok(Value::Bool(e.eval())),
+            Expr::Ident(e) => match self.vars.get(&e.name) {
+                None => Err(format!("{} not found", e.name())),
+                Some(v) => Ok(v.clone()),
+            },
+            Expr::BinaryOp(e) => {
+                if let Value::Str(left) = self.eval(&e.left_expr)? {
+                    return match (e.kind, self.eval(&e.right_expr)?) {
+                        (Kind::Plus, Value::Str(right)) => Ok(Value::Str([left, right].concat())),
+                        (Kind::Plus, v) => Err(format!("cannot add {} to a string; use str()", v)),
+                        (kind, _) => Err(format!("unsupported operator {:?} for strings", kind)),
+                    };
+                }
+                let left = self.eval_int(&e.left_expr)?;
+                let right = self.eval_int(&e.right_expr)?;
+                // LLVM側の -O0 と同じくあふれも調べる
+                let (result, name) = match e.kind {
+                    Kind::Plus => (left.checked_add(right), "add"),
+                    Kind::Minus => (left.checked_sub(right), "subtract"),
+                    Kind::Multi => (left.checked_mul(right), "multiply"),
+                    Kind::Divi => {
+                        if right == 0 {
+                            return Err(panic(&self.source, e.pos, "attempt to divide by zero".to_string()));
+                        }
+                        (left.checked_div(right), "divide")
+                    },
+                    _ => return Err(format!("unsupported operator {:?}", e.kind)),
+                };
+                let result = match result {
+                    None => return Err(panic(&self.source, e.pos, format!("attempt to {} with overflow", name))),
+                    Some(v) => v,
+                };
+                Ok(Value::Int(result))
+            },
+            Expr::Equal(e) => {
+                let left = self.eval(&e.left_expr)?;
+                let right = self.eval(&e.right_expr)?;
+                Ok(Value::Bool(equal(&left, &right)?))
+            },
+            Expr::Array(e) => {
+                let mut list = Vec::new();
+                for item in e.list.iter() {
+                    list.push(self.eval_int(item)?);
+                }
+                list.resize(e.len, 0);
+                Ok(Value::Array(list))
+            },
+            Expr::Index(e) => {
+                let i = self.eval_int(&e.index)?;
+                match self.eval(&e.array)? {
+                    // 文字列はバイト単位で読む
+                    Value::Str(s) => {
+                        let slot = bounds_check(&self.source, s.len(), i, e.pos)?;
+                        Ok(Value::Char(s[slot]))
+                    },
+                    Value::Array(list) | Value::Vector(list) => {
+                        let slot = bounds_check(&self.source, list.len(), i, e.pos)?;
+                        Ok(Value::Int(list[slot]))
+                    },
+                    v => Err(format!("array expected, found {}", v)),
+                }
+            },
+            Expr::Len(e) => match self.eval(&e.array)? {
+                Value::Str(s) => Ok(Value::Int(s.len() as i32)),
+                Value::Array(list) | Value::Vector(list) => Ok(Value::Int(list.len() as i32)),
+                v => Err(format!("array expected, found {}", v)),
+            },
+            Expr::Substr(e) => {
+                let s = self.eval_str(&e.string)?;
+                let start = self.eval_int(&e.start)?;
+                let end = self.eval_int(&e.end)?;
+                if start < 0 || start > end || end as usize > s.len() {
+                    return Err(panic(&self.source, e.pos, format!("substr out of bounds: the len is {} but the range is {}..{}", s.len(), start, end)));
+                }
+                Ok(Value::Str(s[start as usize..end as usize].to_vec()))
+            },
+            Expr::ToStr(e) => Ok(Value::Str(self.eval_int(&e.val)?.to_string().into_bytes())),
+            Expr::ToInt(e) => {
+                let s = String::from_utf8_lossy(&self.eval_str(&e.string)?).to_string();
+                // LLVM側のstrtolと同じく先頭の空白は読み飛ばす
+                match s.trim_start_matches(|c| " \t\n\x0b\x0c\r".contains(c)).parse::<i32>() {
+                    Ok(i) => Ok(Value::Int(i)),
+                    Err(_) => Err(panic(&self.source, e.pos, format!("invalid number \"{}\"", s))),
+                }
+            },
+            Expr::Vector(e) => {
+                let mut list = Vec::new();
+                for item in e.list.iter() {
+                    list.push(self.eval_int(item)?);
+                }
+                Ok(Value::Vector(list))
+            },
+            Expr::Input(e) => self.read(e),
+            Expr::Pop(e) => match self.vector(&e.vector)?.pop() {
+                None => Err(panic(&self.source, e.pos, "pop from an empty vec".to_string())),
+                Some(v) => Ok(Value::Int(v)),
+            },
+            Expr::StructLit(e) => {
+                let values = self.types.check_literal(e)?;
+                let def = self.types.get(&e.name)?.clone();
+                let mut fields = Vec::new();
+                for ((name, type_name), val) in def.fields.iter().zip(values) {
+                    let value = self.eval(val)?;
+                    fields.push((name.clone(), convert(value, type_name)?));
+                }
+                Ok(Value::Struct(e.name.clone(), fields))
+            },
+            Expr::EnumLit(e) => {
+                let types = self.types.variant(&e.name, &e.variant)?.1.clone();
+                if types.len() != e.args.len() {
+                    return Err(format!("{}.{} takes {} value(s) but {} were given ({}:{})", e.name, e.variant, types.len(), e.args.len(), e.pos.line, e.pos.col));
+                }
+                let mut values = Vec::new();
+                for (type_name, arg) in types.iter().zip(e.args.iter()) {
+                    let value = self.eval(arg)?;
+                    values.push(convert(value, type_name)?);
+                }
+                Ok(Value::Enum(e.name.clone(), e.variant.clone(), values))
+            },
+            // `Shape.Empty` はフィールドではなく変数として入っている
+            Expr::Field(_) if self.vars.contains_key(&expr.name()) => Ok(self.vars[&expr.name()].clone()),
+            Expr::Field(e) => match self.eval(&e.object)? {
+                Value::Struct(name, fields) => match fields.into_iter().find(|(n, _)| *n == e.name) {
+                    None => Err(format!("struct {} has no field {}", name, e.name)),
+                    Some((_, v)) => Ok(v),
+                },
+                v => Err(format!("{} is not a struct, found {}", e.object.name(), v)),
+            },
+            _ => Err(format!("{} cannot be evaluated", expr.name())),
+        }
+    }
+
+    // LLVM側のgetcharとscanfに合わせて読む (runtime.rsを参照)
+    fn read(&mut self, e: &Input) -> Result<Value, String> {
+        let _ = self.out.flush();
+        match e.kind {
+            Kind::Str => {
+                let mut line = Vec::new();
+                self.input.read_until(b'\n', &mut line).map_err(|err| err.to_string())?;
+                self.eof = line.is_empty();
+                if line.last() == Some(&b'\n') {
+                    line.pop();
+                }
+                Ok(Value::Str(line))
+            },
+            Kind::Char => {
+                let c = self.next_byte()?;
+                self.eof = c.is_none();
+                Ok(Value::Char(c.unwrap_or(0)))
+            },
+            Kind::Int => {
+                while let Some(b) = self.peek_byte()? {
+                    if !b.is_ascii_whitespace() {
+                        break;
+                    }
+                    self.next_byte()?;
+                }
+                let mut digits = String::new();
+                while let Some(b) = self.peek_byte()? {
+                    let sign = digits.is_empty() && (b == b'-' || b == b'+');
+                    if !sign && !b.is_ascii_digit() {
+                        break;
+                    }
+                    digits.push(b as char);
+                    self.next_byte()?;
+                }
+                self.eof = digits.is_empty() && self.peek_byte()?.is_none();
+                if self.eof {
+                    return Ok(Value::Int(0));
+                }
+                match digits.parse::<i32>() {
+                    Ok(i) => Ok(Value::Int(i)),
+                    Err(_) => Err(panic(&self.source, e.pos, "read_int: the input is not a number".to_string())),
+                }
+            },
+            _ => Ok(Value::Int(self.eof as i32)),
+        }
+    }
+
+    fn peek_byte(&mut self) -> Result<Option<u8>, String> {
+        let buf = self.input.fill_buf().map_err(|err| err.to_string())?;
+        Ok(buf.first().copied())
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>, String> {
+        let b = self.peek_byte()?;
+        if b.is_some() {
+            self.input.consume(1);
+        }
+        Ok(b)
+    }
+
+    fn eval_str(&mut self, expr: &Expr) -> Result<Vec<u8>, String> {
+        match self.eval(expr)? {
+            Value::Str(s) => Ok(s),
+            v => Err(format!("string expected, found {}", v)),
+        }
+    }
+
+    fn vector(&mut self, expr: &Expr) -> Result<&mut Vec<i32>, String> {
+        let name = expr.name();
+        match self.vars.get_mut(&name) {
+            Some(Value::Vector(list)) => Ok(list),
+            Some(v) => Err(format!("{} is not a vec, found {}", name, v)),
+            None => Err(format!("{} not found", name)),
+        }
+    }
+
+    fn eval_int(&mut self, expr: &Expr) -> Result<i32, String> {
+        match self.eval(expr)? {
+            Value::Int(i) => Ok(i),
+            Value::Char(c) => Ok(c as i32),
+            v => Err(format!("int expected, found {}", v)),
+        }
+    }
+}
+
+// ==で比べる. structとenumは比べられない
+fn equal(left: &Value, right: &Value) -> Result<bool, String> {
+    if let (Value::Struct(_, _), _) | (_, Value::Struct(_, _)) = (left, right) {
+        return Err("cannot compare structs; compare their fields".to_string());
+    }
+    if let (Value::Enum(_, _, _), _) | (_, Value::Enum(_, _, _)) = (left, right) {
+        return Err("cannot compare enums; use match".to_string());
+    }
+    // charはLLVM側と同じく整数として比べる
+    match (left, right) {
+        (Value::Int(i), Value::Char(c)) | (Value::Char(c), Value::Int(i)) => Ok(*i == *c as i32),
+        _ => Ok(left == right),
+    }
+}
+
+// LLVM側と同じメッセージで範囲外アクセスを止める
+fn bounds_check(source: &str, len: usize, index: i32, pos: Pos) -> Result<usize, String> {
+    if index < 0 || index as usize >= len {
+        return Err(panic(source, pos, format!("index out of bounds: the len is {} but the index is {}", len, index)));
+    }
+    Ok(index as usize)
+}
+
+// フィールドの型に合わせる. intとcharはLLVM側と同じく整数として入れ替えられる
+fn convert(value: Value, type_name: &str) -> Result<Value, String> {
+    let converted = match (types::kind_of_type(type_name), value) {
+        (Kind::Int, Value::Int(i)) => Value::Int(i),
+        (Kind::Int, Value::Char(c)) => Value::Int(c as i32),
+        (Kind::Int, Value::Bool(b)) => Value::Int(b as i32),
+        (Kind::Char, Value::Char(c)) => Value::Char(c),
+        (Kind::Char, Value::Int(i)) => Value::Char(i as u8),
+        (Kind::Str, Value::Str(s)) => Value::Str(s),
+        (Kind::Struct, Value::Struct(name, fields)) if name == type_name => Value::Struct(name, fields),
+        (_, v) => return Err(format!("expected {}, found {}", type_name, v)),
+    };
+    Ok(converted)
+}
+
+// `p.inner.x = 1;` で書き換える場所
+fn field_mut<'a>(vars: &'a mut HashMap<String, Value>, expr: &Expr) -> Result<&'a mut Value, String> {
+    match expr {
+        Expr::Field(e) => match field_mut(vars, &e.object)? {
+            Value::Struct(name, fields) => match fields.iter_mut().find(|(n, _)| *n == e.name) {
+                None => Err(format!("struct {} has no field {}", name, e.name)),
+                Some((_, v)) => Ok(v),
+            },
+            v => Err(format!("{} is not a struct, found {}", e.object.name(), v)),
+        },
+        _ => {
+            let name = expr.name();
+            match vars.get_mut(&name) {
+                None => Err(format!("{} not found", name)),
+                Some(v) => Ok(v),
+            }
+        }
+    }
+}
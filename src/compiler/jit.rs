@@ -0,0 +1,36 @@
+use inkwell::module::Module;
+use inkwell::targets::{InitializationConfig, Target};
+use inkwell::OptimizationLevel;
+use std::io::Write;
+
+type MainFunc = unsafe extern "C" fn() -> i32;
+
+extern "C" {
+    fn fflush(stream: *mut std::ffi::c_void) -> i32;
+}
+
+// moduleのmainをプロセス内で実行し, その戻り値を返す
+pub fn run(module: &Module, opt_level: OptimizationLevel) -> i32 {
+    if let Err(err) = Target::initialize_native(&InitializationConfig::default()) {
+        jit_error(format!("could not initialize native target: {}", err));
+    }
+    let engine = match module.create_jit_execution_engine(opt_level) {
+        Ok(e) => e,
+        Err(err) => jit_error(format!("could not create execution engine: {}", err.to_string())),
+    };
+    let main = match unsafe { engine.get_function::<MainFunc>("main") } {
+        Ok(f) => f,
+        Err(err) => jit_error(format!("could not find main: {:?}", err)),
+    };
+
+    // Rust側とCのstdoutの出力順が入れ替わらないようにする
+    let _ = std::io::stdout().flush();
+    let ret = unsafe { main.call() };
+    unsafe { fflush(std::ptr::null_mut()); }
+    ret
+}
+
+fn jit_error(message: String) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1);
+}
@@ -1,43 +1,189 @@
+#[cfg(feature = "llvm")]
+pub mod debug;
+pub mod emit;
+pub mod fold;
+pub mod format;
+pub mod import;
+pub mod interp;
+#[cfg(feature = "llvm")]
+pub mod jit;
+#[cfg(feature = "llvm")]
+pub mod optimize;
 pub mod parser;
+#[cfg(feature = "llvm")]
+pub mod runtime;
+pub mod symbols;
 pub mod tokenizer;
+pub mod types;
 
+#[cfg(feature = "llvm")]
 use inkwell::{context::Context};
+#[cfg(feature = "llvm")]
 use inkwell::{AddressSpace, IntPredicate};
-use inkwell::module::{Linkage};
+#[cfg(feature = "llvm")]
+use inkwell::module::{Linkage, Module};
+#[cfg(feature = "llvm")]
 use inkwell::values::{IntValue, PointerValue, BasicValueEnum};
+#[cfg(feature = "llvm")]
 use std::{collections::HashMap};
+#[cfg(feature = "llvm")]
 use std::cell::{RefCell, Cell};
+#[cfg(feature = "llvm")]
 use inkwell::basic_block::BasicBlock;
+#[cfg(feature = "llvm")]
 #[allow(unused_mut)]
 #[allow(non_camel_case_types)]
 #[allow(unused_assignments)]
-pub fn generate(ast: &Vec<Expr>) {
-    let context = Context::create();
-    let module = context.create_module("main");
+pub fn generate<'ctx>(context: &'ctx Context, ast: &[Expr], config: &emit::Config) -> Result<Module<'ctx>, String> {
+    let module = context.create_module(&config.module_name());
+    if !config.input.is_empty() {
+        module.set_source_file_name(&config.input);
+    }
     let builder = context.create_builder();
     let i32_type = context.i32_type();
     let mut var_table_cell: RefCell<HashMap<String, PointerValue>> = RefCell::new(HashMap::new());
+    let var_kind_cell: RefCell<HashMap<String, Kind>> = RefCell::new(HashMap::new());
+    let array_len_cell: RefCell<HashMap<String, u32>> = RefCell::new(HashMap::new());
+    let types_cell: RefCell<types::Registry> = RefCell::new(types::Registry::new());
+    // structやenumの変数と `p.home` のようなフィールドがどの型か
+    let type_name_cell: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
     let putchar_type = i32_type.fn_type(&[i32_type.into()], false);
     let str_type = context.i8_type().ptr_type(AddressSpace::Generic);
     let printf_type = i32_type.fn_type(&[str_type.into()], true);
-    module.add_function("puts", printf_type, Some(Linkage::External));
+    module.add_function("printf", printf_type, Some(Linkage::External));
     module.add_function("putchar", putchar_type, None);
+    let dprintf_type = i32_type.fn_type(&[i32_type.into(), str_type.into()], true);
+    module.add_function("dprintf", dprintf_type, Some(Linkage::External));
+    module.add_function("exit", context.void_type().fn_type(&[i32_type.into()], false), Some(Linkage::External));
+    let i8_ptr_type = context.i8_type().ptr_type(AddressSpace::Generic);
+    let size_type = context.i64_type();
+    module.add_function("malloc", i8_ptr_type.fn_type(&[size_type.into()], false), Some(Linkage::External));
+    module.add_function("realloc", i8_ptr_type.fn_type(&[i8_ptr_type.into(), size_type.into()], false), Some(Linkage::External));
+    module.add_function("free", context.void_type().fn_type(&[i8_ptr_type.into()], false), Some(Linkage::External));
+    module.add_function("memcpy", i8_ptr_type.fn_type(&[i8_ptr_type.into(), i8_ptr_type.into(), size_type.into()], false), Some(Linkage::External));
+    module.add_function("memset", i8_ptr_type.fn_type(&[i8_ptr_type.into(), i32_type.into(), size_type.into()], false), Some(Linkage::External));
+    module.add_function("memcmp", i32_type.fn_type(&[i8_ptr_type.into(), i8_ptr_type.into(), size_type.into()], false), Some(Linkage::External));
+    module.add_function("snprintf", i32_type.fn_type(&[i8_ptr_type.into(), size_type.into(), i8_ptr_type.into()], true), Some(Linkage::External));
+    module.add_function("strtol", size_type.fn_type(&[i8_ptr_type.into(), i8_ptr_type.ptr_type(AddressSpace::Generic).into(), i32_type.into()], false), Some(Linkage::External));
+    module.add_function("getchar", i32_type.fn_type(&[], false), Some(Linkage::External));
+    module.add_function("scanf", i32_type.fn_type(&[i8_ptr_type.into()], true), Some(Linkage::External));
+    let eof_flag = module.add_global(i32_type, None, runtime::EOF_FLAG);
+    eof_flag.set_initializer(&i32_type.const_int(0, false));
     let main_type = i32_type.fn_type(&[], false);
     let function = module.add_function("main", main_type, None);
     let basic_block = context.append_basic_block(function, "entry");
     builder.position_at_end(basic_block);
+    let debug_info = if config.debug { Some(debug::DebugInfo::new(context, &module, function, config)) } else { None };
 
     let mut ast_iter = 0;
 
-    let get_int_from_int_val = |int_val: IntValue| -> i32{
-        let s = int_val.print_to_string().to_string();
-        let vec:Vec<&str> = s.split_whitespace().collect();
-        let val: i32 = vec[1].parse().unwrap();
-        val
-    };
     let int_cell: Cell<BasicValueEnum> = Cell::new(BasicValueEnum::IntValue(context.i32_type().const_int(0, false)));
     let branches: RefCell<Vec<BasicBlock>> = RefCell::new(Vec::new());
     let after_if: Cell<bool> = Cell::new(false);
+    // if連鎖の最後の後ろのブロック. 中に入れ子のブロックがあると関数の最後のブロックとは限らない
+    let after_block_cell: Cell<Option<BasicBlock>> = Cell::new(None);
+    let match_count: Cell<u32> = Cell::new(0);
+    // 最初に見つかったエラー. 生成はそのまま続けて最後に返す
+    let error: RefCell<Option<String>> = RefCell::new(None);
+    let set_error = |message: String| {
+        let mut e = error.borrow_mut();
+        if e.is_none() {
+            *e = Some(message);
+        }
+    };
+    let source_name = if config.input.is_empty() { "<input>".to_string() } else { config.input.clone() };
+    let lookup = |name: &str| -> Option<(PointerValue<'ctx>, Kind)> {
+        let ptr = var_table_cell.borrow().get(name).copied();
+        match ptr {
+            None => { set_error(format!("{} not found", name)); None },
+            Some(p) => Some((p, var_kind_cell.borrow().get(name).copied().unwrap_or(Kind::Int))),
+        }
+    };
+    // 配列の長さは定数, vecの長さは実行時に読む
+    let lookup_len = |name: &str| -> Option<(PointerValue<'ctx>, IntValue<'ctx>)> {
+        match lookup(name)? {
+            (p, Kind::Array) => Some((p, i32_type.const_int(array_len_cell.borrow()[name] as u64, false))),
+            (p, Kind::Vector) => Some((p, runtime::build_vec_len(&builder, p))),
+            _ => { set_error(format!("{} is not an array", name)); None },
+        }
+    };
+    let lookup_vec = |name: &str| -> Option<PointerValue<'ctx>> {
+        match lookup(name)? {
+            (p, Kind::Vector) => Some(p),
+            _ => { set_error(format!("{} is not a vec", name)); None },
+        }
+    };
+    // 範囲外ならインデックス, 長さ, 位置を表示してabortする. 範囲内なら要素へのポインタを返す
+    let element_ptr = |name: &str, index: IntValue<'ctx>, pos: Pos| -> Option<PointerValue<'ctx>> {
+        let (ptr, len) = lookup_len(name)?;
+        // 負のインデックスも符号なしで比べれば範囲外になる
+        let in_bounds = builder.build_int_compare(IntPredicate::ULT, index, len, "");
+        let location = runtime::location(&source_name, pos);
+        runtime::build_check(context, &module, &builder, in_bounds, Some(&location), "index out of bounds: the len is %d but the index is %d", &[len.into(), index.into()]);
+        let slot = if var_kind_cell.borrow().get(name) == Some(&Kind::Vector) {
+            unsafe { builder.build_gep(runtime::build_vec_data(&builder, ptr), &[index], "") }
+        } else {
+            unsafe { builder.build_gep(ptr, &[i32_type.const_int(0, false), index], "") }
+        };
+        Some(slot)
+    };
+    // `p.z` が見つからないときのメッセージ
+    let missing = |expr: &Expr| -> String {
+        if let Expr::Field(e) = expr {
+            let object = e.object.name();
+            if let Some(s) = type_name_cell.borrow().get(&object) {
+                return format!("struct {} has no field {}", s, e.name);
+            }
+            if var_table_cell.borrow().contains_key(&object) {
+                return format!("{} is not a struct", object);
+            }
+        }
+        format!("{} not found", expr.name())
+    };
+    // structやenumの変数を作る. `p.x` や `p.home.y` も変数と同じように表に入れておく.
+    // enumはtagを `s.%tag`, variantの値を `s.Circle.0` のように名前にならない形で入れる
+    let declare_struct = |name: &str, type_name: &str| {
+        let prefix = format!("{}.", name);
+        var_table_cell.borrow_mut().retain(|n, _| !n.starts_with(&prefix));
+        var_kind_cell.borrow_mut().retain(|n, _| !n.starts_with(&prefix));
+        type_name_cell.borrow_mut().retain(|n, _| !n.starts_with(&prefix));
+        let registry = types_cell.borrow();
+        let mut fields: Vec<(String, Vec<u32>, String)> = vec![(name.to_string(), Vec::new(), type_name.to_string())];
+        let mut i = 0;
+        while i < fields.len() {
+            let (path_name, path, field_type) = fields[i].clone();
+            let child = |index: &[u32], name: String, t: &str| {
+                let mut p = path.clone();
+                p.extend_from_slice(index);
+                (name, p, t.to_string())
+            };
+            if let Ok(def) = registry.get(&field_type) {
+                for (j, (field, t)) in def.fields.iter().enumerate() {
+                    fields.push(child(&[j as u32], format!("{}.{}", path_name, field), t));
+                }
+            }
+            if let Ok(def) = registry.get_enum(&field_type) {
+                fields.push(child(&[0], format!("{}.%tag", path_name), "int"));
+                for (v, (variant, types)) in def.variants.iter().enumerate() {
+                    for (j, t) in types.iter().enumerate() {
+                        fields.push(child(&[v as u32 + 1, j as u32], format!("{}.{}.{}", path_name, variant, j), t));
+                    }
+                }
+            }
+            i += 1;
+        }
+        let paths: Vec<Vec<u32>> = fields.iter().map(|(_, p, _)| p.clone()).collect();
+        let ty = runtime::struct_type(context, &registry, type_name);
+        let (_, ptrs) = runtime::build_struct_var(context, function, ty, name, &paths);
+        for ((path_name, _, field_type), ptr) in fields.into_iter().zip(ptrs) {
+            let kind = registry.kind_of(&field_type);
+            if kind == Kind::Struct || kind == Kind::Enum {
+                type_name_cell.borrow_mut().insert(path_name.clone(), field_type);
+            }
+            var_kind_cell.borrow_mut().insert(path_name.clone(), kind);
+            var_table_cell.borrow_mut().insert(path_name, ptr);
+        }
+    };
     struct Eval_Int_Formula<'s>{ f: &'s dyn Fn(&Eval_Int_Formula, Expr) }
     let eval_int_formula= Eval_Int_Formula {
         f: &|eval_int_formula, expr| {
@@ -45,46 +191,181 @@ pub fn generate(ast: &Vec<Expr>) {
                 Expr::Int(e) => {
                     int_cell.set(BasicValueEnum::IntValue(context.i32_type().const_int(e.eval() as u64, false)));
                 },
-                Expr::Ident(e) => {
-                    let mut var_table = var_table_cell.borrow_mut();
-                    let ptr = match var_table.get(&e.name) {
+                Expr::Bool(e) => {
+                    int_cell.set(BasicValueEnum::IntValue(context.bool_type().const_int(e.eval() as u64, false)));
+                },
+                Expr::Char(e) => {
+                    int_cell.set(BasicValueEnum::IntValue(context.i32_type().const_int(e.eval() as u64, false)));
+                },
+                Expr::Index(e) => {
+                    (eval_int_formula.f)(&eval_int_formula, e.index);
+                    let index = int_cell.get().into_int_value();
+                    if var_kind_cell.borrow().get(&e.array.name()) == Some(&Kind::Str) {
+                        (eval_int_formula.f)(&eval_int_formula, e.array);
+                        let location = runtime::location(&source_name, e.pos);
+                        int_cell.set(BasicValueEnum::IntValue(runtime::build_str_index(context, &module, &builder, int_cell.get().into_pointer_value(), index, &location)));
+                        return;
+                    }
+                    int_cell.set(BasicValueEnum::IntValue(index));
+                    match element_ptr(&e.array.name(), int_cell.get().into_int_value(), e.pos) {
+                        None => int_cell.set(BasicValueEnum::IntValue(context.i32_type().const_int(0, false))),
+                        Some(slot) => int_cell.set(builder.build_load(slot, "")),
+                    }
+                },
+                Expr::Len(e) => {
+                    if kind_of(&e.array, &var_kind_cell.borrow()) == Kind::Str {
+                        (eval_int_formula.f)(&eval_int_formula, e.array);
+                        int_cell.set(BasicValueEnum::IntValue(runtime::build_str_len(&builder, int_cell.get().into_pointer_value())));
+                        return;
+                    }
+                    let len = match lookup_len(&e.array.name()) {
+                        None => context.i32_type().const_int(0, false),
+                        Some((_, l)) => l,
+                    };
+                    int_cell.set(BasicValueEnum::IntValue(len));
+                },
+                Expr::Substr(e) => {
+                    (eval_int_formula.f)(&eval_int_formula, e.string);
+                    let string = int_cell.get();
+                    (eval_int_formula.f)(&eval_int_formula, e.start);
+                    let start = int_cell.get().into_int_value();
+                    (eval_int_formula.f)(&eval_int_formula, e.end);
+                    let end = int_cell.get().into_int_value();
+                    if !string.is_pointer_value() {
+                        set_error("substr needs a string".to_string());
+                        return;
+                    }
+                    let location = runtime::location(&source_name, e.pos);
+                    int_cell.set(BasicValueEnum::PointerValue(runtime::build_str_substr(context, &module, &builder, function, string.into_pointer_value(), start, end, &location)));
+                },
+                Expr::ToStr(e) => {
+                    (eval_int_formula.f)(&eval_int_formula, e.val);
+                    let mut val = int_cell.get().into_int_value();
+                    if val.get_type().get_bit_width() < 32 {
+                        val = builder.build_int_z_extend(val, i32_type, "");
+                    }
+                    int_cell.set(BasicValueEnum::PointerValue(runtime::build_int_to_str(context, &module, &builder, function, val)));
+                },
+                Expr::ToInt(e) => {
+                    (eval_int_formula.f)(&eval_int_formula, e.string);
+                    let string = int_cell.get();
+                    if !string.is_pointer_value() {
+                        set_error("int needs a string".to_string());
+                        int_cell.set(BasicValueEnum::IntValue(context.i32_type().const_int(0, false)));
+                        return;
+                    }
+                    let location = runtime::location(&source_name, e.pos);
+                    int_cell.set(BasicValueEnum::IntValue(runtime::build_str_to_int(context, &module, &builder, function, string.into_pointer_value(), &location)));
+                },
+                Expr::Input(e) => {
+                    let value = match e.kind {
+                        Kind::Str => BasicValueEnum::PointerValue(runtime::build_read_line(context, &module, &builder, function)),
+                        Kind::Char => BasicValueEnum::IntValue(runtime::build_read_char(context, &module, &builder)),
+                        Kind::Int => {
+                            let location = runtime::location(&source_name, e.pos);
+                            BasicValueEnum::IntValue(runtime::build_read_int(context, &module, &builder, function, &location))
+                        },
+                        _ => builder.build_load(module.get_global(runtime::EOF_FLAG).unwrap().as_pointer_value(), "eof"),
+                    };
+                    int_cell.set(value);
+                },
+                Expr::Pop(e) => {
+                    match lookup_vec(&e.vector.name()) {
+                        None => int_cell.set(BasicValueEnum::IntValue(context.i32_type().const_int(0, false))),
+                        Some(header) => {
+                            let location = runtime::location(&source_name, e.pos);
+                            int_cell.set(BasicValueEnum::IntValue(runtime::build_vec_pop(context, &module, &builder, header, &location)));
+                        }
+                    }
+                },
+                Expr::Ident(_) | Expr::Field(_) => {
+                    let name = expr.name();
+                    let ptr = match var_table_cell.borrow().get(&name) {
                         None => {
-                            println!("error: {} not found", e.name());
-                            std::process::exit(1);
+                            set_error(missing(&expr));
+                            int_cell.set(BasicValueEnum::IntValue(context.i32_type().const_int(0, false)));
+                            return;
                         },
-                        Some(v) => v
+                        Some(v) => *v
                     };
-                    int_cell.set(builder.build_load(*ptr, &e.name()));
+                    match var_kind_cell.borrow().get(&name) {
+                        // 文字列は {ptr, len} へのポインタのまま使う
+                        Some(Kind::Str) => int_cell.set(BasicValueEnum::PointerValue(ptr)),
+                        Some(Kind::Struct) | Some(Kind::Enum) => {
+                            set_error(format!("{} cannot be used as a value here", name));
+                            int_cell.set(BasicValueEnum::IntValue(context.i32_type().const_int(0, false)));
+                        },
+                        _ => int_cell.set(builder.build_load(ptr, &name)),
+                    }
+                },
+                Expr::Str(e) => {
+                    int_cell.set(BasicValueEnum::PointerValue(runtime::build_str_literal(context, &builder, function, &e.eval())));
                 },
                 Expr::BinaryOp(e) => {
                     (eval_int_formula.f)(&eval_int_formula, e.left_expr);
-                    let left = int_cell.get().into_int_value();
+                    let left = int_cell.get();
                     (eval_int_formula.f)(&eval_int_formula, e.right_expr);
-                    let right = int_cell.get().into_int_value();
-                    let mut ret_int_val:IntValue = context.i32_type().const_int(0, false);
-                    match e.kind {
-                        Kind::Plus => {
-                            ret_int_val = builder.build_int_add(left, right, "");
+                    let right = int_cell.get();
+                    match (left, right, e.kind) {
+                        (BasicValueEnum::PointerValue(l), BasicValueEnum::PointerValue(r), Kind::Plus) => {
+                            int_cell.set(BasicValueEnum::PointerValue(runtime::build_str_concat(context, &module, &builder, function, l, r)));
+                            return;
                         },
-                        Kind::Minus => {
-                            ret_int_val = builder.build_int_sub(left, right, "");
+                        (BasicValueEnum::PointerValue(_), BasicValueEnum::PointerValue(_), kind) => {
+                            set_error(format!("unsupported operator {:?} for strings", kind));
+                            return;
                         },
-                        Kind::Multi => {
-                            ret_int_val = builder.build_int_mul(left, right, "");
+                        (BasicValueEnum::PointerValue(_), _, _) | (_, BasicValueEnum::PointerValue(_), _) => {
+                            set_error("cannot mix strings and numbers; use str() or int()".to_string());
+                            int_cell.set(BasicValueEnum::IntValue(context.i32_type().const_int(0, false)));
+                            return;
                         },
-                        Kind::Divi => {
-                            ret_int_val = builder.build_int_unsigned_div(left, right, "");
-                        }
-                        _ => { std::process::exit(1); }
+                        _ => {}
+                    }
+                    let left = left.into_int_value();
+                    let right = right.into_int_value();
+                    let mut ret_int_val:IntValue = context.i32_type().const_int(0, false);
+                    match e.kind {
+                        Kind::Plus | Kind::Minus | Kind::Multi | Kind::Divi => {
+                            // あふれは -O0 のときだけ調べる
+                            let location = runtime::location(&source_name, e.pos);
+                            ret_int_val = runtime::build_arith(context, &module, &builder, e.kind, left, right, config.opt_level == 0, &location);
+                        },
+                        _ => { set_error(format!("unsupported operator {:?}", e.kind)); }
                     }
                     
                     int_cell.set(BasicValueEnum::IntValue(ret_int_val));
                 },
                 Expr::Equal(e) => {
+                    let kinds = (kind_of(&e.left_expr, &var_kind_cell.borrow()), kind_of(&e.right_expr, &var_kind_cell.borrow()));
+                    if kinds.0 == Kind::Struct || kinds.1 == Kind::Struct {
+                        set_error("cannot compare structs; compare their fields".to_string());
+                        int_cell.set(BasicValueEnum::IntValue(context.bool_type().const_int(0, false)));
+                        return;
+                    }
+                    if kinds.0 == Kind::Enum || kinds.1 == Kind::Enum {
+                        set_error("cannot compare enums; use match".to_string());
+                        int_cell.set(BasicValueEnum::IntValue(context.bool_type().const_int(0, false)));
+                        return;
+                    }
                     (eval_int_formula.f)(&eval_int_formula, e.left_expr);
-                    let left = int_cell.get().into_int_value();
+                    let left = int_cell.get();
                     (eval_int_formula.f)(&eval_int_formula, e.right_expr);
-                    let right = int_cell.get().into_int_value();
+                    let right = int_cell.get();
+                    match (left, right) {
+                        (BasicValueEnum::PointerValue(l), BasicValueEnum::PointerValue(r)) => {
+                            int_cell.set(BasicValueEnum::IntValue(runtime::build_str_eq(context, &module, &builder, l, r)));
+                            return;
+                        },
+                        (BasicValueEnum::PointerValue(_), _) | (_, BasicValueEnum::PointerValue(_)) => {
+                            set_error("cannot compare a string with a number".to_string());
+                            int_cell.set(BasicValueEnum::IntValue(context.bool_type().const_int(0, false)));
+                            return;
+                        },
+                        _ => {}
+                    }
+                    let left = left.into_int_value();
+                    let right = right.into_int_value();
                     let success = builder.build_int_compare(
                         IntPredicate::EQ,
                         left,
@@ -108,8 +389,8 @@ pub fn generate(ast: &Vec<Expr>) {
                 return BasicValueEnum::IntValue(context.i32_type().const_int(c.eval() as u64, false));
             },
             _ => {
-                println!("error: char");
-                std::process::exit(1);
+                set_error("char expected".to_string());
+                BasicValueEnum::IntValue(context.i32_type().const_int(0, false))
             }
         }
     };
@@ -138,17 +419,246 @@ pub fn generate(ast: &Vec<Expr>) {
 
         pointer_value
     };
-    let emit_printf_call = |string: String, name: &str|{
-        let pointer_value = emit_global_string( string, name);
-        let func = module.get_function("puts");
-        builder.build_call(func.unwrap(), &[pointer_value.into()], "");
-
+    // 要素をi32にそろえて計算する
+    let eval_list = |list: &Vec<Expr>| -> Vec<IntValue> {
+        let mut values = Vec::new();
+        for item in list.iter() {
+            (eval_int_formula.f)(&eval_int_formula, item.clone());
+            let mut val = int_cell.get().into_int_value();
+            if val.get_type().get_bit_width() < 32 {
+                val = builder.build_int_z_extend(val, i32_type, "");
+            }
+            values.push(val);
+        }
+        values
+    };
+    let build_array = |array: &Array, name: &str| -> PointerValue {
+        let ptr = builder.build_alloca(i32_type.array_type(array.len as u32), name);
+        // 値を書かない要素は0にする
+        if array.list.len() < array.len {
+            let size = size_type.const_int(array.len as u64 * 4, false);
+            let memset = module.get_function("memset").unwrap();
+            builder.build_call(memset, &[builder.build_pointer_cast(ptr, i8_ptr_type, "").into(), i32_type.const_int(0, false).into(), size.into()], "");
+        }
+        for (i, val) in eval_list(&array.list).into_iter().enumerate() {
+            let slot = unsafe { builder.build_gep(ptr, &[i32_type.const_int(0, false), i32_type.const_int(i as u64, false)], "") };
+            builder.build_store(slot, val);
+        }
+        ptr
+    };
+    // [1, 2, 3] の形で出力する
+    let print_array = |ptr: PointerValue, len: u32| {
+        let printf = module.get_function("printf").unwrap();
+        let open = builder.build_global_string_ptr("[", "").as_pointer_value();
+        builder.build_call(printf, &[open.into()], "");
+        for i in 0..len {
+            let slot = unsafe { builder.build_gep(ptr, &[i32_type.const_int(0, false), i32_type.const_int(i as u64, false)], "") };
+            let val = builder.build_load(slot, "");
+            let fmt = builder.build_global_string_ptr(if i == 0 { "%d" } else { ", %d" }, "").as_pointer_value();
+            builder.build_call(printf, &[fmt.into(), val], "");
+        }
+        let close = builder.build_global_string_ptr("]", "").as_pointer_value();
+        builder.build_call(printf, &[close.into()], "");
+    };
+    // structに入れる値を先に全部計算してから書き込むので,
+    // `p = Point { x: p.y, y: p.x };` でも前の値を読める
+    type Stores<'c> = Vec<(String, BasicValueEnum<'c>)>;
+    struct Struct_Values<'s, 'c> { f: &'s dyn Fn(&Struct_Values<'s, 'c>, &str, &str, &Expr, &mut Stores<'c>) }
+    let struct_values = Struct_Values {
+        f: &|struct_values, path, type_name, expr, out| {
+            match expr {
+                Expr::StructLit(lit) => {
+                    if lit.name != type_name {
+                        set_error(format!("expected {}, found {}", type_name, lit.name));
+                        return;
+                    }
+                    let registry = types_cell.borrow();
+                    let values = match registry.check_literal(lit) {
+                        Err(err) => { set_error(err); return; },
+                        Ok(v) => v,
+                    };
+                    let def = registry.get(type_name).unwrap().clone();
+                    for ((field, field_type), val) in def.fields.iter().zip(values) {
+                        let field_path = format!("{}.{}", path, field);
+                        let kind = types::kind_of_type(field_type);
+                        if kind == Kind::Struct {
+                            (struct_values.f)(struct_values, &field_path, field_type, val, out);
+                            continue;
+                        }
+                        (eval_int_formula.f)(&eval_int_formula, val.clone());
+                        match (kind, int_cell.get()) {
+                            // {ptr, len} を読んでおく. バッファは共有する
+                            (Kind::Str, BasicValueEnum::PointerValue(p)) => out.push((field_path, builder.build_load(p, ""))),
+                            (Kind::Int, BasicValueEnum::IntValue(mut v)) | (Kind::Char, BasicValueEnum::IntValue(mut v)) => {
+                                if v.get_type().get_bit_width() < 32 {
+                                    v = builder.build_int_z_extend(v, i32_type, "");
+                                }
+                                out.push((field_path, BasicValueEnum::IntValue(v)));
+                            },
+                            _ => set_error(format!("expected {} for field {}.{}", field_type, lit.name, field)),
+                        }
+                    }
+                },
+                Expr::EnumLit(lit) => {
+                    if lit.name != type_name {
+                        set_error(format!("expected {}, found {}", type_name, lit.name));
+                        return;
+                    }
+                    let (tag, types) = match types_cell.borrow().variant(&lit.name, &lit.variant) {
+                        Err(err) => { set_error(err); return; },
+                        Ok((tag, types)) => (tag, types.clone()),
+                    };
+                    if types.len() != lit.args.len() {
+                        set_error(format!("{}.{} takes {} value(s) but {} were given ({}:{})", lit.name, lit.variant, types.len(), lit.args.len(), lit.pos.line, lit.pos.col));
+                        return;
+                    }
+                    out.push((format!("{}.%tag", path), BasicValueEnum::IntValue(i32_type.const_int(tag as u64, false))));
+                    for (j, (value_type, arg)) in types.iter().zip(lit.args.iter()).enumerate() {
+                        let value_path = format!("{}.{}.{}", path, lit.variant, j);
+                        let kind = types::kind_of_type(value_type);
+                        if kind == Kind::Struct {
+                            (struct_values.f)(struct_values, &value_path, value_type, arg, out);
+                            continue;
+                        }
+                        (eval_int_formula.f)(&eval_int_formula, arg.clone());
+                        match (kind, int_cell.get()) {
+                            (Kind::Str, BasicValueEnum::PointerValue(p)) => out.push((value_path, builder.build_load(p, ""))),
+                            (Kind::Int, BasicValueEnum::IntValue(mut v)) | (Kind::Char, BasicValueEnum::IntValue(mut v)) => {
+                                if v.get_type().get_bit_width() < 32 {
+                                    v = builder.build_int_z_extend(v, i32_type, "");
+                                }
+                                out.push((value_path, BasicValueEnum::IntValue(v)));
+                            },
+                            _ => set_error(format!("expected {} for {}.{}", value_type, lit.name, lit.variant)),
+                        }
+                    }
+                },
+                // 同じ型の変数やフィールドならまるごとコピーする
+                Expr::Ident(_) | Expr::Field(_) => {
+                    let name = expr.name();
+                    let source = type_name_cell.borrow().get(&name).cloned();
+                    match source {
+                        Some(s) if s == type_name => {
+                            let ptr = var_table_cell.borrow()[&name];
+                            out.push((path.to_string(), builder.build_load(ptr, "")));
+                        },
+                        Some(s) => set_error(format!("expected {}, found {}", type_name, s)),
+                        None if var_table_cell.borrow().contains_key(&name) => set_error(format!("expected {}, found {}", type_name, name)),
+                        None => set_error(missing(expr)),
+                    }
+                },
+                _ => set_error(format!("expected a {} value", type_name)),
+            }
+        }
+    };
+    // nameはstructの変数かフィールド. 型の違う変数ならstructの変数として作り直す
+    let assign_struct = |name: &str, type_name: &str, expr: &Expr| {
+        let mut values = Vec::new();
+        (struct_values.f)(&struct_values, name, type_name, expr, &mut values);
+        let current = type_name_cell.borrow().get(name).cloned();
+        if current.as_deref() != Some(type_name) {
+            declare_struct(name, type_name);
+        }
+        for (path, value) in values.into_iter() {
+            let ptr = var_table_cell.borrow().get(&path).copied();
+            if let Some(ptr) = ptr {
+                builder.build_store(ptr, value);
+            }
+        }
     };
-    let print_int = |ptr: PointerValue| {
-        let str_ptr = emit_global_string("%d".to_string(), "");
-        let ptr2 = builder.build_load(ptr, "");
-        let func = module.get_function("printf");
-        builder.build_call(func.unwrap(), &[str_ptr.into(), ptr2.into()], "");
+    // labelに続けて値を1つ出す. structやenumの中の文字列とcharには引用符を付ける
+    let print_inner = |label: &str, ptr: PointerValue<'ctx>, kind: Kind| {
+        let printf = module.get_function("printf").unwrap();
+        match kind {
+            Kind::Str => {
+                let fmt = builder.build_global_string_ptr(&format!("{}\"%.*s\"", label), "").as_pointer_value();
+                let len = runtime::build_str_len(&builder, ptr);
+                let data = runtime::build_str_ptr(&builder, ptr);
+                builder.build_call(printf, &[fmt.into(), len.into(), data.into()], "");
+            },
+            kind => {
+                let conversion = if kind == Kind::Char { "'%c'" } else { "%d" };
+                let fmt = builder.build_global_string_ptr(&format!("{}{}", label, conversion), "").as_pointer_value();
+                builder.build_call(printf, &[fmt.into(), builder.build_load(ptr, "")], "");
+            },
+        }
+    };
+    // Point { x: 1, name: "a" } の形で出力する. 改行は呼ぶ側で出す
+    struct Print_Struct<'s> { f: &'s dyn Fn(&Print_Struct, &str) }
+    let print_struct = Print_Struct {
+        f: &|print_struct, name| {
+            let printf = module.get_function("printf").unwrap();
+            let type_name = type_name_cell.borrow()[name].clone();
+            let def = match types_cell.borrow().get(&type_name) {
+                Err(err) => { set_error(err); return; },
+                Ok(d) => d.clone(),
+            };
+            for (i, (field, field_type)) in def.fields.iter().enumerate() {
+                let label = if i == 0 { format!("{} {{ {}: ", type_name, field) } else { format!(", {}: ", field) };
+                let path = format!("{}.{}", name, field);
+                let ptr = var_table_cell.borrow()[&path];
+                match types::kind_of_type(field_type) {
+                    Kind::Struct => {
+                        let text = builder.build_global_string_ptr(&label, "").as_pointer_value();
+                        builder.build_call(printf, &[text.into()], "");
+                        (print_struct.f)(print_struct, &path);
+                    },
+                    kind => print_inner(&label, ptr, kind),
+                }
+            }
+            let close = builder.build_global_string_ptr(" }", "").as_pointer_value();
+            builder.build_call(printf, &[close.into()], "");
+        }
+    };
+    // Circle(3) や Empty の形で出力する. tagで分岐してvariantごとに出す
+    let print_enum = |name: &str| {
+        let printf = module.get_function("printf").unwrap();
+        let type_name = type_name_cell.borrow()[name].clone();
+        let def = match types_cell.borrow().get_enum(&type_name) {
+            Err(err) => { set_error(err); return; },
+            Ok(d) => d.clone(),
+        };
+        let tag = builder.build_load(var_table_cell.borrow()[&format!("{}.%tag", name)], "").into_int_value();
+        let current = builder.get_insert_block().unwrap();
+        let done = context.insert_basic_block_after(current, "");
+        let mut blocks: Vec<BasicBlock> = def.variants.iter().map(|_| context.insert_basic_block_after(current, "")).collect();
+        blocks.reverse();
+        let cases: Vec<(IntValue, BasicBlock)> = blocks.iter().enumerate().map(|(v, b)| (i32_type.const_int(v as u64, false), *b)).collect();
+        builder.build_switch(tag, done, &cases);
+        for ((variant, types), block) in def.variants.iter().zip(blocks) {
+            builder.position_at_end(block);
+            for (j, value_type) in types.iter().enumerate() {
+                let label = if j == 0 { format!("{}(", variant) } else { ", ".to_string() };
+                let path = format!("{}.{}.{}", name, variant, j);
+                let ptr = var_table_cell.borrow()[&path];
+                match types::kind_of_type(value_type) {
+                    Kind::Struct => {
+                        let text = builder.build_global_string_ptr(&label, "").as_pointer_value();
+                        builder.build_call(printf, &[text.into()], "");
+                        (print_struct.f)(&print_struct, &path);
+                    },
+                    kind => print_inner(&label, ptr, kind),
+                }
+            }
+            let close = if types.is_empty() { variant.clone() } else { ")".to_string() };
+            let text = builder.build_global_string_ptr(&close, "").as_pointer_value();
+            builder.build_call(printf, &[text.into()], "");
+            builder.build_unconditional_branch(done);
+        }
+        builder.position_at_end(done);
+    };
+
+    // ifの条件をi1にする. 畳み込まれた `true` や `if x {` のような数もそのまま条件にできる
+    let condition_value = |condition: &Expr| -> IntValue<'ctx> {
+        (eval_int_formula.f)(&eval_int_formula, condition.clone());
+        match int_cell.get() {
+            BasicValueEnum::IntValue(v) if v.get_type().get_bit_width() == 1 => v,
+            BasicValueEnum::IntValue(v) => builder.build_int_compare(IntPredicate::NE, v, v.get_type().const_int(0, false), ""),
+            _ => {
+                set_error(format!("condition must be a number or a comparison: {}", condition));
+                context.bool_type().const_int(0, false)
+            },
+        }
     };
 
     /* 
@@ -166,7 +676,7 @@ pub fn generate(ast: &Vec<Expr>) {
 
                 }
                 _ => {
-                    let last = function.get_last_basic_block().unwrap();
+                    let last = after_block_cell.get().unwrap();
                     let vec = branches.borrow_mut().clone();
                     for branch in vec.into_iter() {
                         builder.position_at_end(branch);
@@ -178,26 +688,174 @@ pub fn generate(ast: &Vec<Expr>) {
                 }
             }
         }
+        if let (Some(d), Some(pos)) = (&debug_info, expr.pos()) {
+            d.set_location(&builder, pos);
+        }
         match expr {
         Expr::Assign(e) => {
+            if let Expr::Index(target) = &e.left_expr {
+                (eval_int_formula.f)(&eval_int_formula, e.right_expr.clone());
+                let value = int_cell.get();
+                (eval_int_formula.f)(&eval_int_formula, target.index.clone());
+                if let Some(slot) = element_ptr(&target.array.name(), int_cell.get().into_int_value(), target.pos) {
+                    builder.build_store(slot, value);
+                }
+                return;
+            }
+            // フィールドはstructの中にあるので, 新しく作らずにそこへ書き込む
+            if let Expr::Field(_) = &e.left_expr {
+                let left = e.left_expr.name();
+                let ptr = match var_table_cell.borrow().get(&left) {
+                    None => { set_error(missing(&e.left_expr)); return; },
+                    Some(p) => *p,
+                };
+                let kind = var_kind_cell.borrow().get(&left).copied();
+                if kind == Some(Kind::Enum) {
+                    set_error(format!("cannot assign to {}", left));
+                    return;
+                }
+                if kind == Some(Kind::Struct) {
+                    let type_name = type_name_cell.borrow()[&left].clone();
+                    assign_struct(&left, &type_name, &e.right_expr);
+                    return;
+                }
+                (eval_int_formula.f)(&eval_int_formula, e.right_expr.clone());
+                match (kind, int_cell.get()) {
+                    (Some(Kind::Str), BasicValueEnum::PointerValue(p)) => runtime::build_str_copy(&builder, p, ptr),
+                    (Some(Kind::Str), _) => set_error(format!("expected str for {}", left)),
+                    (_, BasicValueEnum::IntValue(mut v)) => {
+                        if v.get_type().get_bit_width() < 32 {
+                            v = builder.build_int_z_extend(v, i32_type, "");
+                        }
+                        builder.build_store(ptr, v);
+                    },
+                    _ => set_error(format!("expected int for {}", left)),
+                }
+                return;
+            }
             let left = e.left_expr.clone().name();
+            let kind = kind_of(&e.right_expr, &var_kind_cell.borrow());
             match e.right_expr.clone() {
-                Expr::Int(_) | Expr::BinaryOp(_)=> {
+                _ if kind == Kind::Str => {
+                    (eval_int_formula.f)(&eval_int_formula, e.right_expr.clone());
+                    let value = match int_cell.get() {
+                        BasicValueEnum::PointerValue(p) => p,
+                        _ => return,
+                    };
+                    // 文字列の変数は {ptr, len} を持つ. バッファは共有する
+                    let old = match var_kind_cell.borrow().get(&left) {
+                        Some(Kind::Str) => var_table_cell.borrow().get(&left).copied(),
+                        _ => None,
+                    };
+                    let header = match old {
+                        Some(h) => h,
+                        None => {
+                            let header = runtime::build_str_header(context, function, &left);
+                            if let Some(d) = &debug_info {
+                                d.declare_variable(&left, d.str_type(), header, e.pos, builder.get_insert_block().unwrap());
+                            }
+                            header
+                        },
+                    };
+                    runtime::build_str_copy(&builder, value, header);
+                    var_kind_cell.borrow_mut().insert(left.clone(), Kind::Str);
+                    var_table_cell.borrow_mut().insert(left, header);
+                },
+                _ if kind == Kind::Struct || kind == Kind::Enum => {
+                    let type_name = match &e.right_expr {
+                        Expr::StructLit(l) => Some(l.name.clone()),
+                        Expr::EnumLit(l) => Some(l.name.clone()),
+                        other => type_name_cell.borrow().get(&other.name()).cloned(),
+                    };
+                    match type_name {
+                        None => set_error(missing(&e.right_expr)),
+                        Some(t) => {
+                            // 型が変わると新しい場所に置き直すので, そのときだけ宣言する
+                            let fresh = type_name_cell.borrow().get(&left) != Some(&t);
+                            assign_struct(&left, &t, &e.right_expr);
+                            let ptr = var_table_cell.borrow().get(&left).copied();
+                            if let (Some(d), Some(ptr), true) = (&debug_info, ptr, fresh) {
+                                d.declare_variable(&left, d.struct_type(&types_cell.borrow(), &t), ptr, e.pos, builder.get_insert_block().unwrap());
+                            }
+                        },
+                    }
+                },
+                Expr::Index(_) | Expr::Ident(_) | Expr::Field(_) | Expr::Input(_) if kind == Kind::Char => {
+                    (eval_int_formula.f)(&eval_int_formula, e.right_expr.clone());
+                    let ptr = declare_int(left.clone(), int_cell.get().into_int_value());
+                    if let Some(d) = &debug_info {
+                        d.declare_variable(&left, d.int_type(Kind::Char), ptr, e.pos, builder.get_insert_block().unwrap());
+                    }
+                    var_kind_cell.borrow_mut().insert(left.clone(), Kind::Char);
+                    var_table_cell.borrow_mut().insert(left, ptr);
+                },
+                Expr::Ident(_) if kind == Kind::Array => {
+                    let source = e.right_expr.name();
+                    let len = array_len_cell.borrow()[&source];
+                    let from = var_table_cell.borrow()[&source];
+                    let ptr = builder.build_alloca(i32_type.array_type(len), &left);
+                    builder.build_store(ptr, builder.build_load(from, ""));
+                    if let Some(d) = &debug_info {
+                        d.declare_variable(&left, d.array_type(len), ptr, e.pos, builder.get_insert_block().unwrap());
+                    }
+                    var_kind_cell.borrow_mut().insert(left.clone(), Kind::Array);
+                    array_len_cell.borrow_mut().insert(left.clone(), len);
+                    var_table_cell.borrow_mut().insert(left, ptr);
+                },
+                // vecのバッファは1つの変数だけが持つ
+                Expr::Ident(_) if kind == Kind::Vector => {
+                    set_error(format!("a vec cannot be copied: {} = {}", left, e.right_expr.name()));
+                },
+                Expr::Int(_) | Expr::BinaryOp(_) | Expr::Index(_) | Expr::Len(_) | Expr::Pop(_) | Expr::ToInt(_) |
+                Expr::Ident(_) | Expr::Field(_) | Expr::Input(_) => {
                     (eval_int_formula.f)(&eval_int_formula, e.right_expr.clone());
                     let right = int_cell.get();
                     let ptr = declare_int(left.clone(), right.into_int_value());
+                    if let Some(d) = &debug_info {
+                        d.declare_variable(&left, d.int_type(Kind::Int), ptr, e.pos, builder.get_insert_block().unwrap());
+                    }
+                    var_kind_cell.borrow_mut().insert(left.clone(), Kind::Int);
                     var_table_cell.borrow_mut().insert(left, ptr);
                 },
                 Expr::Char(_) => {
                     let right = eval_char(&e.right_expr.clone());
                     let ptr = declare_int(left.clone(), right.into_int_value());
+                    if let Some(d) = &debug_info {
+                        d.declare_variable(&left, d.int_type(Kind::Char), ptr, e.pos, builder.get_insert_block().unwrap());
+                    }
+                    var_kind_cell.borrow_mut().insert(left.clone(), Kind::Char);
                     var_table_cell.borrow_mut().insert(left, ptr);
                 },
-                Expr::Str(s) => {
-                    let string: &str = &s.eval();
-                    let ptr = emit_global_string(string.to_string(), &left);
+                Expr::Array(a) => {
+                    let ptr = build_array(a.as_ref(), &left);
+                    if let Some(d) = &debug_info {
+                        d.declare_variable(&left, d.array_type(a.len as u32), ptr, e.pos, builder.get_insert_block().unwrap());
+                    }
+                    var_kind_cell.borrow_mut().insert(left.clone(), Kind::Array);
+                    array_len_cell.borrow_mut().insert(left.clone(), a.len as u32);
                     var_table_cell.borrow_mut().insert(left, ptr);
                 },
+                Expr::Vector(v) => {
+                    let values = eval_list(&v.list);
+                    // 同じ名前のvecがあればヘッダを使い回し, 前のバッファはbuild_vec_initでfreeする
+                    let old = match var_kind_cell.borrow().get(&left) {
+                        Some(Kind::Vector) => var_table_cell.borrow().get(&left).copied(),
+                        _ => None,
+                    };
+                    let header = match old {
+                        Some(h) => h,
+                        None => {
+                            let header = runtime::build_vec_header(context, function, &left);
+                            if let Some(d) = &debug_info {
+                                d.declare_variable(&left, d.vec_type(), header, e.pos, builder.get_insert_block().unwrap());
+                            }
+                            header
+                        },
+                    };
+                    runtime::build_vec_init(context, &module, &builder, header, &values);
+                    var_kind_cell.borrow_mut().insert(left.clone(), Kind::Vector);
+                    var_table_cell.borrow_mut().insert(left, header);
+                },
                 _ => {
 
                 }
@@ -205,48 +863,102 @@ pub fn generate(ast: &Vec<Expr>) {
             
         },
         Expr::Print(e) => {
-            let val = e.val.clone();
-            match val {
-                Expr::Ident(_) => {
-                    let var_table = var_table_cell.borrow();
-                    let ptr = match var_table.get(&e.val.name()) {
-                        None => {println!("error {} not found", e.val.name()); std::process::exit(1); },
-                        Some(p) => *p
-                    };
-                    print_int(ptr);
-                }
-                Expr::Int(_) | Expr::BinaryOp(_) | Expr::Equal(_)=> {
-                    (eval_int_formula.f)(&eval_int_formula, val);
-                    let int_val = int_cell.get().into_int_value();
-                    let s = int_val.print_to_string().to_string();
-                    let vec:Vec<&str> = s.split_whitespace().collect();
-                    let s_to_print:&str = &(vec[1].to_string());
-                    emit_printf_call(s_to_print.to_string(), "int");
-                },
-                Expr::Char(_) => {
-                    let char_val = eval_char(&val);
-                    let s = get_int_from_int_val(char_val.into_int_value());
-                    let fun = module.get_function("putchar");
-                    builder.build_call(fun.unwrap(), &[context.i32_type().const_int(s as u64, false).into()], "putchar");
-                    builder.build_call(fun.unwrap(), &[context.i32_type().const_int('\n' as u64, false).into()], "putchar");
-                },
-                Expr::Str(s) => {
-                    // emit_global_stringでstringを出力する
-                    let string:&str = &s.eval();
-                    emit_printf_call(string.to_string(), "");
+            // 続けて出せる値は1つのprintfにまとめる. struct, enum, 配列, vecは自分の出し方で出す
+            let printf = module.get_function("printf").unwrap();
+            let mut fmt = String::new();
+            let mut args: Vec<BasicValueEnum> = Vec::new();
+            let flush = |fmt: &mut String, args: &mut Vec<BasicValueEnum<'ctx>>| {
+                if fmt.is_empty() {
+                    return;
                 }
-                _ => {
-                
+                let mut call_args = vec![builder.build_global_string_ptr(fmt, "").as_pointer_value().into()];
+                call_args.append(args);
+                builder.build_call(printf, &call_args, "");
+                fmt.clear();
+            };
+            for piece in e.list.iter() {
+                let kind = kind_of(piece, &var_kind_cell.borrow());
+                match piece {
+                    Expr::Str(s) => fmt.push_str(&s.eval().replace('%', "%%")),
+                    Expr::Int(i) => fmt.push_str(&i.eval().to_string()),
+                    Expr::Bool(b) => fmt.push_str(&b.eval().to_string()),
+                    Expr::Char(c) => fmt.push_str(&c.eval().to_string().replace('%', "%%")),
+                    Expr::StructLit(l) => {
+                        // 名前にならない変数に入れてから出力する
+                        flush(&mut fmt, &mut args);
+                        assign_struct("%struct", &l.name, piece);
+                        if type_name_cell.borrow().contains_key("%struct") {
+                            (print_struct.f)(&print_struct, "%struct");
+                        }
+                    },
+                    Expr::EnumLit(l) => {
+                        flush(&mut fmt, &mut args);
+                        assign_struct("%enum", &l.name, piece);
+                        if type_name_cell.borrow().contains_key("%enum") {
+                            print_enum("%enum");
+                        }
+                    },
+                    Expr::Array(a) => {
+                        flush(&mut fmt, &mut args);
+                        let ptr = build_array(a.as_ref(), "");
+                        print_array(ptr, a.len as u32);
+                    },
+                    Expr::Vector(v) => {
+                        flush(&mut fmt, &mut args);
+                        let values = eval_list(&v.list);
+                        let header = runtime::build_vec_header(context, function, "");
+                        runtime::build_vec_init(context, &module, &builder, header, &values);
+                        runtime::build_vec_print(context, &module, &builder, header);
+                        runtime::build_vec_free(context, &module, &builder, header);
+                    },
+                    Expr::Ident(_) | Expr::Field(_) if kind == Kind::Struct || kind == Kind::Enum || kind == Kind::Array || kind == Kind::Vector => {
+                        flush(&mut fmt, &mut args);
+                        let name = piece.name();
+                        let ptr = var_table_cell.borrow()[&name];
+                        match kind {
+                            Kind::Struct => (print_struct.f)(&print_struct, &name),
+                            Kind::Enum => print_enum(&name),
+                            Kind::Array => print_array(ptr, array_len_cell.borrow()[&name]),
+                            _ => runtime::build_vec_print(context, &module, &builder, ptr),
+                        }
+                    },
+                    _ => {
+                        (eval_int_formula.f)(&eval_int_formula, piece.clone());
+                        match (kind, int_cell.get()) {
+                            (_, BasicValueEnum::PointerValue(p)) => {
+                                fmt.push_str("%.*s");
+                                args.push(runtime::build_str_len(&builder, p).into());
+                                args.push(runtime::build_str_ptr(&builder, p).into());
+                            },
+                            (_, BasicValueEnum::IntValue(v)) if v.get_type().get_bit_width() == 1 => {
+                                // 比較の結果はtrue/falseで出力する
+                                let true_ptr = emit_global_string("true".to_string(), "");
+                                let false_ptr = emit_global_string("false".to_string(), "");
+                                fmt.push_str("%s");
+                                args.push(builder.build_select(v, true_ptr, false_ptr, ""));
+                            },
+                            (_, BasicValueEnum::IntValue(mut v)) => {
+                                fmt.push_str(if kind == Kind::Char { "%c" } else { "%d" });
+                                if v.get_type().get_bit_width() < 32 {
+                                    v = builder.build_int_z_extend(v, i32_type, "");
+                                }
+                                args.push(v.into());
+                            },
+                            _ => set_error(format!("cannot print {}", piece.name())),
+                        }
+                    },
                 }
             }
+            if e.newline {
+                fmt.push('\n');
+            }
+            flush(&mut fmt, &mut args);
         },
         Expr::If(i) => {
             after_if.set(false);
             let then_block = context.append_basic_block(function, "");
             let after_block = context.append_basic_block(function, "");
-            let condition = i.clone().condition;
-            (ast_to_llvm.f)(&ast_to_llvm, &condition);
-            let c_int = int_cell.get().into_int_value();
+            let c_int = condition_value(&i.condition);
             builder.build_conditional_branch(
                 c_int,
                 then_block,
@@ -257,17 +969,17 @@ pub fn generate(ast: &Vec<Expr>) {
             for ex in list {
                 (ast_to_llvm.f)(&ast_to_llvm, &ex);
             }
+            // 範囲チェックなどでthen_blockの後にブロックが増えていることがある
+            branches.borrow_mut().push(builder.get_insert_block().unwrap());
             builder.position_at_end(after_block);
-            branches.borrow_mut().push(then_block);
+            after_block_cell.set(Some(after_block));
             after_if.set(true);
         },
         Expr::ElseIf(e) => {
             after_if.set(false);
             let then_block = context.append_basic_block(function, "");
             let after_block = context.append_basic_block(function, "");
-            let condition = e.clone().condition;
-            (ast_to_llvm.f)(&ast_to_llvm, &condition);
-            let c_int = int_cell.get().into_int_value();
+            let c_int = condition_value(&e.condition);
             builder.build_conditional_branch(
                 c_int,
                 then_block,
@@ -278,8 +990,10 @@ pub fn generate(ast: &Vec<Expr>) {
             for ex in list {
                 (ast_to_llvm.f)(&ast_to_llvm, &ex);
             }
+            // 範囲チェックなどでthen_blockの後にブロックが増えていることがある
+            branches.borrow_mut().push(builder.get_insert_block().unwrap());
             builder.position_at_end(after_block);
-            branches.borrow_mut().push(then_block);
+            after_block_cell.set(Some(after_block));
             after_if.set(true);
         },
         Expr::Else(e) => {
@@ -294,10 +1008,190 @@ pub fn generate(ast: &Vec<Expr>) {
             for ex in list {
                 (ast_to_llvm.f)(&ast_to_llvm, &ex);
             }
+            // 範囲チェックなどでthen_blockの後にブロックが増えていることがある
+            branches.borrow_mut().push(builder.get_insert_block().unwrap());
             builder.position_at_end(after_block);
-            branches.borrow_mut().push(then_block);
+            after_block_cell.set(Some(after_block));
             after_if.set(true);
         }
+        Expr::Struct(def) => {
+            if let Err(err) = types_cell.borrow_mut().define(def) {
+                set_error(err);
+            }
+        },
+        Expr::Enum(def) => {
+            if let Err(err) = types_cell.borrow_mut().define_enum(def) {
+                set_error(err);
+                return;
+            }
+            // 値を持たないvariantは `Shape.Empty` という変数にしておく
+            for (tag, (variant, types)) in def.variants.iter().enumerate() {
+                if types.is_empty() {
+                    let name = format!("{}.{}", def.name, variant);
+                    declare_struct(&name, &def.name);
+                    builder.build_store(var_table_cell.borrow()[&format!("{}.%tag", name)], i32_type.const_int(tag as u64, false));
+                }
+            }
+        },
+        Expr::Return(e) => {
+            (eval_int_formula.f)(&eval_int_formula, e.val.clone());
+            let code = match int_cell.get() {
+                BasicValueEnum::IntValue(v) if v.get_type().get_bit_width() < 32 => builder.build_int_z_extend(v, i32_type, ""),
+                BasicValueEnum::IntValue(v) => v,
+                _ => {
+                    set_error(format!("{} needs an int", if e.exit { "exit" } else { "return" }));
+                    return;
+                },
+            };
+            builder.build_return(Some(&code));
+            // 後ろの文は到達しないブロックに置く
+            let current = builder.get_insert_block().unwrap();
+            builder.position_at_end(context.insert_basic_block_after(current, ""));
+        },
+        Expr::Panic(e) => {
+            (eval_int_formula.f)(&eval_int_formula, e.message.clone());
+            let message = match int_cell.get() {
+                BasicValueEnum::PointerValue(p) => p,
+                _ => {
+                    set_error("panic needs a str message".to_string());
+                    return;
+                },
+            };
+            let location = runtime::location(&source_name, e.pos);
+            let args = [runtime::build_str_len(&builder, message).into(), runtime::build_str_ptr(&builder, message).into()];
+            runtime::build_panic(context, &module, &builder, Some(&location), "%.*s", &args);
+            let current = builder.get_insert_block().unwrap();
+            builder.position_at_end(context.insert_basic_block_after(current, ""));
+        },
+        Expr::Assert(e) => {
+            let location = runtime::location(&source_name, e.pos);
+            let message = format!("assertion failed: {}", e.text.replace('%', "%%"));
+            // 値を出すために == の左右は1回だけ評価する
+            let equal = match &e.condition {
+                Expr::Equal(eq) => eq,
+                condition => {
+                    (eval_int_formula.f)(&eval_int_formula, condition.clone());
+                    let ok = match int_cell.get() {
+                        BasicValueEnum::IntValue(v) if v.get_type().get_bit_width() == 1 => v,
+                        BasicValueEnum::IntValue(v) => builder.build_int_compare(IntPredicate::NE, v, v.get_type().const_int(0, false), ""),
+                        _ => {
+                            set_error(format!("assert needs a bool condition: {}", e.text));
+                            return;
+                        },
+                    };
+                    runtime::build_check(context, &module, &builder, ok, Some(&location), &message, &[]);
+                    return;
+                },
+            };
+            let mut args: Vec<BasicValueEnum> = Vec::new();
+            let mut values = Vec::new();
+            for side in [&equal.left_expr, &equal.right_expr] {
+                let kind = kind_of(side, &var_kind_cell.borrow());
+                if kind == Kind::Struct || kind == Kind::Enum || kind == Kind::Array || kind == Kind::Vector {
+                    set_error(format!("assert_eq can only compare numbers, chars and strings: {}", e.text));
+                    return;
+                }
+                (eval_int_formula.f)(&eval_int_formula, side.clone());
+                values.push((kind, int_cell.get()));
+            }
+            let mut shown = Vec::new();
+            for (kind, value) in values.iter() {
+                match *value {
+                    BasicValueEnum::PointerValue(p) => {
+                        shown.push("\"%.*s\"");
+                        args.push(runtime::build_str_len(&builder, p).into());
+                        args.push(runtime::build_str_ptr(&builder, p).into());
+                    },
+                    BasicValueEnum::IntValue(v) if v.get_type().get_bit_width() == 1 => {
+                        shown.push("%s");
+                        let true_ptr = emit_global_string("true".to_string(), "");
+                        let false_ptr = emit_global_string("false".to_string(), "");
+                        args.push(builder.build_select(v, true_ptr, false_ptr, ""));
+                    },
+                    BasicValueEnum::IntValue(mut v) => {
+                        shown.push(if *kind == Kind::Char { "'%c'" } else { "%d" });
+                        if v.get_type().get_bit_width() < 32 {
+                            v = builder.build_int_z_extend(v, i32_type, "");
+                        }
+                        args.push(v.into());
+                    },
+                    _ => {
+                        set_error(format!("cannot compare {}", e.text));
+                        return;
+                    },
+                }
+            }
+            let ok = match (values[0].1, values[1].1) {
+                (BasicValueEnum::PointerValue(l), BasicValueEnum::PointerValue(r)) => runtime::build_str_eq(context, &module, &builder, l, r),
+                (BasicValueEnum::IntValue(l), BasicValueEnum::IntValue(r)) if l.get_type() == r.get_type() => builder.build_int_compare(IntPredicate::EQ, l, r, ""),
+                _ => {
+                    set_error(format!("cannot compare values of different types: {}", e.text));
+                    return;
+                },
+            };
+            let message = format!("{}\n  left: {}\n right: {}", message, shown[0], shown[1]);
+            runtime::build_check(context, &module, &builder, ok, Some(&location), &message, &args);
+        },
+        Expr::Match(m) => {
+            let enum_name = match types_cell.borrow().check_match(m) {
+                Err(err) => { set_error(err); return; },
+                Ok(n) => n,
+            };
+            // 変数でなければ一度変数に入れる. 腕の中のmatchと混ざらないように名前を変える
+            let name = match &m.value {
+                Expr::Ident(_) | Expr::Field(_) => m.value.name(),
+                other => {
+                    let temp = format!("%match{}", match_count.get());
+                    match_count.set(match_count.get() + 1);
+                    assign_struct(&temp, &enum_name, other);
+                    temp
+                },
+            };
+            if type_name_cell.borrow().get(&name) != Some(&enum_name) {
+                set_error(format!("match on {} needs a {} value", name, enum_name));
+                return;
+            }
+            let def = types_cell.borrow().get_enum(&enum_name).unwrap().clone();
+            let tag = builder.build_load(var_table_cell.borrow()[&format!("{}.%tag", name)], "").into_int_value();
+            let current = builder.get_insert_block().unwrap();
+            let done = context.insert_basic_block_after(current, "");
+            let mut blocks: Vec<BasicBlock> = m.arms.iter().map(|_| context.insert_basic_block_after(current, "")).collect();
+            blocks.reverse();
+            let mut default = done;
+            let mut cases = Vec::new();
+            for (arm, block) in m.arms.iter().zip(blocks.iter()) {
+                match &arm.pattern {
+                    None => default = *block,
+                    Some((_, variant)) => {
+                        let tag = def.variants.iter().position(|(v, _)| v == variant).unwrap();
+                        cases.push((i32_type.const_int(tag as u64, false), *block));
+                    },
+                }
+            }
+            builder.build_switch(tag, default, &cases);
+            for (arm, block) in m.arms.iter().zip(blocks) {
+                builder.position_at_end(block);
+                // 腕で使う名前には値をコピーしておく
+                if let Some((_, variant)) = &arm.pattern {
+                    for (j, binding) in arm.bindings.iter().enumerate() {
+                        let value = Expr::Ident(Box::new(Ident::new(format!("{}.{}.{}", name, variant, j), Kind::Nulkind)));
+                        let target = Expr::Ident(Box::new(Ident::new(binding.clone(), Kind::Nulkind)));
+                        (ast_to_llvm.f)(&ast_to_llvm, &Expr::Assign(Box::new(Assign::new(target, value, arm.pos))));
+                    }
+                }
+                for ex in arm.list.iter() {
+                    (ast_to_llvm.f)(&ast_to_llvm, ex);
+                }
+                builder.build_unconditional_branch(done);
+            }
+            builder.position_at_end(done);
+        },
+        Expr::Push(p) => {
+            if let Some(header) = lookup_vec(&p.vector.name()) {
+                let value = eval_list(&vec![p.val.clone()]);
+                runtime::build_vec_push(context, &module, &builder, header, value[0]);
+            }
+        },
         Expr::Equal(_) => {
             let ast_ = expr.clone();
             (eval_int_formula.f)(&eval_int_formula, ast_);
@@ -312,9 +1206,43 @@ pub fn generate(ast: &Vec<Expr>) {
         ast_iter += 1;
     }
 
+    // vecのバッファはmainの最後でfreeする
+    let mut vectors: Vec<String> = var_kind_cell.borrow().iter().filter(|(_, k)| **k == Kind::Vector).map(|(n, _)| n.clone()).collect();
+    vectors.sort();
+    for name in vectors.iter() {
+        runtime::build_vec_free(context, &module, &builder, var_table_cell.borrow()[name]);
+    }
     builder.build_return(Some(&i32_type.const_int(0, false)));
+    if let Some(d) = &debug_info {
+        d.finalize();
+    }
 
-    let _result = module.print_to_file("main.ll");
+    match error.into_inner() {
+        Some(message) => Err(message),
+        None => Ok(module),
+    }
+}
+
+// 式の値の型. 文字列とcharは出力の仕方が違うので, 実行前に決めておく
+#[cfg(feature = "llvm")]
+fn kind_of(expr: &Expr, vars: &HashMap<String, Kind>) -> Kind {
+    match expr {
+        Expr::Char(_) => Kind::Char,
+        Expr::Str(_) | Expr::Substr(_) | Expr::ToStr(_) => Kind::Str,
+        Expr::Ident(_) | Expr::Field(_) => vars.get(&expr.name()).copied().unwrap_or(Kind::Int),
+        Expr::StructLit(_) => Kind::Struct,
+        Expr::EnumLit(_) => Kind::Enum,
+        Expr::Input(e) if e.kind == Kind::Str || e.kind == Kind::Char => e.kind,
+        Expr::BinaryOp(e) => {
+            if kind_of(&e.left_expr, vars) == Kind::Str { Kind::Str } else { Kind::Int }
+        },
+        Expr::Index(e) => {
+            if kind_of(&e.array, vars) == Kind::Str { Kind::Char } else { Kind::Int }
+        },
+        Expr::Array(_) => Kind::Array,
+        Expr::Vector(_) => Kind::Vector,
+        _ => Kind::Int,
+    }
 }
 
 fn type_of<T>(_: T) -> String{
@@ -322,6 +1250,9 @@ fn type_of<T>(_: T) -> String{
     return a.to_string();
 }
 
+// 実行時のエラー(panic)で止まったときの終了コード
+pub const PANIC_EXIT_CODE: i32 = 101;
+
 #[allow(dead_code)]
 #[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub enum Kind {
@@ -329,7 +1260,39 @@ pub enum Kind {
     Less, LessEq, Greater, GreaterEq, SngQ, DblQ, Assign, Semicolon,
     If, Else, Print, Ident, Int,
     Str, Letter, Digit, Nulkind, EofTkn, Others, Endlist,
-    Lbrace, Rbrace, Char, Nyaan, Addasgn, Mnuasgn, Multiasgn, Divasgn
+    Lbrace, Rbrace, Char, Nyaan, Addasgn, Mnuasgn, Multiasgn, Divasgn, Import,
+    Lbracket, Rbracket, Comma, Colon, Let, Array, Vector, Struct,
+    Enum, Match, Arrow, Println, Return
+}
+
+// Pos: ソース上の位置 (1始まり)
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug, Default)]
+pub struct Pos {
+    pub line: u32,
+    pub col: u32,
+}
+impl Pos {
+    pub fn new(line: u32, col: u32) -> Pos {
+        Pos { line, col }
+    }
+
+    // エラーメッセージの最後の `(行:列)`. nyaan lspで診断の場所にする
+    pub fn from_error(message: &str) -> Option<Pos> {
+        let start = message.rfind('(')?;
+        let (line, col) = message[start + 1..].strip_suffix(')')?.split_once(':')?;
+        match (line.parse(), col.parse()) {
+            (Ok(line), Ok(col)) => Some(Pos::new(line, col)),
+            _ => None,
+        }
+    }
+}
+
+// 位置の書かれていないエラーメッセージに `(行:列)` を付ける
+pub fn error_at(message: String, pos: Pos) -> String {
+    if Pos::from_error(&message).is_some() {
+        return message;
+    }
+    format!("{} ({}:{})", message, pos.line, pos.col)
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -338,10 +1301,33 @@ pub struct Token {
     pub chr: char,
     pub kind: Kind,
     pub val: i32,
+    pub pos: Pos,
 }
 impl Token {
     pub fn new() -> Token {
-        Token{text: "".to_string(), chr:' ', kind: Kind::Others, val: 0}
+        Token{text: "".to_string(), chr:' ', kind: Kind::Others, val: 0, pos: Pos::default()}
+    }
+}
+impl Default for Token {
+    fn default() -> Token {
+        Token::new()
+    }
+}
+
+// Trivia: 構文木に残らないコメントと空行. nyaan fmtで書き戻すときに使う
+#[derive(PartialEq, Clone, Debug)]
+pub enum Trivia {
+    // `//` より後ろの行末まで. trailingは同じ行でトークンの後ろに書いてあるとき
+    Comment { text: String, pos: Pos, trailing: bool },
+    // 続けて何行空いていても1つにする
+    Blank(Pos),
+}
+impl Trivia {
+    pub fn pos(&self) -> Pos {
+        match self {
+            Trivia::Comment { pos, .. } => *pos,
+            Trivia::Blank(pos) => *pos,
+        }
     }
 }
 
@@ -371,15 +1357,37 @@ pub enum Expr {
     Print(Box<Print>),
     Char(Char),
     Str(Str),
+    Bool(Bool),
     Equal(Box<Equal>),
     If(Box<If>),
     Else(Box<Else>),
     ElseIf(Box<ElseIf>),
+    Import(Box<Import>),
+    Array(Box<Array>),
+    Index(Box<Index>),
+    Len(Box<Len>),
+    Vector(Box<Vector>),
+    Push(Box<Push>),
+    Pop(Box<Pop>),
+    Substr(Box<Substr>),
+    ToStr(Box<ToStr>),
+    ToInt(Box<ToInt>),
+    Input(Box<Input>),
+    Return(Box<Return>),
+    Panic(Box<Panic>),
+    Assert(Box<Assert>),
+    Test(Box<Test>),
+    Struct(Box<StructDef>),
+    StructLit(Box<StructLit>),
+    Field(Box<Field>),
+    Enum(Box<EnumDef>),
+    EnumLit(Box<EnumLit>),
+    Match(Box<Match>),
     Nope
 }
 impl Expr {
 
-    fn name(&self) -> String {
+    pub fn name(&self) -> String {
         match self 
         {
             Expr::Ident(e) => e.name(),
@@ -389,14 +1397,58 @@ impl Expr {
             Expr::Print(e) => type_of(e),
             Expr::Char(e) => type_of(e),
             Expr::Str(e) => type_of(e),
+            Expr::Bool(e) => type_of(e),
             Expr::Equal(e) => type_of(e),
             Expr::If(e) => type_of(e),
             Expr::Else(e) => type_of(e),
             Expr::ElseIf(e) => type_of(e),
+            Expr::Import(e) => type_of(e),
+            Expr::Array(e) => type_of(e),
+            Expr::Index(e) => type_of(e),
+            Expr::Len(e) => type_of(e),
+            Expr::Vector(e) => type_of(e),
+            Expr::Push(e) => type_of(e),
+            Expr::Pop(e) => type_of(e),
+            Expr::Substr(e) => type_of(e),
+            Expr::ToStr(e) => type_of(e),
+            Expr::ToInt(e) => type_of(e),
+            Expr::Input(e) => type_of(e),
+            Expr::Return(e) => type_of(e),
+            Expr::Panic(e) => type_of(e),
+            Expr::Assert(e) => type_of(e),
+            Expr::Test(e) => type_of(e),
+            Expr::Struct(e) => type_of(e),
+            Expr::StructLit(e) => type_of(e),
+            Expr::Field(e) => e.name(),
+            Expr::Enum(e) => type_of(e),
+            Expr::EnumLit(e) => type_of(e),
+            Expr::Match(e) => type_of(e),
             Expr::Nope => "None".to_string()
             
         }
     }
+
+    // 文の位置. 式にはない
+    #[cfg(feature = "llvm")]
+    pub fn pos(&self) -> Option<Pos> {
+        match self {
+            Expr::Assign(e) => Some(e.pos),
+            Expr::Print(e) => Some(e.pos),
+            Expr::If(e) => Some(e.pos),
+            Expr::ElseIf(e) => Some(e.pos),
+            Expr::Else(e) => Some(e.pos),
+            Expr::Import(e) => Some(e.pos),
+            Expr::Push(e) => Some(e.pos),
+            Expr::Struct(e) => Some(e.pos),
+            Expr::Enum(e) => Some(e.pos),
+            Expr::Match(e) => Some(e.pos),
+            Expr::Return(e) => Some(e.pos),
+            Expr::Panic(e) => Some(e.pos),
+            Expr::Assert(e) => Some(e.pos),
+            Expr::Test(e) => Some(e.pos),
+            _ => None,
+        }
+    }
 }
 
 
@@ -407,7 +1459,7 @@ impl Int {
     pub fn new(val: i32) -> Int {
         Int(val)
     }
-    fn eval(&self) -> i32 {
+    pub fn eval(&self) -> i32 {
         self.0
     }
 }
@@ -419,7 +1471,7 @@ impl Char {
     pub fn new(val: char) -> Char {
         Char(val)
     }
-    fn eval(&self) -> char {
+    pub fn eval(&self) -> char {
         self.0
     }
 }
@@ -431,21 +1483,35 @@ impl Str {
     pub fn new(val: String) -> Str {
         Str(val)
     }
-    fn eval(&self)-> String {
+    pub fn eval(&self)-> String {
         self.0.clone()
     }
 }
 
+// Bool: 定数畳み込みでできる真偽値
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Bool(bool);
+impl Bool {
+    pub fn new(val: bool) -> Bool {
+        Bool(val)
+    }
+    pub fn eval(&self) -> bool {
+        self.0
+    }
+}
+
 // BinaryOp: �l�����Z + - * /
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BinaryOp {
     pub kind: Kind,
     pub left_expr: Expr,
-    pub right_expr: Expr
+    pub right_expr: Expr,
+    // 演算子の位置. 0での割り算などのエラーに使う
+    pub pos: Pos,
 }
 impl BinaryOp {
-    pub fn new(kind: Kind, left_expr:Expr, right_expr: Expr)-> BinaryOp {
-        BinaryOp {kind, left_expr, right_expr}
+    pub fn new(kind: Kind, left_expr:Expr, right_expr: Expr, pos: Pos)-> BinaryOp {
+        BinaryOp {kind, left_expr, right_expr, pos}
     }
 }
 
@@ -469,22 +1535,25 @@ impl Ident {
 pub struct Assign {
     pub left_expr: Expr,
     pub right_expr: Expr,
+    pub pos: Pos,
 }
 impl Assign {
-    pub fn new(left_expr:Expr, right_expr: Expr) -> Assign {
-        Assign {left_expr, right_expr}
+    pub fn new(left_expr:Expr, right_expr: Expr, pos: Pos) -> Assign {
+        Assign {left_expr, right_expr, pos}
     }
 }
 
 
-// Print: print
+// Print: print(...) と println(...). 書式はparserで文字列と値の並びにしてある
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Print {
-    pub val: Expr,
+    pub list: Vec<Expr>,
+    pub newline: bool,
+    pub pos: Pos,
 }
 impl Print {
-    pub fn new(val: Expr) -> Print {
-        Print { val }
+    pub fn new(list: Vec<Expr>, newline: bool, pos: Pos) -> Print {
+        Print { list, newline, pos }
     }
 }
 
@@ -504,20 +1573,22 @@ impl Equal {
 pub struct If {
     pub condition: Expr,
     pub list: Vec<Expr>,
+    pub pos: Pos,
 }
 impl If {
-    pub fn new(condition: Expr, list: Vec<Expr>) -> If {
-        If { condition, list }
+    pub fn new(condition: Expr, list: Vec<Expr>, pos: Pos) -> If {
+        If { condition, list, pos }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Else {
     pub list: Vec<Expr>,
+    pub pos: Pos,
 }
 impl Else {
-    pub fn new(list: Vec<Expr>) -> Else {
-        Else { list }
+    pub fn new(list: Vec<Expr>, pos: Pos) -> Else {
+        Else { list, pos }
     }
 }
 
@@ -525,11 +1596,298 @@ impl Else {
 pub struct ElseIf {
     pub condition: Expr,
     pub list: Vec<Expr>,
+    pub pos: Pos,
 }
 impl ElseIf {
-    pub fn new(condition: Expr, list: Vec<Expr>) -> ElseIf {
-        ElseIf { condition, list }
+    pub fn new(condition: Expr, list: Vec<Expr>, pos: Pos) -> ElseIf {
+        ElseIf { condition, list, pos }
+    }
+}
+
+// Import: import "path.ny";
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Import {
+    pub path: String,
+    pub pos: Pos,
+}
+impl Import {
+    pub fn new(path: String, pos: Pos) -> Import {
+        Import { path, pos }
+    }
+}
+
+// Array: [1, 2, 3] や let a: [int; 10]; で作る固定長の配列.
+// lenはlistより長くてもよく, 残りは0で埋める
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Array {
+    pub list: Vec<Expr>,
+    pub len: usize,
+}
+impl Array {
+    pub fn new(list: Vec<Expr>) -> Array {
+        let len = list.len();
+        Array { list, len }
+    }
+    pub fn zeroed(len: usize) -> Array {
+        Array { list: Vec::new(), len }
+    }
+}
+
+// Index: a[i]. 範囲外なら実行時に止まる
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Index {
+    pub array: Expr,
+    pub index: Expr,
+    pub pos: Pos,
+}
+impl Index {
+    pub fn new(array: Expr, index: Expr, pos: Pos) -> Index {
+        Index { array, index, pos }
+    }
+}
+
+// Len: len(a)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Len {
+    pub array: Expr,
+}
+impl Len {
+    pub fn new(array: Expr) -> Len {
+        Len { array }
+    }
+}
+
+// Vector: vec(1, 2, 3). 要素はヒープに置かれ, pushで伸びる
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Vector {
+    pub list: Vec<Expr>,
+}
+impl Vector {
+    pub fn new(list: Vec<Expr>) -> Vector {
+        Vector { list }
+    }
+}
+
+// Push: push(v, x);
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Push {
+    pub vector: Expr,
+    pub val: Expr,
+    pub pos: Pos,
+}
+impl Push {
+    pub fn new(vector: Expr, val: Expr, pos: Pos) -> Push {
+        Push { vector, val, pos }
+    }
+}
+
+// Pop: pop(v). 空なら実行時に止まる
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pop {
+    pub vector: Expr,
+    pub pos: Pos,
+}
+impl Pop {
+    pub fn new(vector: Expr, pos: Pos) -> Pop {
+        Pop { vector, pos }
+    }
+}
+
+// Substr: substr(s, start, end). バイト単位で [start, end) を取り出す
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Substr {
+    pub string: Expr,
+    pub start: Expr,
+    pub end: Expr,
+    pub pos: Pos,
+}
+impl Substr {
+    pub fn new(string: Expr, start: Expr, end: Expr, pos: Pos) -> Substr {
+        Substr { string, start, end, pos }
+    }
+}
+
+// ToStr: str(x). intを10進数の文字列にする
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ToStr {
+    pub val: Expr,
+}
+impl ToStr {
+    pub fn new(val: Expr) -> ToStr {
+        ToStr { val }
+    }
+}
+
+// ToInt: int(s). 数として読めなければ実行時に止まる
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ToInt {
+    pub string: Expr,
+    pub pos: Pos,
+}
+impl ToInt {
+    pub fn new(string: Expr, pos: Pos) -> ToInt {
+        ToInt { string, pos }
+    }
+}
+
+// Input: 標準入力から読む. kindはinput()がStr, read_int()がInt, read_char()がChar, eof()がEofTkn.
+// 入力が終わっていれば "", 0, '\0' を返し, そのあとのeof()が1になる
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Input {
+    pub kind: Kind,
+    pub pos: Pos,
+}
+impl Input {
+    pub fn new(kind: Kind, pos: Pos) -> Input {
+        Input { kind, pos }
+    }
+}
+
+// Return: return 式; と exit(式);. 関数はないので, どちらもmainから戻ってプログラムを終える
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Return {
+    pub val: Expr,
+    pub exit: bool,
+    pub pos: Pos,
+}
+impl Return {
+    pub fn new(val: Expr, exit: bool, pos: Pos) -> Return {
+        Return { val, exit, pos }
+    }
+}
+
+// Panic: panic(文字列);. 位置とメッセージを出してプログラムを止める
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Panic {
+    pub message: Expr,
+    pub pos: Pos,
+}
+impl Panic {
+    pub fn new(message: Expr, pos: Pos) -> Panic {
+        Panic { message, pos }
+    }
+}
+
+// Assert: assert(条件); と assert_eq(a, b);. assert_eqは a == b の条件にする.
+// textは失敗したときに出す条件のソース
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Assert {
+    pub condition: Expr,
+    pub text: String,
+    pub pos: Pos,
+}
+impl Assert {
+    pub fn new(condition: Expr, text: String, pos: Pos) -> Assert {
+        Assert { condition, text, pos }
     }
 }
 
+// Test: test "名前" { 文 }. nyaan testのときだけ実行する
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Test {
+    pub name: String,
+    pub list: Vec<Expr>,
+    pub pos: Pos,
+}
+impl Test {
+    pub fn new(name: String, list: Vec<Expr>, pos: Pos) -> Test {
+        Test { name, list, pos }
+    }
+}
 
+// StructDef: struct Point { x: int, y: int }. フィールドは (名前, 型名)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<(String, String)>,
+    pub pos: Pos,
+}
+impl StructDef {
+    pub fn new(name: String, fields: Vec<(String, String)>, pos: Pos) -> StructDef {
+        StructDef { name, fields, pos }
+    }
+}
+
+// StructLit: Point { x: 1, y: 2 }. フィールドは書いた順のまま持つ
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StructLit {
+    pub name: String,
+    pub fields: Vec<(String, Expr)>,
+    pub pos: Pos,
+}
+impl StructLit {
+    pub fn new(name: String, fields: Vec<(String, Expr)>, pos: Pos) -> StructLit {
+        StructLit { name, fields, pos }
+    }
+}
+
+// Field: p.x
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Field {
+    pub object: Expr,
+    pub name: String,
+    pub pos: Pos,
+}
+impl Field {
+    pub fn new(object: Expr, name: String, pos: Pos) -> Field {
+        Field { object, name, pos }
+    }
+    // `p.inner.x` のようにたどった道筋を変数名のように使う
+    fn name(&self) -> String {
+        format!("{}.{}", self.object.name(), self.name)
+    }
+}
+
+// EnumDef: enum Shape { Circle(int), Rect(int, int), Empty }. 値を持たないvariantもある
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<(String, Vec<String>)>,
+    pub pos: Pos,
+}
+impl EnumDef {
+    pub fn new(name: String, variants: Vec<(String, Vec<String>)>, pos: Pos) -> EnumDef {
+        EnumDef { name, variants, pos }
+    }
+}
+
+// EnumLit: Shape.Circle(3). 値を持たないvariantは `Shape.Empty` と書く
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnumLit {
+    pub name: String,
+    pub variant: String,
+    pub args: Vec<Expr>,
+    pub pos: Pos,
+}
+impl EnumLit {
+    pub fn new(name: String, variant: String, args: Vec<Expr>, pos: Pos) -> EnumLit {
+        EnumLit { name, variant, args, pos }
+    }
+}
+
+// Match: match s { Shape.Circle(r) => { ... } _ => { ... } }
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Match {
+    pub value: Expr,
+    pub arms: Vec<Arm>,
+    pub pos: Pos,
+}
+impl Match {
+    pub fn new(value: Expr, arms: Vec<Arm>, pos: Pos) -> Match {
+        Match { value, arms, pos }
+    }
+}
+
+// Arm: matchの1つの腕. patternが (enumの名前, variant) で, Noneなら `_`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Arm {
+    pub pattern: Option<(String, String)>,
+    pub bindings: Vec<String>,
+    pub list: Vec<Expr>,
+    pub pos: Pos,
+}
+impl Arm {
+    pub fn new(pattern: Option<(String, String)>, bindings: Vec<String>, list: Vec<Expr>, pos: Pos) -> Arm {
+        Arm { pattern, bindings, list, pos }
+    }
+}
@@ -0,0 +1,65 @@
+use super::emit::Config;
+use inkwell::module::Module;
+use inkwell::passes::PassManager;
+
+// 最適化レベルごとに走らせるパス
+pub fn passes(config: &Config) -> Vec<&'static str> {
+    let mut list = Vec::new();
+    if config.opt_level == 0 {
+        return list;
+    }
+    list.extend(&["mem2reg", "instcombine", "simplifycfg"]);
+    if config.opt_level >= 2 {
+        list.extend(&["early-cse", "reassociate", "sccp", "gvn", "instcombine", "dse", "adce", "simplifycfg"]);
+    }
+    if config.opt_level >= 3 && config.size_level == 0 {
+        list.extend(&["jump-threading", "licm", "loop-unroll", "instcombine", "simplifycfg"]);
+    }
+    if config.size_level > 0 {
+        list.extend(&["constmerge", "globaldce"]);
+    }
+    list
+}
+
+// 走らせたパスの名前を返す. 知らない名前のパスがあれば何もせずにエラーにする
+pub fn optimize(module: &Module, config: &Config) -> Result<Vec<&'static str>, String> {
+    let list = passes(config);
+    if list.is_empty() {
+        return Ok(list);
+    }
+    let pass_manager = PassManager::create(());
+    for name in list.iter() {
+        add_pass(&pass_manager, name)?;
+    }
+    pass_manager.run_on(module);
+    Ok(list)
+}
+
+fn add_pass(pass_manager: &PassManager<Module>, name: &str) -> Result<(), String> {
+    match name {
+        "mem2reg" => pass_manager.add_promote_memory_to_register_pass(),
+        "instcombine" => pass_manager.add_instruction_combining_pass(),
+        "simplifycfg" => pass_manager.add_cfg_simplification_pass(),
+        "early-cse" => pass_manager.add_early_cse_pass(),
+        "reassociate" => pass_manager.add_reassociate_pass(),
+        "sccp" => pass_manager.add_sccp_pass(),
+        "gvn" => pass_manager.add_gvn_pass(),
+        "dse" => pass_manager.add_dead_store_elimination_pass(),
+        "adce" => pass_manager.add_aggressive_dce_pass(),
+        "jump-threading" => pass_manager.add_jump_threading_pass(),
+        "licm" => pass_manager.add_licm_pass(),
+        "loop-unroll" => pass_manager.add_loop_unroll_pass(),
+        "constmerge" => pass_manager.add_constant_merge_pass(),
+        "globaldce" => pass_manager.add_global_dce_pass(),
+        _ => return Err(format!("unknown pass `{}`", name)),
+    }
+    Ok(())
+}
+
+// generateが壊れたIRを作っていないか確かめる
+pub fn verify(module: &Module) -> Result<(), String> {
+    match module.verify() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
@@ -1,213 +1,645 @@
 use std::cell::Cell;
 use super::*;
 
-pub fn token_to_expr(token_list: &Vec<Token>) -> Vec<Expr> {
+pub fn token_to_expr(token_list: &[Token]) -> Result<Vec<Expr>, String> {
+    check_depth(token_list)?;
     let mut expr_list:Vec<Expr> = Vec::new();
     let mut stack: Vec<Expr> = Vec::new();
     let mut i = 0;
     while i < token_list.len() - 1 {
-        expr_list.push(statement(&mut i, token_list, &mut stack));
+        let token = &token_list[i];
+        if token.kind == Kind::Rbrace {
+            return Err(format!("unmatched }} ({}:{})", token.pos.line, token.pos.col));
+        }
+        // 位置のないエラーは読めなくなったトークンの位置にする
+        match statement(&mut i, token_list, &mut stack) {
+            Ok(expr) => expr_list.push(expr),
+            Err(err) => return Err(error_at(err, token_list[i.min(token_list.len() - 1)].pos)),
+        }
     }
     expr_list.push(Expr::Nope);
-    expr_list
+    Ok(expr_list)
+}
+
+// 文ではなく式だけを読む (REPLで使う)
+pub fn parse_expression(token_list: &[Token]) -> Result<Expr, String> {
+    check_depth(token_list)?;
+    let mut stack: Vec<Expr> = Vec::new();
+    let mut i = 0;
+    let cell_token:Cell<&Token> = match token_list.first() {
+        None => return Err("expression is missing".to_string()),
+        Some(tkn) => Cell::new(tkn),
+    };
+    expression(&cell_token, &mut i, token_list, &mut stack)?;
+    if cell_token.get().kind == Kind::Equal {
+        expression(&cell_token, &mut i, token_list, &mut stack)?;
+    }
+    if cell_token.get().kind == Kind::Semicolon {
+        next_tkn(&cell_token, &mut i, token_list)?;
+    }
+    if cell_token.get().kind != Kind::Endlist {
+        return Err("unexpected token after expression".to_string());
+    }
+    match stack.pop() {
+        None => Err("expression is missing".to_string()),
+        Some(expr) => Ok(expr),
+    }
 }
 
+// 括弧やブロックの深さと1つの文の中の演算子の数の上限. 構文木の深さになるので,
+// これを超えるとパーサや後の処理の再帰でスタックが溢れる
+const MAX_DEPTH: usize = 256;
+
+// let a: [int; N]; の長さの上限. 配列はスタックに置くので大きすぎると確保できない
+const MAX_ARRAY_LEN: i32 = 1 << 20;
+
+fn check_depth(token_list: &[Token]) -> Result<(), String> {
+    let mut depth: usize = 0;
+    let mut ops = 0;
+    for token in token_list.iter() {
+        match token.kind {
+            Kind::Lparen | Kind::Lbracket | Kind::Lbrace => depth += 1,
+            Kind::Rparen | Kind::Rbracket | Kind::Rbrace => depth = depth.saturating_sub(1),
+            Kind::Plus | Kind::Minus | Kind::Multi | Kind::Divi | Kind::Equal => ops += 1,
+            _ => {},
+        }
+        if token.kind == Kind::Semicolon || token.kind == Kind::Lbrace || token.kind == Kind::Rbrace {
+            ops = 0;
+        }
+        if depth + ops > MAX_DEPTH {
+            return Err(format!("expression is nested too deeply ({}:{})", token.pos.line, token.pos.col));
+        }
+    }
+    Ok(())
+}
 
-fn statement(index: &mut usize, token_list: &Vec<Token>, stack: &mut Vec<Expr>) -> Expr {
+fn statement(index: &mut usize, token_list: &[Token], stack: &mut Vec<Expr>) -> Result<Expr, String> {
     let token:&Token = match token_list.get(*index) {
-        None => return Expr::Nope,
+        None => return Ok(Expr::Nope),
         Some(tkn) => tkn,
     };
     let cell_token:Cell<&Token> = Cell::new(token);
+    let pos = token.pos;
 
     match token.kind {
         Kind::Ident => { // todo 変数宣言の宣言(letなど)を認識
             let variable_name: String = token.text.clone();
             
-            next_tkn(&cell_token, index, token_list);
-            check_tkn(&cell_token, index, token_list, Kind::Assign, "= is missing".to_string(), true); // todo Addasgn などに対応させる
+            next_tkn(&cell_token, index, token_list)?;
+            // exit(式);
+            if variable_name == "exit" && cell_token.get().kind == Kind::Lparen {
+                next_tkn(&cell_token, index, token_list)?;
+                expression(&cell_token, index, token_list, stack)?;
+                let val = match stack.pop() {
+                    None => return Err("exit needs a status code".to_string()),
+                    Some(expr) => expr,
+                };
+                check_tkn(&cell_token, index, token_list, Kind::Rparen, ") is missing for exit".to_string(), true)?;
+                check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true)?;
+                return Ok(Expr::Return(Box::new(Return::new(val, true, pos))));
+            }
+            // panic(文字列);
+            if variable_name == "panic" && cell_token.get().kind == Kind::Lparen {
+                next_tkn(&cell_token, index, token_list)?;
+                expression(&cell_token, index, token_list, stack)?;
+                let message = match stack.pop() {
+                    None => return Err("panic needs a message".to_string()),
+                    Some(expr) => expr,
+                };
+                check_tkn(&cell_token, index, token_list, Kind::Rparen, ") is missing for panic".to_string(), true)?;
+                check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true)?;
+                return Ok(Expr::Panic(Box::new(Panic::new(message, pos))));
+            }
+            // test "名前" { 文 }
+            if variable_name == "test" && cell_token.get().kind == Kind::Str {
+                let name = cell_token.get().text.clone();
+                next_tkn(&cell_token, index, token_list)?;
+                check_tkn(&cell_token, index, token_list, Kind::Lbrace, format!("{{ is missing for test \"{}\"", name), true)?;
+                let mut list: Vec<Expr> = Vec::new();
+                loop {
+                    match cell_token.get().kind {
+                        Kind::Endlist => return Err(format!("}} is missing for test \"{}\"", name)),
+                        Kind::Rbrace => {
+                            // ifと同じく最後のNopeでif連鎖を閉じる
+                            next_tkn(&cell_token, index, token_list)?;
+                            list.push(Expr::Nope);
+                            break;
+                        },
+                        _ => {},
+                    }
+                    list.push(statement(index, token_list, stack)?);
+                    match token_list.get(*index) {
+                        None => return Err(format!("}} is missing for test \"{}\"", name)),
+                        Some(tkn) => cell_token.set(tkn),
+                    }
+                }
+                return Ok(Expr::Test(Box::new(Test::new(name, list, pos))));
+            }
+            // assert(条件); assert_eq(式, 式);
+            if (variable_name == "assert" || variable_name == "assert_eq") && cell_token.get().kind == Kind::Lparen {
+                next_tkn(&cell_token, index, token_list)?;
+                let start = *index;
+                expression(&cell_token, index, token_list, stack)?;
+                if cell_token.get().kind == Kind::Equal {
+                    expression(&cell_token, index, token_list, stack)?;
+                }
+                let left = match stack.pop() {
+                    None => return Err(format!("{} needs a condition", variable_name)),
+                    Some(expr) => expr,
+                };
+                let mut text = source_text(&token_list[start..*index]);
+                let mut condition = left;
+                if variable_name == "assert_eq" {
+                    check_tkn(&cell_token, index, token_list, Kind::Comma, ", is missing for assert_eq".to_string(), true)?;
+                    let middle = *index;
+                    expression(&cell_token, index, token_list, stack)?;
+                    let right = match stack.pop() {
+                        None => return Err("assert_eq needs two values".to_string()),
+                        Some(expr) => expr,
+                    };
+                    text = format!("{} == {}", text, source_text(&token_list[middle..*index]));
+                    condition = Expr::Equal(Box::new(Equal::new(condition, right)));
+                }
+                check_tkn(&cell_token, index, token_list, Kind::Rparen, format!(") is missing for {}", variable_name), true)?;
+                check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true)?;
+                return Ok(Expr::Assert(Box::new(Assert::new(condition, text, pos))));
+            }
+            // push(v, 式);
+            if variable_name == "push" && cell_token.get().kind == Kind::Lparen {
+                next_tkn(&cell_token, index, token_list)?;
+                expression(&cell_token, index, token_list, stack)?;
+                let vector = match stack.pop() {
+                    None => return Err("push needs a vec".to_string()),
+                    Some(expr) => expr,
+                };
+                check_tkn(&cell_token, index, token_list, Kind::Comma, ", is missing for push".to_string(), true)?;
+                expression(&cell_token, index, token_list, stack)?;
+                let val = match stack.pop() {
+                    None => return Err("push needs a value".to_string()),
+                    Some(expr) => expr,
+                };
+                check_tkn(&cell_token, index, token_list, Kind::Rparen, ") is missing for push".to_string(), true)?;
+                check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true)?;
+                return Ok(Expr::Push(Box::new(Push::new(vector, val, pos))));
+            }
+            // a[i] = 式;
+            if cell_token.get().kind == Kind::Lbracket {
+                next_tkn(&cell_token, index, token_list)?;
+                expression(&cell_token, index, token_list, stack)?;
+                let index_expr = match stack.pop() {
+                    None => return Err("index is missing".to_string()),
+                    Some(expr) => expr,
+                };
+                check_tkn(&cell_token, index, token_list, Kind::Rbracket, "] is missing".to_string(), true)?;
+                check_tkn(&cell_token, index, token_list, Kind::Assign, "= is missing".to_string(), true)?;
+                expression(&cell_token, index, token_list, stack)?;
+                let right_expr = match stack.pop() {
+                    None => return Err("stack pop failed: stack is empty".to_string()),
+                    Some(expr) => expr,
+                };
+                let array = ident_expr(&variable_name, Kind::Array, pos);
+                let target = Expr::Index(Box::new(Index::new(array, index_expr, pos)));
+                check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true)?;
+                return Ok(Expr::Assign(Box::new(Assign::new(target, right_expr, pos))));
+            }
+            check_tkn(&cell_token, index, token_list, Kind::Assign, "= is missing".to_string(), true)?; // todo Addasgn などに対応させる
 
-            expression(&cell_token, index, token_list, stack);
+            expression(&cell_token, index, token_list, stack)?;
             let right_expr = match stack.pop() {
-                None => {parse_error("stack pop failed: stack is empty".to_string()); return Expr::Nope; },
+                None => return Err("stack pop failed: stack is empty".to_string()),
                 Some(expr) => expr,
             };
             let kind: Kind = match right_expr {
-                Expr::Int(_) | Expr::BinaryOp(_) => {
+                Expr::Int(_) | Expr::BinaryOp(_) | Expr::Index(_) | Expr::Len(_) | Expr::Pop(_) | Expr::ToInt(_) => {
                     Kind::Int
                 },
+                Expr::Input(ref e) => {
+                    if e.kind == Kind::EofTkn { Kind::Int } else { e.kind }
+                },
+                Expr::Array(_) => {
+                    Kind::Array
+                },
+                Expr::Vector(_) => {
+                    Kind::Vector
+                },
                 Expr::Char(_) => {
                     Kind::Char
                 }
-                Expr::Str(_) => {
+                Expr::Str(_) | Expr::Substr(_) | Expr::ToStr(_) => {
                     Kind::Str
                 }
+                Expr::StructLit(_) => {
+                    Kind::Struct
+                }
+                Expr::EnumLit(_) => {
+                    Kind::Enum
+                }
+                // 変数やフィールドの型は実行するまでわからない
+                Expr::Ident(_) | Expr::Field(_) => {
+                    Kind::Nulkind
+                }
                 _ => {
-                    std::process::exit(1);
+                    return Err(format!("cannot assign this expression to {}", variable_name));
                 }
             };
-            let ident = Expr::Ident(Box::new(Ident::new(variable_name.clone(), kind)));
+            let ident = ident_expr(&variable_name, kind, pos);
             let assign: Expr = Expr::Assign(Box::new(Assign::new(
                     ident, 
                     right_expr.clone(),
+                    pos,
             )));
-            check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true);
-            return assign;
+            check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true)?;
+            return Ok(assign);
         },
-        Kind::Print => {
-            next_tkn(&cell_token, index, token_list);
-            check_tkn(&cell_token, index, token_list, Kind::Lparen, "( is missing for print function".to_string(), true);
-            expression(&cell_token, index, token_list, stack);
-            //expression(&cell_token, index, token_list, stack);
-            let print = Expr::Print(Box::new(Print::new(match stack.pop() {
-                None => {parse_error("print error; stack is empty".to_string()); return Expr::Nope; }, // todo error messageを変更
-                Some(expr) => expr
-            })));
-            check_tkn(&cell_token, index, token_list, Kind::Rparen, ") is missing for print function".to_string(), true);
-            check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(),true);
-            return print;
+        Kind::Print | Kind::Println => {
+            let newline = token.kind == Kind::Println;
+            next_tkn(&cell_token, index, token_list)?;
+            check_tkn(&cell_token, index, token_list, Kind::Lparen, "( is missing for print function".to_string(), true)?;
+            let args = arguments(&cell_token, index, token_list, stack, Kind::Rparen)?;
+            check_tkn(&cell_token, index, token_list, Kind::Rparen, ") is missing for print function".to_string(), true)?;
+            let print = Expr::Print(Box::new(Print::new(format_args(args, pos)?, newline, pos)));
+            check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(),true)?;
+            return Ok(print);
         },
         Kind::If => {
-            next_tkn(&cell_token, index, token_list);
-            expression(&cell_token, index, token_list, stack);
-            expression(&cell_token, index, token_list, stack); // Equal
-            next_tkn(&cell_token, index, token_list);
+            next_tkn(&cell_token, index, token_list)?;
+            expression(&cell_token, index, token_list, stack)?;
+            if cell_token.get().kind == Kind::Equal {
+                expression(&cell_token, index, token_list, stack)?;
+            }
+            check_tkn(&cell_token, index, token_list, Kind::Lbrace, "{ is missing for if statement".to_string(), true)?;
             let condition = match stack.pop() {
-                None => {println!("error at if: parsing condition failed"); std::process::exit(1); },
+                None => return Err("if: parsing condition failed".to_string()),
                 Some(e) => e
             };
 
-            //check_tkn(&cell_token, index, token_list, Kind::Lbrace, "{ is missing for if statement".to_string(), false);
             let mut flag = true;
             let mut list: Vec<Expr> =  Vec::new();
             while flag {
                 let token_kind = cell_token.get().kind;
                 match token_kind {
                     Kind::Rbrace => { flag = false; },
-                    Kind::Endlist => { println!("error: }} is missing for if statement"); std::process::exit(1); },
+                    Kind::Endlist => return Err("} is missing for if statement".to_string()),
                      _ => {},
                 };
-                let a = statement(index, token_list, stack);
+                let a = statement(index, token_list, stack)?;
                 if a == Expr::Nope {
                     flag = false;
                 }
                 list.push(a);
             }
-            println!("{:?}", cell_token.get());
-            let if_obj = If::new(condition, list);
-            return Expr::If(Box::new(if_obj));
+            let if_obj = If::new(condition, list, pos);
+            return Ok(Expr::If(Box::new(if_obj)));
         }, 
         Kind::Else => {
-            next_tkn(&cell_token, index, token_list);
+            next_tkn(&cell_token, index, token_list)?;
             if cell_token.get().kind == Kind::If {
-                next_tkn(&cell_token, index, token_list);
-                expression(&cell_token, index, token_list, stack);
-                expression(&cell_token, index, token_list, stack); // Equal
-                next_tkn(&cell_token, index, token_list);
+                next_tkn(&cell_token, index, token_list)?;
+                expression(&cell_token, index, token_list, stack)?;
+                if cell_token.get().kind == Kind::Equal {
+                    expression(&cell_token, index, token_list, stack)?;
+                }
+                check_tkn(&cell_token, index, token_list, Kind::Lbrace, "{ is missing for if statement".to_string(), true)?;
                 let condition = match stack.pop() {
-                    None => {println!("error at if: parsing condition failed"); std::process::exit(1); },
+                    None => return Err("if: parsing condition failed".to_string()),
                     Some(e) => e
                 };
 
-                //check_tkn(&cell_token, index, token_list, Kind::Lbrace, "{ is missing for if statement".to_string(), false);
                 let mut flag = true;
                 let mut list: Vec<Expr> =  Vec::new();
                 while flag {
                     let token_kind = cell_token.get().kind;
                     match token_kind {
                         Kind::Rbrace => { flag = false; },
-                        Kind::Endlist => { println!("error: }} is missing for if statement"); std::process::exit(1); },
+                        Kind::Endlist => return Err("} is missing for if statement".to_string()),
                          _ => {},
                     };
-                    let a = statement(index, token_list, stack);
+                    let a = statement(index, token_list, stack)?;
                     if a == Expr::Nope {
                         flag = false;
                     }
                     list.push(a);
                 }
-                println!("{:?}", cell_token.get());
-                let if_obj = ElseIf::new(condition, list);
-                return Expr::ElseIf(Box::new(if_obj));
+                let if_obj = ElseIf::new(condition, list, pos);
+                return Ok(Expr::ElseIf(Box::new(if_obj)));
             }
 
-            check_tkn(&cell_token, index, token_list, Kind::Lbrace, "{ is missing for if statement".to_string(), true);
+            check_tkn(&cell_token, index, token_list, Kind::Lbrace, "{ is missing for if statement".to_string(), true)?;
             let mut flag = true;
             let mut list: Vec<Expr> =  Vec::new();
             while flag {
                 let token_kind = cell_token.get().kind;
                 match token_kind {
                     Kind::Rbrace => { flag = false; },
-                    Kind::Endlist => { println!("error: }} is missing for if statement"); std::process::exit(1); },
+                    Kind::Endlist => return Err("} is missing for if statement".to_string()),
                      _ => {},
                 };
-                let a = statement(index, token_list, stack);
+                let a = statement(index, token_list, stack)?;
                 if a == Expr::Nope {
                     flag = false;
                 }
                 list.push(a);
             }
-            println!("{:?}", cell_token.get());
-            let else_obj = Else::new(list);
-            return Expr::Else(Box::new(else_obj));
+            let else_obj = Else::new(list, pos);
+            return Ok(Expr::Else(Box::new(else_obj)));
+        },
+        // let a: [int; 10]; は0で埋めた配列になる
+        Kind::Let => {
+            next_tkn(&cell_token, index, token_list)?;
+            let name = cell_token.get();
+            if name.kind != Kind::Ident {
+                return Err("variable name is missing after let".to_string());
+            }
+            let variable_name = name.text.clone();
+            next_tkn(&cell_token, index, token_list)?;
+            check_tkn(&cell_token, index, token_list, Kind::Colon, format!(": is missing after {}", variable_name), true)?;
+            check_tkn(&cell_token, index, token_list, Kind::Lbracket, "only array types like [int; 10] can be declared with let".to_string(), true)?;
+            if cell_token.get().text != "int" {
+                return Err("only [int; N] arrays are supported".to_string());
+            }
+            next_tkn(&cell_token, index, token_list)?;
+            check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing in array type".to_string(), true)?;
+            let size = cell_token.get();
+            if size.kind != Kind::Int {
+                return Err("array length must be an integer literal".to_string());
+            }
+            if size.val > MAX_ARRAY_LEN {
+                return Err(format!("array length {} is too large (the limit is {})", size.val, MAX_ARRAY_LEN));
+            }
+            let len = size.val as usize;
+            next_tkn(&cell_token, index, token_list)?;
+            check_tkn(&cell_token, index, token_list, Kind::Rbracket, "] is missing in array type".to_string(), true)?;
+            check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true)?;
+            let ident = Expr::Ident(Box::new(Ident::new(variable_name, Kind::Array)));
+            return Ok(Expr::Assign(Box::new(Assign::new(ident, Expr::Array(Box::new(Array::zeroed(len))), pos))));
+        },
+        // struct Point { x: int, y: int }
+        Kind::Struct => {
+            next_tkn(&cell_token, index, token_list)?;
+            let name = cell_token.get();
+            if name.kind != Kind::Ident || name.text.contains('.') {
+                return Err("struct name is missing".to_string());
+            }
+            let struct_name = name.text.clone();
+            next_tkn(&cell_token, index, token_list)?;
+            check_tkn(&cell_token, index, token_list, Kind::Lbrace, format!("{{ is missing for struct {}", struct_name), true)?;
+            let mut fields = Vec::new();
+            while cell_token.get().kind != Kind::Rbrace {
+                let field = cell_token.get();
+                if field.kind != Kind::Ident || field.text.contains('.') {
+                    return Err(format!("field name is missing in struct {}", struct_name));
+                }
+                next_tkn(&cell_token, index, token_list)?;
+                check_tkn(&cell_token, index, token_list, Kind::Colon, format!(": is missing after {}", field.text), true)?;
+                let type_name = cell_token.get();
+                if type_name.kind != Kind::Ident {
+                    return Err(format!("type is missing for field {}", field.text));
+                }
+                fields.push((field.text.clone(), type_name.text.clone()));
+                next_tkn(&cell_token, index, token_list)?;
+                match cell_token.get().kind {
+                    Kind::Comma => next_tkn(&cell_token, index, token_list)?,
+                    Kind::Rbrace => {},
+                    _ => return Err(format!("}} is missing for struct {}", struct_name)),
+                }
+            }
+            next_tkn(&cell_token, index, token_list)?;
+            return Ok(Expr::Struct(Box::new(StructDef::new(struct_name, fields, pos))));
+        },
+        // enum Shape { Circle(int), Rect(int, int), Empty }
+        Kind::Enum => {
+            next_tkn(&cell_token, index, token_list)?;
+            let name = cell_token.get();
+            if name.kind != Kind::Ident || name.text.contains('.') {
+                return Err("enum name is missing".to_string());
+            }
+            let enum_name = name.text.clone();
+            next_tkn(&cell_token, index, token_list)?;
+            check_tkn(&cell_token, index, token_list, Kind::Lbrace, format!("{{ is missing for enum {}", enum_name), true)?;
+            let mut variants = Vec::new();
+            while cell_token.get().kind != Kind::Rbrace {
+                let variant = cell_token.get();
+                if variant.kind != Kind::Ident || variant.text.contains('.') {
+                    return Err(format!("variant name is missing in enum {}", enum_name));
+                }
+                next_tkn(&cell_token, index, token_list)?;
+                let mut types = Vec::new();
+                if cell_token.get().kind == Kind::Lparen {
+                    next_tkn(&cell_token, index, token_list)?;
+                    types = names(&cell_token, index, token_list, format!("type is missing in {}.{}", enum_name, variant.text))?;
+                }
+                variants.push((variant.text.clone(), types));
+                match cell_token.get().kind {
+                    Kind::Comma => next_tkn(&cell_token, index, token_list)?,
+                    Kind::Rbrace => {},
+                    _ => return Err(format!("}} is missing for enum {}", enum_name)),
+                }
+            }
+            next_tkn(&cell_token, index, token_list)?;
+            return Ok(Expr::Enum(Box::new(EnumDef::new(enum_name, variants, pos))));
+        },
+        // match s { Shape.Circle(r) => { ... } _ => { ... } }
+        Kind::Match => {
+            next_tkn(&cell_token, index, token_list)?;
+            expression(&cell_token, index, token_list, stack)?;
+            let value = match stack.pop() {
+                None => return Err("match: value is missing".to_string()),
+                Some(e) => e
+            };
+            check_tkn(&cell_token, index, token_list, Kind::Lbrace, "{ is missing for match".to_string(), true)?;
+            let mut arms = Vec::new();
+            while cell_token.get().kind != Kind::Rbrace {
+                let pattern_token = cell_token.get();
+                if pattern_token.kind == Kind::Endlist {
+                    return Err("} is missing for match".to_string());
+                }
+                let pattern = match pattern_token.text.rsplit_once('.') {
+                    _ if pattern_token.kind == Kind::Ident && pattern_token.text == "_" => None,
+                    Some((name, variant)) if pattern_token.kind == Kind::Ident => Some((name.to_string(), variant.to_string())),
+                    _ => return Err(format!("match pattern must be Enum.Variant or _ ({}:{})", pattern_token.pos.line, pattern_token.pos.col)),
+                };
+                next_tkn(&cell_token, index, token_list)?;
+                let mut bindings = Vec::new();
+                if cell_token.get().kind == Kind::Lparen {
+                    next_tkn(&cell_token, index, token_list)?;
+                    bindings = names(&cell_token, index, token_list, format!("variable name is missing in pattern {}", pattern_token.text))?;
+                }
+                check_tkn(&cell_token, index, token_list, Kind::Arrow, "=> is missing in match arm".to_string(), true)?;
+                check_tkn(&cell_token, index, token_list, Kind::Lbrace, "{ is missing in match arm".to_string(), true)?;
+                let mut list: Vec<Expr> = Vec::new();
+                loop {
+                    match cell_token.get().kind {
+                        Kind::Endlist => return Err("} is missing for match arm".to_string()),
+                        Kind::Rbrace => {
+                            // ifと同じく最後のNopeでif連鎖を閉じる
+                            next_tkn(&cell_token, index, token_list)?;
+                            list.push(Expr::Nope);
+                            break;
+                        },
+                        _ => {},
+                    }
+                    list.push(statement(index, token_list, stack)?);
+                    match token_list.get(*index) {
+                        None => return Err("} is missing for match arm".to_string()),
+                        Some(tkn) => cell_token.set(tkn),
+                    }
+                }
+                arms.push(Arm::new(pattern, bindings, list, pattern_token.pos));
+                if cell_token.get().kind == Kind::Comma {
+                    next_tkn(&cell_token, index, token_list)?;
+                }
+            }
+            next_tkn(&cell_token, index, token_list)?;
+            return Ok(Expr::Match(Box::new(Match::new(value, arms, pos))));
+        },
+        Kind::Return => {
+            next_tkn(&cell_token, index, token_list)?;
+            expression(&cell_token, index, token_list, stack)?;
+            let val = match stack.pop() {
+                None => return Err("return needs a status code".to_string()),
+                Some(expr) => expr,
+            };
+            check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true)?;
+            return Ok(Expr::Return(Box::new(Return::new(val, false, pos))));
+        },
+        Kind::Import => {
+            next_tkn(&cell_token, index, token_list)?;
+            let path = cell_token.get();
+            if path.kind != Kind::Str {
+                return Err("import needs a file path like import \"math.ny\";".to_string());
+            }
+            let import = Expr::Import(Box::new(Import::new(path.text.clone(), pos)));
+            next_tkn(&cell_token, index, token_list)?;
+            check_tkn(&cell_token, index, token_list, Kind::Semicolon, "; is missing".to_string(), true)?;
+            return Ok(import);
+        },
+        // ifやelseのブロックの終わり
+        Kind::Rbrace => {
+            next_tkn(&cell_token, index, token_list)?;
         },
         _ => {
-            next_tkn(&cell_token, index, token_list);
+            let text = source_text(std::slice::from_ref(token));
+            if text.is_empty() {
+                return Err(format!("unexpected token ({}:{})", pos.line, pos.col));
+            }
+            return Err(format!("unexpected `{}` ({}:{})", text, pos.line, pos.col));
         }
     }
-    Expr::Nope
+    Ok(Expr::Nope)
 }
 
 #[allow(unused_assignments)]
-fn expression<'a>(cell_token:&'a Cell<&'a Token>, index: &mut usize, token_list: &'a Vec<Token>, stack: &mut Vec<Expr>) {
+fn expression<'a>(cell_token:&'a Cell<&'a Token>, index: &mut usize, token_list: &'a [Token], stack: &mut Vec<Expr>) -> Result<(), String> {
     let mut op: Kind = Kind::Nulkind;
-    term(cell_token, index, token_list, stack);
+    term(cell_token, index, token_list, stack)?;
     let mut token: &Token = cell_token.get();
     while token.kind == Kind::Plus || token.kind == Kind::Minus {
         op = token.kind;
-        next_tkn(cell_token, index, token_list);
-        term(cell_token, index, token_list, stack);
-        operate(op, stack);
+        let pos = token.pos;
+        next_tkn(cell_token, index, token_list)?;
+        term(cell_token, index, token_list, stack)?;
+        operate(op, pos, stack)?;
         token = cell_token.get();
     }
+    Ok(())
 }
 
 #[allow(unused_assignments)]
-fn term<'a>(cell_token: &'a Cell<&'a Token>, index: &mut usize, token_list: &'a Vec<Token>, stack: &mut Vec<Expr>) {
+fn term<'a>(cell_token: &'a Cell<&'a Token>, index: &mut usize, token_list: &'a [Token], stack: &mut Vec<Expr>) -> Result<(), String> {
     let mut op: Kind = Kind::Nulkind;
-    factor(cell_token, index, token_list, stack);
+    factor(cell_token, index, token_list, stack)?;
     let mut token: &Token = cell_token.get();
     while token.kind == Kind::Multi || token.kind == Kind::Divi {
         op = token.kind;
-        next_tkn(cell_token, index, token_list);
-        factor(cell_token, index, token_list, stack);
-        operate(op, stack);
+        let pos = token.pos;
+        next_tkn(cell_token, index, token_list)?;
+        factor(cell_token, index, token_list, stack)?;
+        operate(op, pos, stack)?;
         token = cell_token.get();
     }
+    Ok(())
 }
 
-fn factor<'a>(cell_token: &'a Cell<&'a Token>, index: &mut usize, token_list: &'a Vec<Token>, stack: &mut Vec<Expr>) {
+fn factor<'a>(cell_token: &'a Cell<&'a Token>, index: &mut usize, token_list: &'a [Token], stack: &mut Vec<Expr>) -> Result<(), String> {
     let token: &Token = cell_token.get();
     match token.kind {
         Kind::Ident => {
-            //stack.push(id_map[&token.text].clone());
-            let expr = Expr::Ident(Box::new(Ident::new(token.text.clone(), token.kind)));
-            stack.push(expr);
+            let next = token_list.get(*index + 1).map(|t| t.kind);
+            if next == Some(Kind::Lparen) {
+                next_tkn(cell_token, index, token_list)?;
+                next_tkn(cell_token, index, token_list)?;
+                let args = arguments(cell_token, index, token_list, stack, Kind::Rparen)?;
+                // `Shape.Circle(3)` はenumの値
+                match token.text.rsplit_once('.') {
+                    Some((name, variant)) => stack.push(Expr::EnumLit(Box::new(EnumLit::new(name.to_string(), variant.to_string(), args, token.pos)))),
+                    None => stack.push(builtin(&token.text, args, token.pos)?),
+                }
+            } else if next == Some(Kind::Lbracket) {
+                next_tkn(cell_token, index, token_list)?;
+                next_tkn(cell_token, index, token_list)?;
+                expression(cell_token, index, token_list, stack)?;
+                let index_expr = match stack.pop() {
+                    None => return Err("index is missing".to_string()),
+                    Some(expr) => expr
+                };
+                check_tkn(cell_token, index, token_list, Kind::Rbracket, "] is missing".to_string(), false)?;
+                let array = ident_expr(&token.text, token.kind, token.pos);
+                stack.push(Expr::Index(Box::new(Index::new(array, index_expr, token.pos))));
+            } else if next == Some(Kind::Lbrace) && is_struct_literal(*index, token_list) {
+                next_tkn(cell_token, index, token_list)?;
+                next_tkn(cell_token, index, token_list)?;
+                let mut fields = Vec::new();
+                while cell_token.get().kind != Kind::Rbrace {
+                    let field = cell_token.get();
+                    if field.kind != Kind::Ident {
+                        return Err(format!("field name is missing in {} literal", token.text));
+                    }
+                    next_tkn(cell_token, index, token_list)?;
+                    check_tkn(cell_token, index, token_list, Kind::Colon, format!(": is missing after {}", field.text), true)?;
+                    expression(cell_token, index, token_list, stack)?;
+                    match stack.pop() {
+                        None => return Err(format!("value is missing for field {}", field.text)),
+                        Some(expr) => fields.push((field.text.clone(), expr)),
+                    };
+                    match cell_token.get().kind {
+                        Kind::Comma => next_tkn(cell_token, index, token_list)?,
+                        Kind::Rbrace => {},
+                        _ => return Err(format!("}} is missing for {} literal", token.text)),
+                    }
+                }
+                stack.push(Expr::StructLit(Box::new(StructLit::new(token.text.clone(), fields, token.pos))));
+            } else {
+                //stack.push(id_map[&token.text].clone());
+                let expr = ident_expr(&token.text, token.kind, token.pos);
+                stack.push(expr);
+            }
+        },
+        Kind::Lbracket => {
+            next_tkn(cell_token, index, token_list)?;
+            let list = arguments(cell_token, index, token_list, stack, Kind::Rbracket)?;
+            stack.push(Expr::Array(Box::new(Array::new(list))));
         },
         Kind::Int => {
             stack.push(Expr::Int(Int::new(token.val)));
         },
         Kind::Equal => {
-            let left = stack.pop().unwrap();
-            next_tkn(cell_token, index, token_list);
-            expression(cell_token, index, token_list, stack);
-            let right = stack.pop().unwrap();
+            let left = match stack.pop() {
+                None => return Err("left side of == is missing".to_string()),
+                Some(expr) => expr
+            };
+            next_tkn(cell_token, index, token_list)?;
+            expression(cell_token, index, token_list, stack)?;
+            let right = match stack.pop() {
+                None => return Err("right side of == is missing".to_string()),
+                Some(expr) => expr
+            };
             stack.push(Expr::Equal(Box::new(Equal::new(left, right))));
-            return;
+            return Ok(());
         }
         Kind::Lparen => {
-            next_tkn(cell_token, index, token_list);
-            expression(cell_token, index, token_list, stack);
-            check_tkn(cell_token, index, token_list, Kind::Rparen, ") is missing".to_string(), false);
+            next_tkn(cell_token, index, token_list)?;
+            expression(cell_token, index, token_list, stack)?;
+            check_tkn(cell_token, index, token_list, Kind::Rparen, ") is missing".to_string(), false)?;
         },
         Kind::Char => {
             stack.push(Expr::Char(Char::new(token.chr)));
@@ -219,53 +651,247 @@ fn factor<'a>(cell_token: &'a Cell<&'a Token>, index: &mut usize, token_list: &'
             
         }
     }
-    next_tkn(cell_token, index, token_list);
+    next_tkn(cell_token, index, token_list)
+}
+
+
+// `,` で区切られた式をcloseの手前まで読む. closeは読み進めない
+fn arguments<'a>(cell_token: &'a Cell<&'a Token>, index: &mut usize, token_list: &'a [Token], stack: &mut Vec<Expr>, close: Kind) -> Result<Vec<Expr>, String> {
+    let mut list = Vec::new();
+    while cell_token.get().kind != close {
+        expression(cell_token, index, token_list, stack)?;
+        match stack.pop() {
+            None => return Err("argument is missing".to_string()),
+            Some(expr) => list.push(expr),
+        };
+        match cell_token.get().kind {
+            Kind::Comma => next_tkn(cell_token, index, token_list)?,
+            k if k == close => {},
+            _ => return Err(format!("{} is missing", if close == Kind::Rparen { ")" } else { "]" })),
+        }
+    }
+    Ok(list)
+}
+
+// `(a, b)` の名前の並びを `)` まで読む
+fn names<'a>(cell_token: &'a Cell<&'a Token>, index: &mut usize, token_list: &'a [Token], message: String) -> Result<Vec<String>, String> {
+    let mut list = Vec::new();
+    while cell_token.get().kind != Kind::Rparen {
+        let name = cell_token.get();
+        if name.kind != Kind::Ident || name.text.contains('.') {
+            return Err(message);
+        }
+        list.push(name.text.clone());
+        next_tkn(cell_token, index, token_list)?;
+        match cell_token.get().kind {
+            Kind::Comma => next_tkn(cell_token, index, token_list)?,
+            Kind::Rparen => {},
+            _ => return Err(") is missing".to_string()),
+        }
+    }
+    next_tkn(cell_token, index, token_list)?;
+    Ok(list)
+}
+
+// ブロックの中も含めて最初のreturnかexitの位置
+pub fn find_return(list: &[Expr]) -> Option<Pos> {
+    list.iter().find_map(|expr| match expr {
+        Expr::Return(e) => Some(e.pos),
+        Expr::If(e) => find_return(&e.list),
+        Expr::ElseIf(e) => find_return(&e.list),
+        Expr::Else(e) => find_return(&e.list),
+        Expr::Match(e) => e.arms.iter().find_map(|arm| find_return(&arm.list)),
+        _ => None,
+    })
+}
+
+// トークンを並べ直して式のソースに戻す. assertのメッセージに使う
+fn source_text(token_list: &[Token]) -> String {
+    let mut text = String::new();
+    let mut prev = Kind::Lparen;
+    for token in token_list.iter() {
+        let word = match token.kind {
+            Kind::Int => token.val.to_string(),
+            Kind::Char => format!("'{}'", token.chr),
+            Kind::Str => format!("\"{}\"", token.text),
+            _ if !token.text.is_empty() => token.text.clone(),
+            kind => match tokenizer::KEY_WD_TBL.iter().find(|w| w.kind == kind) {
+                Some(w) => w.val.to_string(),
+                None => continue,
+            },
+        };
+        let tight = matches!(prev, Kind::Lparen | Kind::Lbracket)
+            || matches!(token.kind, Kind::Rparen | Kind::Rbracket | Kind::Comma)
+            || (token.kind == Kind::Lparen && prev == Kind::Ident)
+            || (token.kind == Kind::Lbracket && prev == Kind::Ident);
+        if !text.is_empty() && !tight {
+            text.push(' ');
+        }
+        text.push_str(&word);
+        prev = token.kind;
+    }
+    text
 }
 
+// 最初の引数が文字列リテラルなら書式として読む. `{}` には残りの引数を順に入れ,
+// `{x}` には変数xを入れる. `{{` と `}}` は括弧そのもの.
+// そうでなければ引数を空白で区切って並べる
+fn format_args(mut args: Vec<Expr>, pos: Pos) -> Result<Vec<Expr>, String> {
+    let format = match args.first() {
+        Some(Expr::Str(s)) => s.eval(),
+        _ => {
+            let mut list = Vec::new();
+            for (i, arg) in args.into_iter().enumerate() {
+                if i > 0 {
+                    list.push(Expr::Str(Str::new(" ".to_string())));
+                }
+                list.push(arg);
+            }
+            return Ok(list);
+        },
+    };
+    let mut rest = args.split_off(1).into_iter();
+    let given = rest.len();
+    let mut used = 0;
+    let mut list = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); text.push('{'); },
+            '}' if chars.peek() == Some(&'}') => { chars.next(); text.push('}'); },
+            '}' => return Err(format!("unmatched }} in format string ({}:{})", pos.line, pos.col)),
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(format!("unclosed {{ in format string ({}:{})", pos.line, pos.col)),
+                        Some('}') => break,
+                        Some(ch) => name.push(ch),
+                    }
+                }
+                if !text.is_empty() {
+                    list.push(Expr::Str(Str::new(std::mem::take(&mut text))));
+                }
+                if name.is_empty() {
+                    used += 1;
+                    match rest.next() {
+                        None => return Err(format!("format string has {} {{}} but {} argument(s) were given ({}:{})", format.matches("{}").count(), given, pos.line, pos.col)),
+                        Some(arg) => list.push(arg),
+                    }
+                    continue;
+                }
+                let valid = name.split('.').all(|n| n.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && n.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+                if !valid {
+                    return Err(format!("invalid name {{{}}} in format string ({}:{})", name, pos.line, pos.col));
+                }
+                list.push(ident_expr(&name, Kind::Nulkind, pos));
+            },
+            _ => text.push(c),
+        }
+    }
+    if used < given {
+        return Err(format!("format string uses {} argument(s) but {} were given ({}:{})", used, given, pos.line, pos.col));
+    }
+    if !text.is_empty() {
+        list.push(Expr::Str(Str::new(text)));
+    }
+    Ok(list)
+}
 
-fn check_tkn<'a>(cell_token:&'a Cell<&'a Token>, index: &mut usize, token_list: &'a Vec<Token>, tp: Kind, message:String, next: bool) {
+// `p.x` はフィールドを読む. importしたファイルの変数 `math.x` もここではFieldになり,
+// import::resolveでIdentに戻す
+fn ident_expr(text: &str, kind: Kind, pos: Pos) -> Expr {
+    let mut names = text.split('.');
+    let mut expr = Expr::Ident(Box::new(Ident::new(names.next().unwrap_or("").to_string(), kind)));
+    for name in names {
+        expr = Expr::Field(Box::new(Field::new(expr, name.to_string(), pos)));
+    }
+    expr
+}
+
+// `Point { x: ...` ならstructのリテラル. `if x {` のブロックと区別するために
+// `{` の後が `名前:` になっているかを見る
+fn is_struct_literal(index: usize, token_list: &[Token]) -> bool {
+    let kind = |i: usize| token_list.get(i).map(|t| t.kind);
+    kind(index + 2) == Some(Kind::Ident) && kind(index + 3) == Some(Kind::Colon)
+}
+
+// 組み込み関数の呼び出し
+fn builtin(name: &str, mut args: Vec<Expr>, pos: Pos) -> Result<Expr, String> {
+    let expected = match name {
+        "vec" => return Ok(Expr::Vector(Box::new(Vector::new(args)))),
+        "input" | "read_int" | "read_char" | "eof" => 0,
+        "len" | "pop" | "str" | "int" => 1,
+        "substr" => 3,
+        _ => return Err(format!("unknown function {}", name)),
+    };
+    if args.len() != expected {
+        return Err(format!("{} takes {} argument(s) but {} were given", name, expected, args.len()));
+    }
+    let input = match name {
+        "input" => Some(Kind::Str),
+        "read_int" => Some(Kind::Int),
+        "read_char" => Some(Kind::Char),
+        "eof" => Some(Kind::EofTkn),
+        _ => None,
+    };
+    if let Some(kind) = input {
+        return Ok(Expr::Input(Box::new(Input::new(kind, pos))));
+    }
+    let first = args.remove(0);
+    let expr = match name {
+        "len" => Expr::Len(Box::new(Len::new(first))),
+        "pop" => Expr::Pop(Box::new(Pop::new(first, pos))),
+        "str" => Expr::ToStr(Box::new(ToStr::new(first))),
+        "int" => Expr::ToInt(Box::new(ToInt::new(first, pos))),
+        _ => {
+            let end = args.pop().unwrap();
+            let start = args.pop().unwrap();
+            Expr::Substr(Box::new(Substr::new(first, start, end, pos)))
+        }
+    };
+    Ok(expr)
+}
+
+fn check_tkn<'a>(cell_token:&'a Cell<&'a Token>, index: &mut usize, token_list: &'a [Token], tp: Kind, message:String, next: bool) -> Result<(), String> {
     let token:&Token = cell_token.get();
     if token.kind != tp {
-        println!("error: {}", message);
-        std::process::exit(1);
+        return Err(message);
     }
     if next {
-        next_tkn(cell_token, index, token_list);
+        next_tkn(cell_token, index, token_list)?;
     }
+    Ok(())
 }
 
 #[allow(unused_assignments)]
-fn next_tkn<'a>(cell_token: &'a Cell<&'a Token>, index: &mut usize, token_list: &'a Vec<Token>) {
+fn next_tkn<'a>(cell_token: &'a Cell<&'a Token>, index: &mut usize, token_list: &'a [Token]) -> Result<(), String> {
     if *index >= token_list.len() {
-        println!("overflow");
-        std::process::exit(1);
+        return Err("unexpected end of file".to_string());
     }
     let mut token = cell_token.get();
     *index += 1;
     token = match token_list.get(*index) {
-        None => return,
+        None => return Ok(()),
         Some(tkn) => tkn
     };
     cell_token.set(token);
+    Ok(())
 }
 
-fn operate(op: Kind, stack: &mut Vec<Expr>) {
+fn operate(op: Kind, pos: Pos, stack: &mut Vec<Expr>) -> Result<(), String> {
     let d2: Expr = match stack.pop() {
-        None => { parse_error("error: stack is empty".to_string()); return;},
+        None => return Err("stack is empty".to_string()),
         Some(expr) => expr
     };
     let d1 = match stack.pop() {
-        None => { parse_error("error: stack is empty".to_string()); return;},
+        None => return Err("stack is empty".to_string()),
         Some(expr) => expr
     };
-    stack.push(Expr::BinaryOp(Box::new(BinaryOp::new(op, d1, d2))));
-}  
-
-
-fn parse_error(message: String) {
-    println!("{}", message);
-    println!("NG");
-    std::process::exit(1);
+    stack.push(Expr::BinaryOp(Box::new(BinaryOp::new(op, d1, d2, pos))));
+    Ok(())
 }
 
 
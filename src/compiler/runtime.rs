@@ -0,0 +1,487 @@
+// nyaanのプログラムが実行時に使う約束事 (runtime ABI)
+//
+// - 実行時のエラー(panic)は `panicked at file.ny:行:列: message` をdprintf(2, ...)で
+//   標準エラーに書いてから exit(PANIC_EXIT_CODE) する
+// - vecは {i32* data, i32 len, i32 cap} をmainのentryブロックにallocaしたもの.
+//   dataはmalloc/reallocで確保し, 変数がバッファを持つ.
+//   同じ変数に新しいvecを代入したとき, またはmainの最後でfreeする.
+//   vecの代入はできないので, 1つのバッファを2つの変数が持つことはない
+// - 文字列は {i8* ptr, i32 len}. ptrの先はlenバイトの後にNULが続く.
+//   文字列は書き換えられないので, 連結などで作ったバッファは共有してよく, freeしない
+// - structはフィールドを定義の順に並べたもの. intとcharはi32, strは {i8* ptr, i32 len},
+//   structのフィールドはそのstructをそのまま中に持つ
+// - 入力はgetcharとscanfで読む. 入力が終わっていたらグローバル変数 nyaan.eof を1にし,
+//   読めたら0に戻す
+// - enumは {i32 tag, {variant0の値}, {variant1の値}, ...}. tagはvariantの定義の順番で,
+//   値はstructのフィールドと同じ並べ方. 使わないvariantの場所も確保する
+use super::types::{kind_of_type, Registry};
+use super::{Kind, Pos, PANIC_EXIT_CODE};
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::Module;
+use inkwell::types::{BasicTypeEnum, StructType};
+use inkwell::values::{BasicValueEnum, FunctionValue, IntValue, PointerValue};
+use inkwell::{AddressSpace, IntPredicate};
+
+const DATA: u32 = 0;
+const LEN: u32 = 1;
+const CAP: u32 = 2;
+const STR_PTR: u32 = 0;
+const STR_LEN: u32 = 1;
+pub const EOF_FLAG: &str = "nyaan.eof";
+
+pub fn vec_type<'ctx>(context: &'ctx Context) -> StructType<'ctx> {
+    let i32_type = context.i32_type();
+    let data_type = i32_type.ptr_type(AddressSpace::Generic);
+    context.struct_type(&[BasicTypeEnum::PointerType(data_type), i32_type.into(), i32_type.into()], false)
+}
+
+// messageを出して止まる. messageはdprintfの書式. 位置のわからないエラーはlocationをNoneにする
+pub fn build_panic<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, location: Option<&str>, message: &str, args: &[BasicValueEnum<'ctx>]) {
+    let text = match location {
+        Some(l) => format!("panicked at {}: {}\n", l, message),
+        None => format!("panicked: {}\n", message),
+    };
+    let fmt = builder.build_global_string_ptr(&text, "").as_pointer_value();
+    let mut call_args = vec![context.i32_type().const_int(2, false).into(), fmt.into()];
+    call_args.extend_from_slice(args);
+    builder.build_call(module.get_function("dprintf").unwrap(), &call_args, "");
+    let code = context.i32_type().const_int(PANIC_EXIT_CODE as u64, false);
+    builder.build_call(module.get_function("exit").unwrap(), &[code.into()], "");
+    builder.build_unreachable();
+}
+
+// okが偽ならpanicする
+pub fn build_check<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, ok: IntValue<'ctx>, location: Option<&str>, message: &str, args: &[BasicValueEnum<'ctx>]) {
+    let current = builder.get_insert_block().unwrap();
+    let ok_block = context.insert_basic_block_after(current, "ok");
+    let fail_block = context.insert_basic_block_after(current, "panic");
+    builder.build_conditional_branch(ok, ok_block, fail_block);
+    builder.position_at_end(fail_block);
+    build_panic(context, module, builder, location, message, args);
+    builder.position_at_end(ok_block);
+}
+
+// エラーメッセージに付ける位置. %はdprintfの書式にならないようにする
+pub fn location(source: &str, pos: Pos) -> String {
+    format!("{}:{}:{}", source.replace('%', "%%"), pos.line, pos.col)
+}
+
+// 四則演算. 0での割り算と INT_MIN / -1 はいつも調べる. checkedならi64で計算して
+// i32からあふれていないかも調べる
+#[allow(clippy::too_many_arguments)]
+pub fn build_arith<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, op: Kind, left: IntValue<'ctx>, right: IntValue<'ctx>, checked: bool, location: &str) -> IntValue<'ctx> {
+    let i32_type = context.i32_type();
+    if op == Kind::Divi {
+        let not_zero = builder.build_int_compare(IntPredicate::NE, right, i32_type.const_int(0, false), "");
+        build_check(context, module, builder, not_zero, Some(location), "attempt to divide by zero", &[]);
+        let min = builder.build_int_compare(IntPredicate::EQ, left, i32_type.const_int(i32::MIN as u32 as u64, false), "");
+        let minus_one = builder.build_int_compare(IntPredicate::EQ, right, i32_type.const_int(u32::MAX as u64, false), "");
+        let overflow = builder.build_and(min, minus_one, "");
+        build_check(context, module, builder, builder.build_not(overflow, ""), Some(location), "attempt to divide with overflow", &[]);
+        return builder.build_int_signed_div(left, right, "");
+    }
+    let build = |l: IntValue<'ctx>, r: IntValue<'ctx>| match op {
+        Kind::Plus => builder.build_int_add(l, r, ""),
+        Kind::Minus => builder.build_int_sub(l, r, ""),
+        _ => builder.build_int_mul(l, r, ""),
+    };
+    let result = build(left, right);
+    if checked {
+        let i64_type = context.i64_type();
+        let wide = build(builder.build_int_s_extend(left, i64_type, ""), builder.build_int_s_extend(right, i64_type, ""));
+        let fits = builder.build_int_compare(IntPredicate::EQ, builder.build_int_s_extend(result, i64_type, ""), wide, "");
+        let name = match op { Kind::Plus => "add", Kind::Minus => "subtract", _ => "multiply" };
+        build_check(context, module, builder, fits, Some(location), &format!("attempt to {} with overflow", name), &[]);
+    }
+    result
+}
+
+// ヘッダはループやifの中で作っても1つになるようにentryブロックに置く
+fn entry_builder<'ctx>(context: &'ctx Context, function: FunctionValue<'ctx>) -> Builder<'ctx> {
+    let entry = function.get_first_basic_block().unwrap();
+    let entry_builder = context.create_builder();
+    match entry.get_first_instruction() {
+        Some(first) => entry_builder.position_before(&first),
+        None => entry_builder.position_at_end(entry),
+    }
+    entry_builder
+}
+
+pub fn build_vec_header<'ctx>(context: &'ctx Context, function: FunctionValue<'ctx>, name: &str) -> PointerValue<'ctx> {
+    let entry_builder = entry_builder(context, function);
+    let header = entry_builder.build_alloca(vec_type(context), name);
+    let i32_type = context.i32_type();
+    entry_builder.build_store(field(&entry_builder, header, DATA), i32_type.ptr_type(AddressSpace::Generic).const_null());
+    entry_builder.build_store(field(&entry_builder, header, LEN), i32_type.const_int(0, false));
+    entry_builder.build_store(field(&entry_builder, header, CAP), i32_type.const_int(0, false));
+    header
+}
+
+fn field<'ctx>(builder: &Builder<'ctx>, header: PointerValue<'ctx>, index: u32) -> PointerValue<'ctx> {
+    builder.build_struct_gep(header, index, "").unwrap()
+}
+
+// headerを要素valuesの新しいvecにする. 前のバッファはfreeする
+pub fn build_vec_init<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, header: PointerValue<'ctx>, values: &[IntValue<'ctx>]) {
+    build_vec_free(context, module, builder, header);
+    let i32_type = context.i32_type();
+    let data_type = i32_type.ptr_type(AddressSpace::Generic);
+    let count = i32_type.const_int(values.len() as u64, false);
+    let data = if values.is_empty() {
+        data_type.const_null()
+    } else {
+        let bytes = context.i64_type().const_int(values.len() as u64 * 4, false);
+        let raw = builder.build_call(module.get_function("malloc").unwrap(), &[bytes.into()], "").try_as_basic_value().left().unwrap();
+        let data = builder.build_bitcast(raw, data_type, "").into_pointer_value();
+        build_check(context, module, builder, builder.build_is_not_null(data, ""), None, "out of memory", &[]);
+        for (i, value) in values.iter().enumerate() {
+            let slot = unsafe { builder.build_gep(data, &[i32_type.const_int(i as u64, false)], "") };
+            builder.build_store(slot, *value);
+        }
+        data
+    };
+    builder.build_store(field(builder, header, DATA), data);
+    builder.build_store(field(builder, header, LEN), count);
+    builder.build_store(field(builder, header, CAP), count);
+}
+
+pub fn build_vec_free<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, header: PointerValue<'ctx>) {
+    let data = builder.build_load(field(builder, header, DATA), "").into_pointer_value();
+    let raw = builder.build_bitcast(data, context.i8_type().ptr_type(AddressSpace::Generic), "");
+    builder.build_call(module.get_function("free").unwrap(), &[raw], "");
+}
+
+pub fn build_vec_len<'ctx>(builder: &Builder<'ctx>, header: PointerValue<'ctx>) -> IntValue<'ctx> {
+    builder.build_load(field(builder, header, LEN), "len").into_int_value()
+}
+
+pub fn build_vec_data<'ctx>(builder: &Builder<'ctx>, header: PointerValue<'ctx>) -> PointerValue<'ctx> {
+    builder.build_load(field(builder, header, DATA), "data").into_pointer_value()
+}
+
+// いっぱいなら容量を2倍(最初は4)にしてから末尾に足す
+pub fn build_vec_push<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, header: PointerValue<'ctx>, value: IntValue<'ctx>) {
+    let i32_type = context.i32_type();
+    let len = build_vec_len(builder, header);
+    let cap = builder.build_load(field(builder, header, CAP), "cap").into_int_value();
+    let current = builder.get_insert_block().unwrap();
+    let store_block = context.insert_basic_block_after(current, "push");
+    let grow_block = context.insert_basic_block_after(current, "grow");
+    let full = builder.build_int_compare(IntPredicate::EQ, len, cap, "");
+    builder.build_conditional_branch(full, grow_block, store_block);
+
+    builder.position_at_end(grow_block);
+    let empty = builder.build_int_compare(IntPredicate::EQ, cap, i32_type.const_int(0, false), "");
+    let doubled = builder.build_int_mul(cap, i32_type.const_int(2, false), "");
+    let new_cap = builder.build_select(empty, i32_type.const_int(4, false), doubled, "").into_int_value();
+    let bytes = builder.build_int_mul(builder.build_int_z_extend(new_cap, context.i64_type(), ""), context.i64_type().const_int(4, false), "");
+    let old = builder.build_bitcast(build_vec_data(builder, header), context.i8_type().ptr_type(AddressSpace::Generic), "");
+    let raw = builder.build_call(module.get_function("realloc").unwrap(), &[old, bytes.into()], "").try_as_basic_value().left().unwrap();
+    let data = builder.build_bitcast(raw, i32_type.ptr_type(AddressSpace::Generic), "").into_pointer_value();
+    build_check(context, module, builder, builder.build_is_not_null(data, ""), None, "out of memory", &[]);
+    builder.build_store(field(builder, header, DATA), data);
+    builder.build_store(field(builder, header, CAP), new_cap);
+    builder.build_unconditional_branch(store_block);
+
+    builder.position_at_end(store_block);
+    let slot = unsafe { builder.build_gep(build_vec_data(builder, header), &[len], "") };
+    builder.build_store(slot, value);
+    builder.build_store(field(builder, header, LEN), builder.build_int_add(len, i32_type.const_int(1, false), ""));
+}
+
+pub fn build_vec_pop<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, header: PointerValue<'ctx>, location: &str) -> IntValue<'ctx> {
+    let i32_type = context.i32_type();
+    let len = build_vec_len(builder, header);
+    let not_empty = builder.build_int_compare(IntPredicate::NE, len, i32_type.const_int(0, false), "");
+    build_check(context, module, builder, not_empty, Some(location), "pop from an empty vec", &[]);
+    let last = builder.build_int_sub(len, i32_type.const_int(1, false), "");
+    builder.build_store(field(builder, header, LEN), last);
+    let slot = unsafe { builder.build_gep(build_vec_data(builder, header), &[last], "") };
+    builder.build_load(slot, "").into_int_value()
+}
+
+// [1, 2, 3] の形で出力する. 長さは実行時にしかわからないのでループにする
+pub fn build_vec_print<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, header: PointerValue<'ctx>) {
+    let i32_type = context.i32_type();
+    let printf = module.get_function("printf").unwrap();
+    let open = builder.build_global_string_ptr("[", "").as_pointer_value();
+    builder.build_call(printf, &[open.into()], "");
+    let counter = builder.build_alloca(i32_type, "i");
+    builder.build_store(counter, i32_type.const_int(0, false));
+
+    let current = builder.get_insert_block().unwrap();
+    let done_block = context.insert_basic_block_after(current, "print.done");
+    let body_block = context.insert_basic_block_after(current, "print.body");
+    let cond_block = context.insert_basic_block_after(current, "print.cond");
+    builder.build_unconditional_branch(cond_block);
+
+    builder.position_at_end(cond_block);
+    let i = builder.build_load(counter, "").into_int_value();
+    let more = builder.build_int_compare(IntPredicate::SLT, i, build_vec_len(builder, header), "");
+    builder.build_conditional_branch(more, body_block, done_block);
+
+    builder.position_at_end(body_block);
+    let first = builder.build_int_compare(IntPredicate::EQ, i, i32_type.const_int(0, false), "");
+    let first_fmt = builder.build_global_string_ptr("%d", "").as_pointer_value();
+    let rest_fmt = builder.build_global_string_ptr(", %d", "").as_pointer_value();
+    let fmt = builder.build_select(first, first_fmt, rest_fmt, "");
+    let slot = unsafe { builder.build_gep(build_vec_data(builder, header), &[i], "") };
+    let value = builder.build_load(slot, "");
+    builder.build_call(printf, &[fmt, value], "");
+    builder.build_store(counter, builder.build_int_add(i, i32_type.const_int(1, false), ""));
+    builder.build_unconditional_branch(cond_block);
+
+    builder.position_at_end(done_block);
+    let close = builder.build_global_string_ptr("]", "").as_pointer_value();
+    builder.build_call(printf, &[close.into()], "");
+}
+
+pub fn str_type<'ctx>(context: &'ctx Context) -> StructType<'ctx> {
+    let ptr_type = context.i8_type().ptr_type(AddressSpace::Generic);
+    context.struct_type(&[BasicTypeEnum::PointerType(ptr_type), context.i32_type().into()], false)
+}
+
+// 文字列はentryブロックに置いた {ptr, len} へのポインタとして持ち回る
+pub fn build_str_header<'ctx>(context: &'ctx Context, function: FunctionValue<'ctx>, name: &str) -> PointerValue<'ctx> {
+    entry_builder(context, function).build_alloca(str_type(context), name)
+}
+
+pub fn build_str<'ctx>(context: &'ctx Context, builder: &Builder<'ctx>, function: FunctionValue<'ctx>, ptr: PointerValue<'ctx>, len: IntValue<'ctx>) -> PointerValue<'ctx> {
+    let header = build_str_header(context, function, "str");
+    builder.build_store(field(builder, header, STR_PTR), ptr);
+    builder.build_store(field(builder, header, STR_LEN), len);
+    header
+}
+
+pub fn build_str_literal<'ctx>(context: &'ctx Context, builder: &Builder<'ctx>, function: FunctionValue<'ctx>, value: &str) -> PointerValue<'ctx> {
+    let ptr = builder.build_global_string_ptr(value, "").as_pointer_value();
+    build_str(context, builder, function, ptr, context.i32_type().const_int(value.len() as u64, false))
+}
+
+pub fn build_str_ptr<'ctx>(builder: &Builder<'ctx>, header: PointerValue<'ctx>) -> PointerValue<'ctx> {
+    builder.build_load(field(builder, header, STR_PTR), "ptr").into_pointer_value()
+}
+
+pub fn build_str_len<'ctx>(builder: &Builder<'ctx>, header: PointerValue<'ctx>) -> IntValue<'ctx> {
+    builder.build_load(field(builder, header, STR_LEN), "len").into_int_value()
+}
+
+// 変数への代入. バッファは共有する
+pub fn build_str_copy<'ctx>(builder: &Builder<'ctx>, from: PointerValue<'ctx>, to: PointerValue<'ctx>) {
+    builder.build_store(field(builder, to, STR_PTR), build_str_ptr(builder, from));
+    builder.build_store(field(builder, to, STR_LEN), build_str_len(builder, from));
+}
+
+// len + 1バイト確保して最後をNULにする
+fn build_str_alloc<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, len: IntValue<'ctx>) -> PointerValue<'ctx> {
+    let size = builder.build_int_add(builder.build_int_z_extend(len, context.i64_type(), ""), context.i64_type().const_int(1, false), "");
+    let ptr = builder.build_call(module.get_function("malloc").unwrap(), &[size.into()], "").try_as_basic_value().left().unwrap().into_pointer_value();
+    build_check(context, module, builder, builder.build_is_not_null(ptr, ""), None, "out of memory", &[]);
+    let end = unsafe { builder.build_gep(ptr, &[len], "") };
+    builder.build_store(end, context.i8_type().const_int(0, false));
+    ptr
+}
+
+fn build_memcpy<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, to: PointerValue<'ctx>, from: PointerValue<'ctx>, len: IntValue<'ctx>) {
+    let size = builder.build_int_z_extend(len, context.i64_type(), "");
+    builder.build_call(module.get_function("memcpy").unwrap(), &[to.into(), from.into(), size.into()], "");
+}
+
+pub fn build_str_concat<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, function: FunctionValue<'ctx>, left: PointerValue<'ctx>, right: PointerValue<'ctx>) -> PointerValue<'ctx> {
+    let left_len = build_str_len(builder, left);
+    let right_len = build_str_len(builder, right);
+    let len = builder.build_int_add(left_len, right_len, "");
+    let ptr = build_str_alloc(context, module, builder, len);
+    build_memcpy(context, module, builder, ptr, build_str_ptr(builder, left), left_len);
+    let tail = unsafe { builder.build_gep(ptr, &[left_len], "") };
+    build_memcpy(context, module, builder, tail, build_str_ptr(builder, right), right_len);
+    build_str(context, builder, function, ptr, len)
+}
+
+pub fn build_str_eq<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, left: PointerValue<'ctx>, right: PointerValue<'ctx>) -> IntValue<'ctx> {
+    let left_len = build_str_len(builder, left);
+    let right_len = build_str_len(builder, right);
+    let same_len = builder.build_int_compare(IntPredicate::EQ, left_len, right_len, "");
+    // 短い方の長さだけ比べれば範囲外を読まない
+    let shorter = builder.build_select(builder.build_int_compare(IntPredicate::ULT, left_len, right_len, ""), left_len, right_len, "").into_int_value();
+    let size = builder.build_int_z_extend(shorter, context.i64_type(), "");
+    let cmp = builder.build_call(module.get_function("memcmp").unwrap(), &[build_str_ptr(builder, left).into(), build_str_ptr(builder, right).into(), size.into()], "").try_as_basic_value().left().unwrap().into_int_value();
+    let same_bytes = builder.build_int_compare(IntPredicate::EQ, cmp, context.i32_type().const_int(0, false), "");
+    builder.build_and(same_len, same_bytes, "")
+}
+
+// s[i] はバイトをcharとして返す
+pub fn build_str_index<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, header: PointerValue<'ctx>, index: IntValue<'ctx>, location: &str) -> IntValue<'ctx> {
+    let len = build_str_len(builder, header);
+    let in_bounds = builder.build_int_compare(IntPredicate::ULT, index, len, "");
+    let message = "index out of bounds: the len is %d but the index is %d";
+    build_check(context, module, builder, in_bounds, Some(location), message, &[len.into(), index.into()]);
+    let slot = unsafe { builder.build_gep(build_str_ptr(builder, header), &[index], "") };
+    builder.build_int_z_extend(builder.build_load(slot, "").into_int_value(), context.i32_type(), "")
+}
+
+// substr(s, start, end) は [start, end) のバイトをコピーする
+#[allow(clippy::too_many_arguments)]
+pub fn build_str_substr<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, function: FunctionValue<'ctx>, header: PointerValue<'ctx>, start: IntValue<'ctx>, end: IntValue<'ctx>, location: &str) -> PointerValue<'ctx> {
+    let len = build_str_len(builder, header);
+    let start_ok = builder.build_int_compare(IntPredicate::ULE, start, end, "");
+    let end_ok = builder.build_int_compare(IntPredicate::ULE, end, len, "");
+    let message = "substr out of bounds: the len is %d but the range is %d..%d";
+    build_check(context, module, builder, builder.build_and(start_ok, end_ok, ""), Some(location), message, &[len.into(), start.into(), end.into()]);
+    let new_len = builder.build_int_sub(end, start, "");
+    let ptr = build_str_alloc(context, module, builder, new_len);
+    let from = unsafe { builder.build_gep(build_str_ptr(builder, header), &[start], "") };
+    build_memcpy(context, module, builder, ptr, from, new_len);
+    build_str(context, builder, function, ptr, new_len)
+}
+
+pub fn build_int_to_str<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, function: FunctionValue<'ctx>, value: IntValue<'ctx>) -> PointerValue<'ctx> {
+    // i32は符号を入れて11文字に収まる
+    let ptr = build_str_alloc(context, module, builder, context.i32_type().const_int(11, false));
+    let fmt = builder.build_global_string_ptr("%d", "").as_pointer_value();
+    let size = context.i64_type().const_int(12, false);
+    let len = builder.build_call(module.get_function("snprintf").unwrap(), &[ptr.into(), size.into(), fmt.into(), value.into()], "").try_as_basic_value().left().unwrap().into_int_value();
+    build_str(context, builder, function, ptr, len)
+}
+
+// int(s) は文字列全体が10進数でi32に収まるときだけ成功する
+pub fn build_str_to_int<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, function: FunctionValue<'ctx>, header: PointerValue<'ctx>, location: &str) -> IntValue<'ctx> {
+    let i64_type = context.i64_type();
+    let ptr = build_str_ptr(builder, header);
+    let len = build_str_len(builder, header);
+    let end_slot = entry_builder(context, function).build_alloca(context.i8_type().ptr_type(AddressSpace::Generic), "end");
+    let value = builder.build_call(module.get_function("strtol").unwrap(), &[ptr.into(), end_slot.into(), context.i32_type().const_int(10, false).into()], "").try_as_basic_value().left().unwrap().into_int_value();
+    let end = builder.build_ptr_to_int(builder.build_load(end_slot, "").into_pointer_value(), i64_type, "");
+    let expected = builder.build_ptr_to_int(unsafe { builder.build_gep(ptr, &[len], "") }, i64_type, "");
+    let consumed = builder.build_int_compare(IntPredicate::EQ, end, expected, "");
+    let not_empty = builder.build_int_compare(IntPredicate::NE, len, context.i32_type().const_int(0, false), "");
+    let result = builder.build_int_truncate(value, context.i32_type(), "");
+    let fits = builder.build_int_compare(IntPredicate::EQ, builder.build_int_s_extend(result, i64_type, ""), value, "");
+    let ok = builder.build_and(builder.build_and(consumed, not_empty, ""), fits, "");
+    build_check(context, module, builder, ok, Some(location), "invalid number \"%.*s\"", &[len.into(), ptr.into()]);
+    result
+}
+
+fn build_set_eof<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, at_eof: IntValue<'ctx>) {
+    let flag = module.get_global(EOF_FLAG).unwrap().as_pointer_value();
+    builder.build_store(flag, builder.build_int_z_extend(at_eof, context.i32_type(), ""));
+}
+
+// input() は改行までを読み, 改行は含めない. 何も読めずに終わったときだけeofにする
+pub fn build_read_line<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, function: FunctionValue<'ctx>) -> PointerValue<'ctx> {
+    let i32_type = context.i32_type();
+    let entry_builder = entry_builder(context, function);
+    let buffer = entry_builder.build_alloca(context.i8_type().ptr_type(AddressSpace::Generic), "line");
+    let len_slot = entry_builder.build_alloca(i32_type, "len");
+    let cap_slot = entry_builder.build_alloca(i32_type, "cap");
+    let initial = i32_type.const_int(16, false);
+    let size = builder.build_int_z_extend(initial, context.i64_type(), "");
+    let ptr = builder.build_call(module.get_function("malloc").unwrap(), &[size.into()], "").try_as_basic_value().left().unwrap().into_pointer_value();
+    build_check(context, module, builder, builder.build_is_not_null(ptr, ""), None, "out of memory", &[]);
+    builder.build_store(buffer, ptr);
+    builder.build_store(len_slot, i32_type.const_int(0, false));
+    builder.build_store(cap_slot, initial);
+
+    let current = builder.get_insert_block().unwrap();
+    let done = context.insert_basic_block_after(current, "read_done");
+    let append = context.insert_basic_block_after(current, "read_append");
+    let check_end = context.insert_basic_block_after(current, "read_check");
+    let read = context.insert_basic_block_after(current, "read");
+    builder.build_unconditional_branch(read);
+
+    builder.position_at_end(read);
+    let c = builder.build_call(module.get_function("getchar").unwrap(), &[], "").try_as_basic_value().left().unwrap().into_int_value();
+    let at_eof = builder.build_int_compare(IntPredicate::SLT, c, i32_type.const_int(0, false), "");
+    builder.build_conditional_branch(at_eof, done, check_end);
+
+    builder.position_at_end(check_end);
+    let newline = builder.build_int_compare(IntPredicate::EQ, c, i32_type.const_int('\n' as u64, false), "");
+    builder.build_conditional_branch(newline, done, append);
+
+    // NULの分を残して足りなければ倍にする
+    builder.position_at_end(append);
+    let len = builder.build_load(len_slot, "").into_int_value();
+    let cap = builder.build_load(cap_slot, "").into_int_value();
+    let full = builder.build_int_compare(IntPredicate::UGE, builder.build_int_add(len, i32_type.const_int(1, false), ""), cap, "");
+    let new_cap = builder.build_select(full, builder.build_int_mul(cap, i32_type.const_int(2, false), ""), cap, "").into_int_value();
+    let size = builder.build_int_z_extend(new_cap, context.i64_type(), "");
+    let old = builder.build_load(buffer, "").into_pointer_value();
+    let ptr = builder.build_call(module.get_function("realloc").unwrap(), &[old.into(), size.into()], "").try_as_basic_value().left().unwrap().into_pointer_value();
+    build_check(context, module, builder, builder.build_is_not_null(ptr, ""), None, "out of memory", &[]);
+    builder.build_store(buffer, ptr);
+    builder.build_store(cap_slot, new_cap);
+    let slot = unsafe { builder.build_gep(ptr, &[len], "") };
+    builder.build_store(slot, builder.build_int_truncate(c, context.i8_type(), ""));
+    builder.build_store(len_slot, builder.build_int_add(len, i32_type.const_int(1, false), ""));
+    builder.build_unconditional_branch(read);
+
+    builder.position_at_end(done);
+    let len = builder.build_load(len_slot, "").into_int_value();
+    let ptr = builder.build_load(buffer, "").into_pointer_value();
+    let end = unsafe { builder.build_gep(ptr, &[len], "") };
+    builder.build_store(end, context.i8_type().const_int(0, false));
+    let empty = builder.build_int_compare(IntPredicate::EQ, len, i32_type.const_int(0, false), "");
+    build_set_eof(context, module, builder, builder.build_and(at_eof, empty, ""));
+    build_str(context, builder, function, ptr, len)
+}
+
+// read_int() は空白を飛ばして10進数を1つ読む. 数でないかi32に収まらなければ止まる.
+// %dで読むと範囲外の数は未定義動作なので, i64で読んでから確かめる
+pub fn build_read_int<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>, function: FunctionValue<'ctx>, location: &str) -> IntValue<'ctx> {
+    let i32_type = context.i32_type();
+    let i64_type = context.i64_type();
+    let slot = entry_builder(context, function).build_alloca(i64_type, "read");
+    builder.build_store(slot, i64_type.const_int(0, false));
+    let fmt = builder.build_global_string_ptr("%lld", "").as_pointer_value();
+    let count = builder.build_call(module.get_function("scanf").unwrap(), &[fmt.into(), slot.into()], "").try_as_basic_value().left().unwrap().into_int_value();
+    let at_eof = builder.build_int_compare(IntPredicate::SLT, count, i32_type.const_int(0, false), "");
+    let value = builder.build_load(slot, "").into_int_value();
+    let result = builder.build_int_truncate(value, i32_type, "");
+    let fits = builder.build_int_compare(IntPredicate::EQ, builder.build_int_s_extend(result, i64_type, ""), value, "");
+    let read = builder.build_int_compare(IntPredicate::NE, count, i32_type.const_int(0, false), "");
+    let ok = builder.build_and(read, fits, "");
+    build_check(context, module, builder, ok, Some(location), "read_int: the input is not a number", &[]);
+    build_set_eof(context, module, builder, at_eof);
+    result
+}
+
+// read_char() は1バイト読む
+pub fn build_read_char<'ctx>(context: &'ctx Context, module: &Module<'ctx>, builder: &Builder<'ctx>) -> IntValue<'ctx> {
+    let i32_type = context.i32_type();
+    let c = builder.build_call(module.get_function("getchar").unwrap(), &[], "").try_as_basic_value().left().unwrap().into_int_value();
+    let at_eof = builder.build_int_compare(IntPredicate::SLT, c, i32_type.const_int(0, false), "");
+    build_set_eof(context, module, builder, at_eof);
+    builder.build_select(at_eof, i32_type.const_int(0, false), c, "").into_int_value()
+}
+
+// structやenumの型
+pub fn struct_type<'ctx>(context: &'ctx Context, registry: &Registry, name: &str) -> StructType<'ctx> {
+    let value_types = |types: &mut dyn Iterator<Item = &String>| -> Vec<BasicTypeEnum<'ctx>> {
+        types.map(|type_name| match kind_of_type(type_name) {
+            Kind::Str => str_type(context).into(),
+            Kind::Struct => struct_type(context, registry, type_name).into(),
+            _ => context.i32_type().into(),
+        }).collect()
+    };
+    let mut fields: Vec<BasicTypeEnum> = Vec::new();
+    if let Ok(def) = registry.get(name) {
+        fields = value_types(&mut def.fields.iter().map(|(_, t)| t));
+    }
+    if let Ok(def) = registry.get_enum(name) {
+        fields.push(context.i32_type().into());
+        for (_, types) in def.variants.iter() {
+            fields.push(context.struct_type(&value_types(&mut types.iter()), false).into());
+        }
+    }
+    context.struct_type(&fields, false)
+}
+
+// structの変数をentryブロックに置く. pathsはフィールドの番号をたどる道筋で,
+// それぞれのフィールドへのポインタもentryブロックで作っておく
+pub fn build_struct_var<'ctx>(context: &'ctx Context, function: FunctionValue<'ctx>, struct_type: StructType<'ctx>, name: &str, paths: &[Vec<u32>]) -> (PointerValue<'ctx>, Vec<PointerValue<'ctx>>) {
+    let entry_builder = entry_builder(context, function);
+    let ptr = entry_builder.build_alloca(struct_type, name);
+    let fields = paths.iter().map(|path| path.iter().fold(ptr, |p, i| field(&entry_builder, p, *i))).collect();
+    (ptr, fields)
+}
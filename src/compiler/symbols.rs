@@ -0,0 +1,133 @@
+use super::*;
+use super::types::Registry;
+
+// 変数, struct, enum, test, importの定義. nyaan lspのhoverと定義へのジャンプに使う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable, Struct, Enum, Test, Import
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // 変数なら型名 (わからなければ空), それ以外は整形した定義
+    pub detail: String,
+    // 定義した文の位置. 名前のトークンはこの位置かその後ろにある
+    pub pos: Pos,
+    // ifやmatchやtestの中で定義したものはfalse
+    pub top: bool,
+}
+
+// 変数は最初に代入した文で定義される. 型は代入した値から決める.
+// importしたファイルの中身は見ないので `math.x` の型はわからない
+pub struct Symbols {
+    pub list: Vec<Symbol>,
+    pub registry: Registry,
+}
+
+impl Symbols {
+    pub fn new(ast: &[Expr]) -> Symbols {
+        let mut symbols = Symbols { list: Vec::new(), registry: Registry::new() };
+        symbols.collect(ast, true);
+        symbols
+    }
+
+    // testの名前は文字列なので名前では引かない
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.list.iter().find(|s| s.name == name && s.kind != SymbolKind::Test)
+    }
+
+    fn define(&mut self, name: &str, kind: SymbolKind, detail: String, pos: Pos, top: bool) {
+        if self.get(name).is_none() {
+            self.list.push(Symbol { name: name.to_string(), kind, detail, pos, top });
+        }
+    }
+
+    fn collect(&mut self, list: &[Expr], top: bool) {
+        for expr in list.iter() {
+            match expr {
+                Expr::Assign(e) => {
+                    if let Expr::Ident(ident) = &e.left_expr {
+                        let type_name = self.type_of(&e.right_expr).unwrap_or_default();
+                        self.define(&ident.name, SymbolKind::Variable, type_name, e.pos, top);
+                    }
+                },
+                // 定義の誤りはtypes::checkが見つけるので, ここでは読めたものだけを使う
+                Expr::Struct(e) => {
+                    let _ = self.registry.define(e);
+                    self.define(&e.name, SymbolKind::Struct, expr.to_string(), e.pos, top);
+                },
+                Expr::Enum(e) => {
+                    let _ = self.registry.define_enum(e);
+                    self.define(&e.name, SymbolKind::Enum, expr.to_string(), e.pos, top);
+                },
+                Expr::Import(e) => {
+                    let namespace = std::path::Path::new(&e.path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+                    self.define(&namespace, SymbolKind::Import, expr.to_string(), e.pos, top);
+                },
+                Expr::Test(e) => {
+                    self.list.push(Symbol { name: e.name.clone(), kind: SymbolKind::Test, detail: format!("test \"{}\"", e.name), pos: e.pos, top });
+                    self.collect(&e.list, false);
+                },
+                Expr::If(e) => self.collect(&e.list, false),
+                Expr::ElseIf(e) => self.collect(&e.list, false),
+                Expr::Else(e) => self.collect(&e.list, false),
+                Expr::Match(e) => {
+                    for arm in e.arms.iter() {
+                        if let Some((name, variant)) = &arm.pattern {
+                            let types = self.registry.variant(name, variant).map(|(_, t)| t.clone()).unwrap_or_default();
+                            for (i, binding) in arm.bindings.iter().enumerate() {
+                                let type_name = types.get(i).cloned().unwrap_or_default();
+                                self.define(binding, SymbolKind::Variable, type_name, arm.pos, false);
+                            }
+                        }
+                        self.collect(&arm.list, false);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    // 式の型名. int, char, str, bool, vec, `[int; 3]`, structやenumの名前
+    pub fn type_of(&self, expr: &Expr) -> Option<String> {
+        let name = match expr {
+            Expr::Int(_) | Expr::Len(_) | Expr::Pop(_) | Expr::ToInt(_) => "int",
+            Expr::Char(_) => "char",
+            Expr::Str(_) | Expr::Substr(_) | Expr::ToStr(_) => "str",
+            Expr::Bool(_) | Expr::Equal(_) => "bool",
+            Expr::Vector(_) => "vec",
+            Expr::Array(e) => return Some(format!("[int; {}]", e.len)),
+            // 文字列の + は連結
+            Expr::BinaryOp(e) => match self.type_of(&e.left_expr).as_deref() {
+                Some("str") => "str",
+                _ => "int",
+            },
+            Expr::Index(e) => match self.type_of(&e.array).as_deref() {
+                Some("str") => "char",
+                _ => "int",
+            },
+            Expr::Input(e) => match e.kind {
+                Kind::Str => "str",
+                Kind::Char => "char",
+                _ => "int",
+            },
+            Expr::StructLit(e) => return Some(e.name.clone()),
+            Expr::EnumLit(e) => return Some(e.name.clone()),
+            Expr::Ident(e) => return self.get(&e.name).filter(|s| s.kind == SymbolKind::Variable && !s.detail.is_empty()).map(|s| s.detail.clone()),
+            Expr::Field(e) => {
+                // `Shape.Empty` は値を持たないvariant
+                if let Expr::Ident(object) = &e.object {
+                    if self.registry.variant(&object.name, &e.name).is_ok() {
+                        return Some(object.name.clone());
+                    }
+                }
+                let object = self.type_of(&e.object)?;
+                return self.registry.field(&object, &e.name).ok().map(|(_, t)| t.to_string());
+            },
+            _ => return None,
+        };
+        Some(name.to_string())
+    }
+}
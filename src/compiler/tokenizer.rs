@@ -1,6 +1,6 @@
 use std::str::Chars;
 use super::*;
-pub const KEY_WD_TBL: [KeyWd; 25] = [
+pub const KEY_WD_TBL: [KeyWd; 37] = [
     KeyWd{val: "(", kind: Kind::Lparen},
     KeyWd{val: ")", kind: Kind::Rparen},
     KeyWd{val: "{", kind: Kind::Lbrace},
@@ -16,16 +16,28 @@ pub const KEY_WD_TBL: [KeyWd; 25] = [
     KeyWd{val: ">", kind: Kind::Greater},
     KeyWd{val: ">=", kind: Kind::GreaterEq},
     KeyWd{val: "=", kind: Kind::Assign},
+    KeyWd{val: "=>", kind: Kind::Arrow},
     KeyWd{val: "+=", kind: Kind::Addasgn},
     KeyWd{val: "-=", kind: Kind::Mnuasgn},
     KeyWd{val: "*=", kind: Kind::Multiasgn},
     KeyWd{val: "/=", kind: Kind::Divasgn},
     KeyWd{val: ";", kind: Kind::Semicolon},
+    KeyWd{val: "[", kind: Kind::Lbracket},
+    KeyWd{val: "]", kind: Kind::Rbracket},
+    KeyWd{val: ",", kind: Kind::Comma},
+    KeyWd{val: ":", kind: Kind::Colon},
     KeyWd{val: "", kind: Kind::Endlist},
     KeyWd{val: "if", kind:Kind::If},
     KeyWd{val: "else", kind: Kind::Else},
     KeyWd{val:  "print", kind: Kind::Print},
+    KeyWd{val: "println", kind: Kind::Println},
     KeyWd{val: "nyaan", kind: Kind::Nyaan},
+    KeyWd{val: "import", kind: Kind::Import},
+    KeyWd{val: "let", kind: Kind::Let},
+    KeyWd{val: "struct", kind: Kind::Struct},
+    KeyWd{val: "enum", kind: Kind::Enum},
+    KeyWd{val: "match", kind: Kind::Match},
+    KeyWd{val: "return", kind: Kind::Return},
     ]; // todo
 
 pub fn init_ch_type() -> [Ch;256]{
@@ -60,36 +72,86 @@ pub fn init_ch_type() -> [Ch;256]{
 }
 
 
+// 読んでいる文字とその位置. 読み飛ばしたコメントと空行はtriviaにためる
+struct Source<'a, 'b> {
+    chars: &'a mut Chars<'b>,
+    pos: Pos,
+    newline: bool,
+    trivia: Vec<Trivia>,
+    started: bool,
+}
+
+pub fn tokenize(text: &mut Chars) -> Result<Vec<Token>, String> {
+    tokenize_with_trivia(text).map(|(tokens, _)| tokens)
+}
+
+// コメントと空行も位置つきで返す (nyaan fmt用)
 #[allow(while_true)]
-pub fn tokenize(text: &mut Chars) -> Vec<Token> {
+pub fn tokenize_with_trivia(text: &mut Chars) -> Result<(Vec<Token>, Vec<Trivia>), String> {
     let mut tkn_res = vec![];
     let ch_list:[Ch;256] = init_ch_type();
     let mut prev_ch = ' ';
+    let mut source = Source { chars: text, pos: Pos::new(1, 0), newline: false, trivia: Vec::new(), started: false };
+    let text = &mut source;
     while true {
-        tkn_res.push(next_tkn(text, &ch_list, &mut prev_ch));
+        match next_tkn(text, &ch_list, &mut prev_ch) {
+            Ok(token) => tkn_res.push(token),
+            Err(err) => return Err(error_at(err, text.pos)),
+        }
+        text.started = true;
         //println!("{:?}", tkn_res.last().unwrap().kind);
         if tkn_res.last().unwrap().kind == Kind::Endlist {
             break;
         }
     }
-    tkn_res
+    Ok((tkn_res, source.trivia))
 }
 
-fn next_tkn(text: &mut Chars, ch_list:&[Ch;256], prev_ch: &mut char) -> Token {
+fn next_tkn(text: &mut Source, ch_list:&[Ch;256], prev_ch: &mut char) -> Result<Token, String> {
     let mut ch:char = *prev_ch;
     let mut token:Token = Token::new();
-    while ch == ' ' || ch == '\n' {
+    let mut newlines = 0;
+    loop {
+        while ch == ' ' || ch == '\n' || ch == '\t' || ch == '\r' {
+            if ch == '\n' {
+                newlines += 1;
+                if newlines == 2 {
+                    text.trivia.push(Trivia::Blank(text.pos));
+                }
+            }
+            ch = next_ch(text);
+        }
+        if ch != '/' {
+            break;
+        }
+        // `//` から行末までがコメント. `/` が1つなら割り算
+        let pos = text.pos;
+        let nch = next_ch(text);
+        if nch != '/' {
+            *prev_ch = nch;
+            return Ok(Token { text: "".to_string(), chr: ' ', kind: Kind::Divi, val: 0, pos });
+        }
+        let mut s = String::new();
         ch = next_ch(text);
+        while ch != '\n' && ch != '\0' {
+            s.push(ch);
+            ch = next_ch(text);
+        }
+        let trailing = newlines == 0 && text.started;
+        text.trivia.push(Trivia::Comment { text: s.trim_end().to_string(), pos, trailing });
+        newlines = 0;
     }
 
+    token.pos = text.pos;
     if ch == '\0' {
-        return Token { text: "".to_string(), chr:' ', kind: Kind::Endlist, val: 0 }
+        return Ok(Token { text: "".to_string(), chr:' ', kind: Kind::Endlist, val: 0, pos: text.pos })
     }
     //println!("{}", ch);
-    match ch_list[ch as usize] {
+    match ch_type(ch_list, ch) {
         Ch::Letter => {
             let mut s: String = "".to_string();
-            while ch_list[ch as usize] == Ch::Letter || ch_list[ch as usize] == Ch::Digit {
+            // `math.pi` のようにimportしたファイルの変数を指す名前も1つのIdentにする
+            while ch_type(ch_list, ch) == Ch::Letter || ch_type(ch_list, ch) == Ch::Digit || ch == '.' {
                 s = s + &ch.to_string();
                 ch = next_ch(text);
                 // todo 文字数制限
@@ -110,19 +172,24 @@ fn next_tkn(text: &mut Chars, ch_list:&[Ch;256], prev_ch: &mut char) -> Token {
 
         Ch::Digit => {
             let mut s:String = "".to_string();
-            while ch_list[ch as usize] == Ch::Digit {
+            while ch_type(ch_list, ch) == Ch::Digit {
                 s = s + &ch.to_string();
                 ch = next_ch(text);
                 // todo 文字数制限
             }
-            if ch_list[ch as usize] == Ch::Letter { parse_error("error at: Digit ".to_string() + &ch.to_string()); }
+            if ch_type(ch_list, ch) == Ch::Letter { return Err("invalid number: unexpected ".to_string() + &ch.to_string()); }
             token.kind = Kind::Int;
-            token.val = s.parse().unwrap();
+            token.val = match s.parse() {
+                Ok(v) => v,
+                Err(_) => return Err(format!("integer literal {} is too large ({}:{})", s, token.pos.line, token.pos.col)),
+            };
             *prev_ch = ch;
         },
         Ch::SngQ => {
             let c: char = next_ch(text);
-            if next_ch(text) != '\'' { parse_error("error at: SngQ ".to_string() + &ch.to_string()); }
+            if next_ch(text) != '\'' { return Err("' is missing for char literal".to_string()); }
+            // charは1バイト. ASCIIでない文字は文字列に入れる
+            if !c.is_ascii() { return Err(format!("char literal '{}' is not ASCII; use a string ({}:{})", c, token.pos.line, token.pos.col)); }
             token.kind = Kind::Char;
             token.chr = c;
             *prev_ch = next_ch(text);
@@ -134,7 +201,7 @@ fn next_tkn(text: &mut Chars, ch_list:&[Ch;256], prev_ch: &mut char) -> Token {
             while ch != '"' {
                 s = s + &ch.to_string();
                 ch = next_ch(text);
-                if ch == '\0' { parse_error("error at: DblQ ".to_string() + &ch.to_string());  }
+                if ch == '\0' { return Err("\" is missing for string literal".to_string());  }
             }
             token.kind = Kind::Str;
             token.text = s;
@@ -147,6 +214,9 @@ fn next_tkn(text: &mut Chars, ch_list:&[Ch;256], prev_ch: &mut char) -> Token {
             if ch == '=' && nch == '=' {
                 token.kind = Kind::Equal;
             }
+            else if ch == '=' && nch == '>' {
+                token.kind = Kind::Arrow;
+            }
             else if ch == '!' && nch == '=' {
                 token.kind = Kind::NotEq;
             }
@@ -177,7 +247,7 @@ fn next_tkn(text: &mut Chars, ch_list:&[Ch;256], prev_ch: &mut char) -> Token {
                     }
                 }
                 *prev_ch = nch;
-                return token;
+                return Ok(token);
             }
             *prev_ch = next_ch(text);
         }
@@ -191,43 +261,27 @@ fn next_tkn(text: &mut Chars, ch_list:&[Ch;256], prev_ch: &mut char) -> Token {
             *prev_ch = next_ch(text);
         }
     }
-    token
+    Ok(token)
 }
 
 
 
-fn next_ch(text: &mut Chars) -> char{
-    let ch: char = match text.next() {
+// ASCIIでない文字はどの記号にもならない
+fn ch_type(ch_list: &[Ch;256], ch: char) -> Ch {
+    if ch.is_ascii() { ch_list[ch as usize] } else { Ch::Others }
+}
+
+fn next_ch(text: &mut Source) -> char{
+    let ch: char = match text.chars.next() {
         None => return '\0',
         Some(h) => h
     };
-    // todo commentを処理したい.....
-    /*
-    if ch == '/'  {
-
-        println!("{}", nch);
-        if nch == '/' {
-            let mut nnch = match text.next() {
-                None => return '\0',
-                Some(h) => h,
-            };
-            while nnch != '\n' {
-                nnch = match text.next() {
-                    None => return '\0',
-                    Some(h) => h,
-                }
-            }
-            return nnch;
-        } else {
-            //parse_error("error at: comment ".to_string() + &ch.to_string());
-        }
+    if text.newline {
+        text.pos.line += 1;
+        text.pos.col = 1;
+    } else {
+        text.pos.col += 1;
     }
-    */
+    text.newline = ch == '\n';
     ch
 }
-
-fn parse_error(message: String) {
-    println!("{}", message);
-    println!("NG");
-    std::process::exit(1);
-}
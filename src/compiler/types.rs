@@ -0,0 +1,216 @@
+use super::*;
+use std::collections::{HashMap, HashSet};
+
+// structとenumの定義を名前から引けるようにしておく. generateとinterpの両方が使う
+#[derive(Default)]
+pub struct Registry {
+    structs: HashMap<String, StructDef>,
+    enums: HashMap<String, EnumDef>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry { structs: HashMap::new(), enums: HashMap::new() }
+    }
+
+    pub fn define(&mut self, def: &StructDef) -> Result<(), String> {
+        if self.structs.contains_key(&def.name) || self.enums.contains_key(&def.name) {
+            return Err(format!("struct {} is already defined ({}:{})", def.name, def.pos.line, def.pos.col));
+        }
+        if kind_of_type(&def.name) != Kind::Struct {
+            return Err(format!("{} is a builtin type and cannot be used as a struct name", def.name));
+        }
+        if def.fields.is_empty() {
+            return Err(format!("struct {} has no fields ({}:{})", def.name, def.pos.line, def.pos.col));
+        }
+        for (i, (name, type_name)) in def.fields.iter().enumerate() {
+            if def.fields[..i].iter().any(|(n, _)| n == name) {
+                return Err(format!("field {} is declared twice in struct {}", name, def.name));
+            }
+            // 自分自身を含むstructは大きさが決まらないので, 先に定義したstructだけを使える
+            if kind_of_type(type_name) == Kind::Struct && !self.structs.contains_key(type_name) {
+                return Err(format!("unknown type {} for field {}.{}", type_name, def.name, name));
+            }
+        }
+        self.structs.insert(def.name.clone(), def.clone());
+        Ok(())
+    }
+
+    pub fn define_enum(&mut self, def: &EnumDef) -> Result<(), String> {
+        if self.structs.contains_key(&def.name) || self.enums.contains_key(&def.name) {
+            return Err(format!("type {} is already defined ({}:{})", def.name, def.pos.line, def.pos.col));
+        }
+        if kind_of_type(&def.name) != Kind::Struct {
+            return Err(format!("{} is a builtin type and cannot be used as an enum name", def.name));
+        }
+        if def.variants.is_empty() {
+            return Err(format!("enum {} has no variants ({}:{})", def.name, def.pos.line, def.pos.col));
+        }
+        for (i, (name, types)) in def.variants.iter().enumerate() {
+            if def.variants[..i].iter().any(|(n, _)| n == name) {
+                return Err(format!("variant {} is declared twice in enum {}", name, def.name));
+            }
+            // enumの値はenumの中に入れられない
+            for type_name in types.iter() {
+                if kind_of_type(type_name) == Kind::Struct && !self.structs.contains_key(type_name) {
+                    return Err(format!("unknown type {} in {}.{}", type_name, def.name, name));
+                }
+            }
+        }
+        self.enums.insert(def.name.clone(), def.clone());
+        Ok(())
+    }
+
+    pub fn get_enum(&self, name: &str) -> Result<&EnumDef, String> {
+        match self.enums.get(name) {
+            None => Err(format!("unknown enum {}", name)),
+            Some(def) => Ok(def),
+        }
+    }
+
+    // variantの番号 (tag) と持つ値の型名
+    pub fn variant(&self, enum_name: &str, variant: &str) -> Result<(usize, &Vec<String>), String> {
+        let def = self.get_enum(enum_name)?;
+        match def.variants.iter().position(|(n, _)| n == variant) {
+            None => Err(format!("enum {} has no variant {}", enum_name, variant)),
+            Some(i) => Ok((i, &def.variants[i].1)),
+        }
+    }
+
+    // 腕のパターンを調べ, すべてのvariantを扱っているか確かめる. matchするenumの名前を返す
+    pub fn check_match(&self, m: &Match) -> Result<String, String> {
+        let at = format!("{}:{}", m.pos.line, m.pos.col);
+        let mut enum_name: Option<&str> = None;
+        let mut covered = HashSet::new();
+        let mut wildcard = false;
+        for arm in m.arms.iter() {
+            if wildcard {
+                return Err(format!("unreachable match arm after `_` ({}:{})", arm.pos.line, arm.pos.col));
+            }
+            let (name, variant) = match &arm.pattern {
+                None => {
+                    if !arm.bindings.is_empty() {
+                        return Err(format!("`_` cannot bind values ({}:{})", arm.pos.line, arm.pos.col));
+                    }
+                    wildcard = true;
+                    continue;
+                },
+                Some(p) => p,
+            };
+            match enum_name {
+                Some(n) if n != name => return Err(format!("pattern {}.{} does not match enum {} ({}:{})", name, variant, n, arm.pos.line, arm.pos.col)),
+                _ => enum_name = Some(name),
+            }
+            let (_, types) = self.variant(name, variant)?;
+            if types.len() != arm.bindings.len() {
+                return Err(format!("{}.{} has {} value(s) but the pattern binds {} ({}:{})", name, variant, types.len(), arm.bindings.len(), arm.pos.line, arm.pos.col));
+            }
+            if !covered.insert(variant.clone()) {
+                return Err(format!("{}.{} is matched twice ({}:{})", name, variant, arm.pos.line, arm.pos.col));
+            }
+        }
+        let name = match enum_name {
+            None => return Err(format!("match needs at least one enum pattern ({})", at)),
+            Some(n) => n,
+        };
+        if !wildcard {
+            let missing: Vec<String> = self.get_enum(name)?.variants.iter()
+                .filter(|(v, _)| !covered.contains(v))
+                .map(|(v, _)| format!("{}.{}", name, v))
+                .collect();
+            if !missing.is_empty() {
+                return Err(format!("match is not exhaustive: {} not covered ({})", missing.join(", "), at));
+            }
+        }
+        Ok(name.to_string())
+    }
+
+    // 型名がenumならKind::Enum, それ以外はkind_of_typeと同じ
+    #[cfg(feature = "llvm")]
+    pub fn kind_of(&self, type_name: &str) -> Kind {
+        if self.enums.contains_key(type_name) {
+            return Kind::Enum;
+        }
+        kind_of_type(type_name)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&StructDef, String> {
+        match self.structs.get(name) {
+            None => Err(format!("unknown struct {}", name)),
+            Some(def) => Ok(def),
+        }
+    }
+
+    // フィールドの番号と型名
+    pub fn field(&self, struct_name: &str, field: &str) -> Result<(usize, &str), String> {
+        let def = self.get(struct_name)?;
+        match def.fields.iter().position(|(n, _)| n == field) {
+            None => Err(format!("struct {} has no field {}", struct_name, field)),
+            Some(i) => Ok((i, &def.fields[i].1)),
+        }
+    }
+
+    // リテラルにすべてのフィールドがちょうど1回ずつ書かれているか調べる.
+    // 値は定義の順に並べて返す
+    pub fn check_literal<'a>(&self, lit: &'a StructLit) -> Result<Vec<&'a Expr>, String> {
+        let def = self.get(&lit.name)?;
+        for (name, _) in lit.fields.iter() {
+            if def.fields.iter().all(|(n, _)| n != name) {
+                return Err(format!("struct {} has no field {} ({}:{})", lit.name, name, lit.pos.line, lit.pos.col));
+            }
+        }
+        let mut values = Vec::new();
+        for (name, _) in def.fields.iter() {
+            let mut found = lit.fields.iter().filter(|(n, _)| n == name);
+            match (found.next(), found.next()) {
+                (Some((_, val)), None) => values.push(val),
+                (None, _) => return Err(format!("missing field {} in {} literal ({}:{})", name, lit.name, lit.pos.line, lit.pos.col)),
+                (Some(_), Some(_)) => return Err(format!("field {} is given twice ({}:{})", name, lit.pos.line, lit.pos.col)),
+            }
+        }
+        Ok(values)
+    }
+}
+
+// フィールドの型名. int, char, str 以外はstructの名前
+pub fn kind_of_type(type_name: &str) -> Kind {
+    match type_name {
+        "int" => Kind::Int,
+        "char" => Kind::Char,
+        "str" => Kind::Str,
+        _ => Kind::Struct,
+    }
+}
+
+// 実行する前にmatchが網羅的かを調べる. インタプリタでも通らない腕のエラーを見逃さない.
+// 調べるのは型の定義とmatchだけで, 式の型は調べない. `p + 1` のような型の誤りは
+// LLVM側ならgenerateで, インタプリタならその文を実行したときに見つかる.
+// todo 式の型検査. 関数ができたらstructを引数と戻り値に使えるようにする
+pub fn check(ast: &[Expr]) -> Result<(), String> {
+    check_list(&mut Registry::new(), ast, true)
+}
+
+// 型の定義はどの道を通っても同じになるように一番外側にだけ書ける
+fn check_list(registry: &mut Registry, list: &[Expr], top: bool) -> Result<(), String> {
+    for expr in list.iter() {
+        match expr {
+            Expr::Struct(e) if !top => return Err(format!("struct {} must be defined at the top level ({}:{})", e.name, e.pos.line, e.pos.col)),
+            Expr::Enum(e) if !top => return Err(format!("enum {} must be defined at the top level ({}:{})", e.name, e.pos.line, e.pos.col)),
+            Expr::Test(e) if !top => return Err(format!("test \"{}\" must be at the top level ({}:{})", e.name, e.pos.line, e.pos.col)),
+            Expr::Test(e) => check_list(registry, &e.list, false)?,
+            Expr::Struct(e) => registry.define(e)?,
+            Expr::Enum(e) => registry.define_enum(e)?,
+            Expr::Match(e) => {
+                registry.check_match(e)?;
+                for arm in e.arms.iter() {
+                    check_list(registry, &arm.list, false)?;
+                }
+            },
+            Expr::If(e) => check_list(registry, &e.list, false)?,
+            Expr::ElseIf(e) => check_list(registry, &e.list, false)?,
+            Expr::Else(e) => check_list(registry, &e.list, false)?,
+            _ => {},
+        }
+    }
+    Ok(())
+}
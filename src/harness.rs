@@ -0,0 +1,193 @@
+use crate::cli::{self, Backend, Options};
+use crate::compiler::*;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{self, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// nyaan test: testごとに `nyaan run --test 名前` を別のプロセスで実行する.
+// panicやexitで止まっても他のtestには影響しない
+
+struct Outcome {
+    name: String,
+    passed: bool,
+    time: Duration,
+    // 失敗したときに出す標準出力と標準エラー
+    output: String,
+}
+
+pub fn names(ast: &[Expr]) -> Vec<String> {
+    ast.iter().filter_map(|e| match e {
+        Expr::Test(t) => Some(t.name.clone()),
+        _ => None,
+    }).collect()
+}
+
+// testの外の文を実行してから, 選んだtestの中身を実行するプログラムにする
+pub fn select(ast: &[Expr], name: &str) -> Result<Vec<Expr>, String> {
+    let body = match ast.iter().find_map(|e| match e {
+        Expr::Test(t) if t.name == name => Some(t.list.clone()),
+        _ => None,
+    }) {
+        None => return Err(format!("no test named \"{}\"", name)),
+        Some(list) => list,
+    };
+    let mut list: Vec<Expr> = ast.iter().filter(|e| !matches!(e, Expr::Test(_))).cloned().collect();
+    list.extend(body);
+    Ok(list)
+}
+
+// ファイルごとのtestを実行して結果を出す. すべて通ればtrue
+pub fn run(options: &Options, suites: &[(String, Vec<String>)]) -> bool {
+    let exe = match env::current_exe() {
+        Ok(p) => p,
+        Err(err) => {
+            eprintln!("error: cannot find the nyaan executable: {}", err);
+            process::exit(cli::EXIT_FAILURE);
+        }
+    };
+    let start = Instant::now();
+    let mut results: Vec<(String, Vec<Outcome>)> = Vec::new();
+    let mut filtered = 0;
+    for (input, names) in suites.iter() {
+        if let Some(name) = names.iter().enumerate().find_map(|(i, n)| if names[..i].contains(n) { Some(n) } else { None }) {
+            eprintln!("error: {}: test \"{}\" is defined twice", input, name);
+            process::exit(cli::EXIT_FAILURE);
+        }
+        let selected: Vec<&String> = names.iter().filter(|n| match &options.filter {
+            None => true,
+            Some(f) => n.contains(f.as_str()),
+        }).collect();
+        filtered += names.len() - selected.len();
+
+        println!("running {} tests in {}", selected.len(), input);
+        let mut outcomes = Vec::new();
+        for name in selected {
+            let outcome = run_one(&exe, options, input, name);
+            println!("test {} ... {} ({})", name, if outcome.passed { "ok" } else { "FAILED" }, seconds(outcome.time));
+            outcomes.push(outcome);
+        }
+        println!();
+        results.push((input.clone(), outcomes));
+    }
+
+    let failures: Vec<&Outcome> = results.iter().flat_map(|(_, o)| o.iter()).filter(|o| !o.passed).collect();
+    let passed = results.iter().map(|(_, o)| o.len()).sum::<usize>() - failures.len();
+    if !failures.is_empty() {
+        println!("failures:");
+        for outcome in failures.iter() {
+            println!();
+            println!("---- {} ----", outcome.name);
+            print!("{}", outcome.output);
+        }
+        println!();
+    }
+    println!("test result: {}. {} passed; {} failed; {} filtered out; finished in {}",
+        if failures.is_empty() { "ok" } else { "FAILED" }, passed, failures.len(), filtered, seconds(start.elapsed()));
+
+    if let Some(path) = &options.junit {
+        if let Err(err) = fs::write(path, junit(&results)) {
+            eprintln!("error: {}: {}", path, err);
+            process::exit(cli::EXIT_FAILURE);
+        }
+    }
+    failures.is_empty()
+}
+
+fn run_one(exe: &Path, options: &Options, input: &str, name: &str) -> Outcome {
+    let backend = match options.backend {
+        Backend::Llvm => "--backend=llvm",
+        Backend::Interp => "--backend=interp",
+    };
+    let level = if options.config.size_level > 0 { "s".to_string() } else { options.config.opt_level.to_string() };
+    let mut command = process::Command::new(exe);
+    command.arg("run").arg(backend).arg(format!("-O{}", level)).arg("--test").arg(name).arg(input);
+    // 入力を待って止まらないようにする
+    command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    let start = Instant::now();
+    let mut child = match command.spawn() {
+        Ok(c) => c,
+        Err(err) => return Outcome { name: name.to_string(), passed: false, time: start.elapsed(), output: format!("cannot run {}: {}\n", exe.display(), err) },
+    };
+    // 出力が多くてもパイプが詰まらないように, 待っている間も別のスレッドで読む
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if start.elapsed() < options.timeout => thread::sleep(Duration::from_millis(5)),
+            // 時間切れか待てなくなったら止める
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            },
+        }
+    };
+    let time = start.elapsed();
+    let mut output = String::from_utf8_lossy(&stdout.join().unwrap_or_default()).to_string();
+    output.push_str(&String::from_utf8_lossy(&stderr.join().unwrap_or_default()));
+    // panicならメッセージでわかるので, それ以外で失敗したときだけ理由を足す
+    let passed = match status {
+        None => {
+            output.push_str(&format!("the test timed out after {}\n", seconds(options.timeout)));
+            false
+        },
+        Some(status) => {
+            match status.code() {
+                Some(0) | Some(PANIC_EXIT_CODE) => {},
+                Some(code) => output.push_str(&format!("the test exited with status {}\n", code)),
+                None => output.push_str("the test was killed by a signal\n"),
+            }
+            status.success()
+        },
+    };
+    Outcome { name: name.to_string(), passed, time, output }
+}
+
+fn read_all<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+fn seconds(time: Duration) -> String {
+    format!("{:.2}s", time.as_secs_f64())
+}
+
+// CIで読めるようにJUnitのXMLにする. ファイルごとに1つのtestsuite
+fn junit(results: &[(String, Vec<Outcome>)]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    for (input, outcomes) in results.iter() {
+        let failures = outcomes.iter().filter(|o| !o.passed).count();
+        let time: f64 = outcomes.iter().map(|o| o.time.as_secs_f64()).sum();
+        xml.push_str(&format!("  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n", escape(input), outcomes.len(), failures, time));
+        for outcome in outcomes.iter() {
+            xml.push_str(&format!("    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"", escape(&outcome.name), escape(input), outcome.time.as_secs_f64()));
+            if outcome.passed {
+                xml.push_str("/>\n");
+                continue;
+            }
+            // panicの行がなければ最後の行を使う
+            let message = outcome.output.lines().find(|l| l.starts_with("panicked at"))
+                .or_else(|| outcome.output.lines().last())
+                .unwrap_or("failed");
+            xml.push_str(&format!(">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n", escape(message), escape(&outcome.output)));
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+
+// nyaan lspのJSON-RPCで使う小さなJSON. オブジェクトはキーを書いた順に持つ
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { chars: text.chars().collect(), i: 0 };
+        let value = parser.value(0)?;
+        parser.skip_space();
+        if parser.i < parser.chars.len() {
+            return Err(format!("unexpected {:?} after JSON value (offset {})", parser.chars[parser.i], parser.i));
+        }
+        Ok(value)
+    }
+
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn str(text: &str) -> Json {
+        Json::Str(text.to_string())
+    }
+
+    pub fn int(n: i64) -> Json {
+        Json::Number(n as f64)
+    }
+
+    // オブジェクトのメンバー. オブジェクトでなければNone
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // `a.b.c` のようにたどる
+    pub fn path(&self, path: &str) -> Option<&Json> {
+        path.split('.').try_fold(self, |json, key| match key.parse::<usize>() {
+            Ok(i) if matches!(json, Json::Array(_)) => json.as_array()?.get(i),
+            _ => json.get(key),
+        })
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(n) if n.fract() == 0.0 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(list) => Some(list),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Json::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Json::Number(_) => f.write_str("null"),
+            Json::Str(s) => write_str(f, s),
+            Json::Array(list) => {
+                f.write_str("[")?;
+                for (i, v) in list.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                f.write_str("]")
+            },
+            Json::Object(members) => {
+                f.write_str("{")?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_str(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                f.write_str("}")
+            },
+        }
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+// 入れ子が深すぎるとスタックがあふれるので制限する
+const MAX_DEPTH: usize = 128;
+
+struct Parser {
+    chars: Vec<char>,
+    i: usize,
+}
+
+impl Parser {
+    fn skip_space(&mut self) {
+        while self.i < self.chars.len() && matches!(self.chars[self.i], ' ' | '\t' | '\n' | '\r') {
+            self.i += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.i).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_space();
+        match self.peek() {
+            Some(p) if p == c => { self.i += 1; Ok(()) },
+            Some(p) => Err(format!("expected {:?} but found {:?} (offset {})", c, p, self.i)),
+            None => Err(format!("expected {:?} but the JSON ended", c)),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_DEPTH {
+            return Err("JSON is nested too deeply".to_string());
+        }
+        self.skip_space();
+        match self.peek() {
+            None => Err("JSON value is missing".to_string()),
+            Some('{') => {
+                self.i += 1;
+                let mut members = Vec::new();
+                self.skip_space();
+                if self.peek() == Some('}') {
+                    self.i += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_space();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    members.push((key, self.value(depth + 1)?));
+                    self.skip_space();
+                    match self.peek() {
+                        Some(',') => self.i += 1,
+                        Some('}') => { self.i += 1; return Ok(Json::Object(members)); },
+                        _ => return Err(format!(", or }} is missing in object (offset {})", self.i)),
+                    }
+                }
+            },
+            Some('[') => {
+                self.i += 1;
+                let mut list = Vec::new();
+                self.skip_space();
+                if self.peek() == Some(']') {
+                    self.i += 1;
+                    return Ok(Json::Array(list));
+                }
+                loop {
+                    list.push(self.value(depth + 1)?);
+                    self.skip_space();
+                    match self.peek() {
+                        Some(',') => self.i += 1,
+                        Some(']') => { self.i += 1; return Ok(Json::Array(list)); },
+                        _ => return Err(format!(", or ] is missing in array (offset {})", self.i)),
+                    }
+                }
+            },
+            Some('"') => Ok(Json::Str(self.string()?)),
+            Some('t') => self.word("true", Json::Bool(true)),
+            Some('f') => self.word("false", Json::Bool(false)),
+            Some('n') => self.word("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(format!("unexpected {:?} in JSON (offset {})", c, self.i)),
+        }
+    }
+
+    fn word(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        let end = self.i + word.len();
+        if end <= self.chars.len() && self.chars[self.i..end].iter().copied().eq(word.chars()) {
+            self.i = end;
+            return Ok(value);
+        }
+        Err(format!("invalid literal in JSON (offset {})", self.i))
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.i;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+            self.i += 1;
+        }
+        let text: String = self.chars[start..self.i].iter().collect();
+        match text.parse::<f64>() {
+            Ok(n) => Ok(Json::Number(n)),
+            Err(_) => Err(format!("invalid number {} in JSON", text)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.peek() != Some('"') {
+            return Err(format!("string is missing in JSON (offset {})", self.i));
+        }
+        self.i += 1;
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                None => return Err("\" is missing for JSON string".to_string()),
+                Some(c) => c,
+            };
+            self.i += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = self.peek().ok_or("escape is missing in JSON string")?;
+                    self.i += 1;
+                    match escape {
+                        '"' | '\\' | '/' => s.push(escape),
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // サロゲートペアは2つで1文字
+                            if (0xd800..0xdc00).contains(&code) && self.chars.get(self.i..self.i + 2) == Some(&['\\', 'u']) {
+                                self.i += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            s.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                        },
+                        c => return Err(format!("invalid escape \\{} in JSON string", c)),
+                    }
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits: String = self.chars.get(self.i..self.i + 4).ok_or("\\u needs 4 hex digits")?.iter().collect();
+        self.i += 4;
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("invalid \\u{} in JSON string", digits))
+    }
+}
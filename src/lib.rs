@@ -0,0 +1,4 @@
+// コンパイラ本体. nyaanコマンド(main.rs)とfuzz/から使う
+pub mod compiler;
+// nyaan lspとtests/lsp.rsで使う
+pub mod json;
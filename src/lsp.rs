@@ -0,0 +1,652 @@
+use crate::cli;
+use crate::compiler::*;
+use crate::compiler::symbols::{Symbol, SymbolKind, Symbols};
+use nyaan::json::Json;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+// nyaan lsp: 標準入出力でLanguage Server Protocolを話す.
+// 文書は変更のたびに全文を受け取り, コンパイルと同じ順に調べて診断を送り直す
+
+// JSON-RPCのエラーコード
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+// LSPのSymbolKind
+const SYMBOL_MODULE: i64 = 2;
+const SYMBOL_FIELD: i64 = 8;
+const SYMBOL_ENUM: i64 = 10;
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+const SYMBOL_ENUM_MEMBER: i64 = 22;
+const SYMBOL_STRUCT: i64 = 23;
+
+type Failure = (i64, String);
+
+// 終了コードを返す. shutdownの後のexitなら0
+pub fn start() -> i32 {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut stdout = io::stdout();
+    let mut server = Server { documents: HashMap::new(), shutdown: false, outgoing: Vec::new() };
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(m)) => m,
+            Ok(None) => return cli::EXIT_FAILURE,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                return cli::EXIT_FAILURE;
+            }
+        };
+        let exit = server.handle(&message);
+        for json in server.outgoing.drain(..) {
+            let body = json.to_string();
+            let written = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body).and_then(|_| stdout.flush());
+            if written.is_err() {
+                return cli::EXIT_FAILURE;
+            }
+        }
+        if let Some(code) = exit {
+            return code;
+        }
+    }
+}
+
+// `Content-Length: n` のヘッダーと空行の後にn バイトの本文. 入力が終わればNone
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            match length {
+                Some(_) => break,
+                None => continue,
+            }
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = match value.trim().parse() {
+                    Ok(n) => Some(n),
+                    Err(_) => return Err(format!("invalid Content-Length: {}", value.trim())),
+                };
+            }
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body).map_err(|err| err.to_string())?;
+    match String::from_utf8(body) {
+        Ok(s) => Ok(Some(s)),
+        Err(_) => Err("message is not UTF-8".to_string()),
+    }
+}
+
+struct Server {
+    // URIごとの今の全文
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    // 送るメッセージ. handleの後にまとめて書き出す
+    outgoing: Vec<Json>,
+}
+
+impl Server {
+    // exitを受け取ったら終了コードを返す
+    fn handle(&mut self, message: &str) -> Option<i32> {
+        let json = match Json::parse(message) {
+            Ok(j) => j,
+            Err(err) => {
+                self.reply(Json::Null, Err((PARSE_ERROR, err)));
+                return None;
+            }
+        };
+        let params = json.get("params").cloned().unwrap_or(Json::Null);
+        let method = match json.get("method").and_then(|m| m.as_str()) {
+            Some(m) => m,
+            // クライアントからの応答. こちらからは何も頼まないので捨てる
+            None if json.get("id").is_some() => return None,
+            None => {
+                self.reply(Json::Null, Err((INVALID_REQUEST, "method is missing".to_string())));
+                return None;
+            }
+        };
+        match json.get("id") {
+            Some(id) => {
+                let result = match self.shutdown {
+                    true => Err((INVALID_REQUEST, "the server is shut down".to_string())),
+                    false => self.request(method, &params),
+                };
+                self.reply(id.clone(), result);
+                None
+            },
+            None => self.notify(method, &params),
+        }
+    }
+
+    fn reply(&mut self, id: Json, result: Result<Json, Failure>) {
+        let outcome = match result {
+            Ok(r) => ("result", r),
+            Err((code, message)) => ("error", Json::object(vec![("code", Json::int(code)), ("message", Json::Str(message))])),
+        };
+        self.outgoing.push(Json::object(vec![("jsonrpc", Json::str("2.0")), ("id", id), outcome]));
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, Failure> {
+        match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            },
+            "textDocument/hover" => {
+                let (file, pos) = self.cursor(params)?;
+                Ok(match file.as_ref().and_then(|f| self.find(f, pos)) {
+                    None => Json::Null,
+                    Some(found) => Json::object(vec![
+                        ("contents", Json::object(vec![("kind", Json::str("markdown")), ("value", Json::Str(format!("```nyaan\n{}\n```", found.text)))])),
+                        ("range", found.range),
+                    ]),
+                })
+            },
+            "textDocument/definition" => {
+                let (file, pos) = self.cursor(params)?;
+                Ok(file.as_ref().and_then(|f| self.find(f, pos)).and_then(|found| found.definition).unwrap_or(Json::Null))
+            },
+            "textDocument/documentSymbol" => {
+                let (uri, text) = self.document(params)?;
+                Ok(match File::parse(uri, text) {
+                    None => Json::Array(Vec::new()),
+                    Some(file) => Json::Array(file.symbols.list.iter().filter(|s| s.top).map(|s| file.document_symbol(s)).collect()),
+                })
+            },
+            "textDocument/formatting" => {
+                let (_, text) = self.document(params)?;
+                let formatted = format::format_source(text).map_err(|err| (REQUEST_FAILED, err))?;
+                if formatted == *text {
+                    return Ok(Json::Array(Vec::new()));
+                }
+                let lines: Vec<&str> = text.split('\n').collect();
+                let last = lines.len() - 1;
+                let end = Json::object(vec![("line", Json::int(last as i64)), ("character", Json::int(utf16_len(lines[last]) as i64))]);
+                let range = Json::object(vec![("start", position(0, 0)), ("end", end)]);
+                Ok(Json::Array(vec![Json::object(vec![("range", range), ("newText", Json::Str(formatted))])]))
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) -> Option<i32> {
+        let uri = params.path("textDocument.uri").and_then(|u| u.as_str()).unwrap_or("").to_string();
+        match method {
+            "exit" => return Some(if self.shutdown { 0 } else { cli::EXIT_FAILURE }),
+            "textDocument/didOpen" => {
+                let text = params.path("textDocument.text").and_then(|t| t.as_str()).unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                self.publish(&uri);
+            },
+            // 全文を送ってもらうので最後の変更だけを見る
+            "textDocument/didChange" => {
+                let change = params.get("contentChanges").and_then(|c| c.as_array()).and_then(|c| c.last());
+                if let Some(text) = change.and_then(|c| c.get("text")).and_then(|t| t.as_str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                    self.publish(&uri);
+                }
+            },
+            // 保存したファイルを他の文書がimportしているかもしれない
+            "textDocument/didSave" => {
+                let mut uris: Vec<String> = self.documents.keys().cloned().collect();
+                uris.sort();
+                for uri in uris.iter() {
+                    self.publish(uri);
+                }
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.outgoing.push(diagnostics(&uri, Vec::new()));
+            },
+            _ => {},
+        }
+        None
+    }
+
+    fn document<'a>(&'a self, params: &'a Json) -> Result<(&'a str, &'a String), Failure> {
+        let uri = match params.path("textDocument.uri").and_then(|u| u.as_str()) {
+            None => return Err((INVALID_PARAMS, "textDocument.uri is missing".to_string())),
+            Some(u) => u,
+        };
+        match self.documents.get(uri) {
+            None => Err((INVALID_PARAMS, format!("{} is not open", uri))),
+            Some(text) => Ok((uri, text)),
+        }
+    }
+
+    // 文書を読み, カーソルの位置を1始まりのPosにする. 読めない文書ならNone
+    fn cursor(&self, params: &Json) -> Result<(Option<File>, Pos), Failure> {
+        let (uri, text) = self.document(params)?;
+        let line = params.path("position.line").and_then(|l| l.as_i64());
+        let character = params.path("position.character").and_then(|c| c.as_i64());
+        let (line, character) = match (line, character) {
+            (Some(l), Some(c)) if l >= 0 && c >= 0 => (l as usize, c as usize),
+            _ => return Err((INVALID_PARAMS, "position is missing".to_string())),
+        };
+        let col = text.split('\n').nth(line).map(|l| char_index(l, character)).unwrap_or(0);
+        Ok((File::parse(uri, text), Pos::new(line as u32 + 1, col as u32 + 1)))
+    }
+
+    fn publish(&mut self, uri: &str) {
+        let text = match self.documents.get(uri) {
+            None => return,
+            Some(t) => t,
+        };
+        let list = match check(uri, text) {
+            Ok(()) => Vec::new(),
+            Err((message, pos)) => {
+                let pos = pos.unwrap_or(Pos::new(1, 1));
+                let line = text.split('\n').nth(pos.line as usize - 1).unwrap_or("");
+                // 位置は範囲で示すのでメッセージからは外す
+                let suffix = format!(" ({}:{})", pos.line, pos.col);
+                let message = message.strip_suffix(&suffix).unwrap_or(&message).to_string();
+                vec![Json::object(vec![
+                    ("range", range(line, pos, word_len(line, pos.col))),
+                    ("severity", Json::int(1)),
+                    ("source", Json::str("nyaan")),
+                    ("message", Json::Str(message)),
+                ])]
+            },
+        };
+        self.outgoing.push(diagnostics(uri, list));
+    }
+
+    // カーソルの下の名前の定義を探す
+    fn find(&self, file: &File, pos: Pos) -> Option<Found> {
+        let i = file.tokens.iter().position(|t| {
+            t.kind == Kind::Ident && t.pos.line == pos.line && t.pos.col <= pos.col && pos.col <= t.pos.col + t.text.chars().count() as u32
+        })?;
+        let token = &file.tokens[i];
+        let mut path: Vec<&str> = Vec::new();
+        let mut start = 0;
+        for name in token.text.split('.') {
+            path.push(name);
+            let len = name.chars().count() as u32;
+            if pos.col <= token.pos.col + start + len {
+                break;
+            }
+            start += len + 1;
+        }
+        let name = *path.last()?;
+        let name_pos = Pos::new(token.pos.line, token.pos.col + start);
+        let owner = if token.text.contains('.') { None } else { member_of(&file.tokens, i) };
+        let path = match &owner {
+            Some(o) => vec![o.as_str(), name],
+            None => path,
+        };
+        let mut found = self.lookup(file, &path, "")?;
+        found.range = file.range(name_pos, name.chars().count());
+        Some(found)
+    }
+
+    // `p.home.x` や `math.pi` のようにたどる. prefixはhoverに出すimportの名前
+    fn lookup(&self, file: &File, path: &[&str], prefix: &str) -> Option<Found> {
+        let symbol = file.symbols.get(path[0])?;
+        let label = format!("{}{}", prefix, path.join("."));
+        match (symbol.kind, path.len()) {
+            (SymbolKind::Import, 1) => {
+                let uri = path_to_uri(&self.import_path(file, symbol)?);
+                let start = position(0, 0);
+                let range = Json::object(vec![("start", start.clone()), ("end", start)]);
+                Some(Found::new(symbol.detail.clone(), Some(Json::object(vec![("uri", Json::Str(uri)), ("range", range)]))))
+            },
+            (SymbolKind::Import, _) => {
+                let path_buf = self.import_path(file, symbol)?;
+                let uri = path_to_uri(&path_buf);
+                let text = match self.documents.get(&uri) {
+                    Some(t) => t.clone(),
+                    None => fs::read_to_string(&path_buf).ok()?,
+                };
+                let imported = File::parse(&uri, &text)?;
+                self.lookup(&imported, &path[1..], &format!("{}{}.", prefix, path[0]))
+            },
+            (SymbolKind::Struct, 1) | (SymbolKind::Enum, 1) => {
+                Some(Found::new(symbol.detail.clone(), Some(file.location(file.name_pos(symbol.pos, &symbol.name), &symbol.name))))
+            },
+            (SymbolKind::Struct, 2) => {
+                let (_, type_name) = file.symbols.registry.field(path[0], path[1]).ok()?;
+                Some(Found::new(format!("{}: {}", label, type_name), Some(file.member(symbol, path[1]))))
+            },
+            (SymbolKind::Enum, 2) => {
+                let (_, types) = file.symbols.registry.variant(path[0], path[1]).ok()?;
+                let text = match types.is_empty() {
+                    true => label,
+                    false => format!("{}({})", label, types.join(", ")),
+                };
+                Some(Found::new(text, Some(file.member(symbol, path[1]))))
+            },
+            (SymbolKind::Variable, _) => {
+                let mut type_name = symbol.detail.clone();
+                let mut definition = file.location(file.name_pos(symbol.pos, &symbol.name), &symbol.name);
+                for field in path[1..].iter() {
+                    let def = file.symbols.get(&type_name).filter(|s| s.kind == SymbolKind::Struct)?;
+                    let (_, t) = file.symbols.registry.field(&type_name, field).ok()?;
+                    definition = file.member(def, field);
+                    type_name = t.to_string();
+                }
+                let text = match type_name.is_empty() {
+                    true => label,
+                    false => format!("{}: {}", label, type_name),
+                };
+                Some(Found::new(text, Some(definition)))
+            },
+            _ => None,
+        }
+    }
+
+    fn import_path(&self, file: &File, symbol: &Symbol) -> Option<PathBuf> {
+        let import = file.ast.iter().find_map(|e| match e {
+            Expr::Import(i) if i.pos == symbol.pos => Some(i),
+            _ => None,
+        })?;
+        let from = uri_to_path(&file.uri)?;
+        from.parent()?.join(&import.path).canonicalize().ok()
+    }
+}
+
+fn capabilities() -> Json {
+    let sync = Json::object(vec![
+        ("openClose", Json::Bool(true)),
+        ("change", Json::int(1)),
+        ("save", Json::Bool(true)),
+    ]);
+    Json::object(vec![
+        ("capabilities", Json::object(vec![
+            ("textDocumentSync", sync),
+            ("hoverProvider", Json::Bool(true)),
+            ("definitionProvider", Json::Bool(true)),
+            ("documentSymbolProvider", Json::Bool(true)),
+            ("documentFormattingProvider", Json::Bool(true)),
+        ])),
+        ("serverInfo", Json::object(vec![("name", Json::str("nyaan")), ("version", Json::str(env!("CARGO_PKG_VERSION")))])),
+    ])
+}
+
+fn diagnostics(uri: &str, list: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/publishDiagnostics")),
+        ("params", Json::object(vec![("uri", Json::str(uri)), ("diagnostics", Json::Array(list))])),
+    ])
+}
+
+// nyaan checkと同じ順に調べる. 最初のエラーとその位置を返す
+fn check(uri: &str, text: &str) -> Result<(), (String, Option<Pos>)> {
+    let at = |err: String| { let pos = Pos::from_error(&err); (err, pos) };
+    let token_list = tokenizer::tokenize(&mut text.chars()).map_err(at)?;
+    let ast = parser::token_to_expr(&token_list).map_err(at)?;
+    // 保存されていない文書ならimportは展開しない
+    let ast = match uri_to_path(uri) {
+        None => ast,
+        Some(path) => match import::resolve(&path.to_string_lossy(), &ast) {
+            Ok(a) => a,
+            // importしたファイルのエラーの位置はこの文書の位置ではないので, import文に付ける
+            Err(err) => {
+                let import = ast.iter().find_map(|e| match e {
+                    Expr::Import(i) if err.contains(i.path.rsplit('/').next().unwrap_or("")) => Some(i.pos),
+                    _ => None,
+                });
+                let pos = import.or_else(|| Pos::from_error(&err));
+                return Err((err, pos));
+            },
+        },
+    };
+    types::check(&ast).map_err(at)?;
+    fold::fold(&ast).map_err(at)?;
+    Ok(())
+}
+
+struct Found {
+    // hoverに出すもの
+    text: String,
+    // 定義の場所 (Location)
+    definition: Option<Json>,
+    // カーソルの下の名前の範囲
+    range: Json,
+}
+
+impl Found {
+    fn new(text: String, definition: Option<Json>) -> Found {
+        Found { text, definition, range: Json::Null }
+    }
+}
+
+// 読めた文書. 構文木はimportを展開する前のもの
+struct File {
+    uri: String,
+    text: String,
+    tokens: Vec<Token>,
+    ast: Vec<Expr>,
+    symbols: Symbols,
+}
+
+impl File {
+    fn parse(uri: &str, text: &str) -> Option<File> {
+        let tokens = tokenizer::tokenize(&mut text.chars()).ok()?;
+        let ast = parser::token_to_expr(&tokens).ok()?;
+        let symbols = Symbols::new(&ast);
+        Some(File { uri: uri.to_string(), text: text.to_string(), tokens, ast, symbols })
+    }
+
+    fn line(&self, line: u32) -> &str {
+        self.text.split('\n').nth(line as usize - 1).unwrap_or("")
+    }
+
+    fn range(&self, pos: Pos, len: usize) -> Json {
+        range(self.line(pos.line), pos, len)
+    }
+
+    fn location(&self, pos: Pos, name: &str) -> Json {
+        Json::object(vec![("uri", Json::str(&self.uri)), ("range", self.range(pos, name.chars().count()))])
+    }
+
+    // fromから後ろで最初に出てくるnameのトークンの位置. `Shape.Circle` のCircleも探す
+    fn name_pos(&self, from: Pos, name: &str) -> Pos {
+        for token in self.tokens.iter().filter(|t| t.kind == Kind::Ident && t.pos >= from) {
+            let mut col = token.pos.col;
+            for part in token.text.split('.') {
+                if part == name {
+                    return Pos::new(token.pos.line, col);
+                }
+                col += part.chars().count() as u32 + 1;
+            }
+        }
+        from
+    }
+
+    // structのフィールドかenumのvariantの定義の場所
+    fn member(&self, owner: &Symbol, member: &str) -> Json {
+        let start = self.name_pos(owner.pos, &owner.name);
+        let pos = self.tokens.iter()
+            .skip_while(|t| t.pos <= start)
+            .take_while(|t| t.kind != Kind::Rbrace)
+            .find(|t| t.kind == Kind::Ident && t.text == member)
+            .map(|t| t.pos)
+            .unwrap_or(start);
+        self.location(pos, member)
+    }
+
+    // fromの後の最初の `{` から対応する `}` まで
+    fn block_range(&self, from: Pos) -> Json {
+        let mut depth = 0;
+        let mut end = from;
+        for token in self.tokens.iter().skip_while(|t| t.pos < from) {
+            match token.kind {
+                Kind::Lbrace => depth += 1,
+                Kind::Rbrace => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                end = Pos::new(token.pos.line, token.pos.col + 1);
+                break;
+            }
+        }
+        self.span(from, end)
+    }
+
+    // fromから次の `;` まで
+    fn statement_range(&self, from: Pos) -> Json {
+        let end = match self.tokens.iter().find(|t| t.pos >= from && t.kind == Kind::Semicolon) {
+            Some(t) => Pos::new(t.pos.line, t.pos.col + 1),
+            None => from,
+        };
+        self.span(from, end)
+    }
+
+    fn span(&self, start: Pos, end: Pos) -> Json {
+        let start = self.range(start, 0).get("start").cloned().unwrap_or(Json::Null);
+        let end = self.range(end, 0).get("start").cloned().unwrap_or(Json::Null);
+        Json::object(vec![("start", start), ("end", end)])
+    }
+
+    fn document_symbol(&self, symbol: &Symbol) -> Json {
+        let name_pos = self.name_pos(symbol.pos, &symbol.name);
+        let selection = self.range(name_pos, symbol.name.chars().count());
+        let (kind, detail, range, children) = match symbol.kind {
+            SymbolKind::Variable => (SYMBOL_VARIABLE, symbol.detail.clone(), self.statement_range(symbol.pos), Vec::new()),
+            SymbolKind::Import => (SYMBOL_MODULE, symbol.detail.clone(), self.statement_range(symbol.pos), Vec::new()),
+            SymbolKind::Test => (SYMBOL_FUNCTION, "test".to_string(), self.block_range(symbol.pos), Vec::new()),
+            SymbolKind::Struct => {
+                let fields = self.symbols.registry.get(&symbol.name).map(|d| d.fields.clone()).unwrap_or_default();
+                let children = fields.iter().map(|(name, type_name)| self.member_symbol(symbol, name, type_name.clone(), SYMBOL_FIELD)).collect();
+                (SYMBOL_STRUCT, String::new(), self.block_range(symbol.pos), children)
+            },
+            SymbolKind::Enum => {
+                let variants = self.symbols.registry.get_enum(&symbol.name).map(|d| d.variants.clone()).unwrap_or_default();
+                let children = variants.iter().map(|(name, types)| self.member_symbol(symbol, name, types.join(", "), SYMBOL_ENUM_MEMBER)).collect();
+                (SYMBOL_ENUM, String::new(), self.block_range(symbol.pos), children)
+            },
+        };
+        // testの名前は文字列なので `test` の範囲を選ぶ
+        let selection = match symbol.kind {
+            SymbolKind::Test => self.range(symbol.pos, "test".len()),
+            SymbolKind::Import => range.clone(),
+            _ => selection,
+        };
+        Json::object(vec![
+            ("name", Json::str(&symbol.name)),
+            ("detail", Json::Str(detail)),
+            ("kind", Json::int(kind)),
+            ("range", range),
+            ("selectionRange", selection),
+            ("children", Json::Array(children)),
+        ])
+    }
+
+    fn member_symbol(&self, owner: &Symbol, name: &str, detail: String, kind: i64) -> Json {
+        let range = self.member(owner, name).get("range").cloned().unwrap_or(Json::Null);
+        Json::object(vec![
+            ("name", Json::str(name)),
+            ("detail", Json::Str(detail)),
+            ("kind", Json::int(kind)),
+            ("range", range.clone()),
+            ("selectionRange", range),
+        ])
+    }
+}
+
+// `Point { x: 1 }` や `struct Point { x: int }` のx, `enum Shape { Circle(int) }` のCircleなら
+// 持ち主の型の名前
+fn member_of(tokens: &[Token], i: usize) -> Option<String> {
+    let mut depth = 0;
+    let mut j = i;
+    while j > 0 {
+        j -= 1;
+        match tokens[j].kind {
+            Kind::Rbrace => depth += 1,
+            Kind::Lbrace if depth > 0 => depth -= 1,
+            Kind::Lbrace => break,
+            _ => {},
+        }
+    }
+    if j == 0 || tokens[j].kind != Kind::Lbrace || tokens[j - 1].kind != Kind::Ident {
+        return None;
+    }
+    let owner = &tokens[j - 1];
+    let field = tokens.get(i + 1).map(|t| t.kind) == Some(Kind::Colon);
+    let variant = j >= 2 && tokens[j - 2].kind == Kind::Enum;
+    if field || variant {
+        return Some(owner.text.clone());
+    }
+    None
+}
+
+// LSPの位置は0始まりで, 列はUTF-16で数える. Posの列は文字で数える
+fn position(line: usize, character: usize) -> Json {
+    Json::object(vec![("line", Json::int(line as i64)), ("character", Json::int(character as i64))])
+}
+
+fn range(line: &str, pos: Pos, len: usize) -> Json {
+    let col = pos.col.saturating_sub(1) as usize;
+    let start: String = line.chars().take(col).collect();
+    let word: String = line.chars().skip(col).take(len).collect();
+    let character = utf16_len(&start);
+    let line = pos.line.saturating_sub(1) as usize;
+    Json::object(vec![("start", position(line, character)), ("end", position(line, character + utf16_len(&word)))])
+}
+
+fn utf16_len(s: &str) -> usize {
+    s.chars().map(|c| c.len_utf16()).sum()
+}
+
+// UTF-16での列を文字の番号にする
+fn char_index(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.chars().enumerate() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.chars().count()
+}
+
+// 診断で下線を引く長さ. 名前や数なら全体, それ以外は1文字
+fn word_len(line: &str, col: u32) -> usize {
+    let rest = line.chars().skip(col.saturating_sub(1) as usize);
+    let len = rest.clone().take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '.').count();
+    match len {
+        0 => rest.take(1).filter(|c| !c.is_whitespace()).count(),
+        n => n,
+    }
+}
+
+// file:///a/b%20c.ny を /a/b c.ny にする. fileでなければNone
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let bytes = rest.as_bytes();
+    let mut path = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => { path.push(b); i += 3; },
+            (b, _) => { path.push(b); i += 1; },
+        }
+    }
+    String::from_utf8(path).ok().map(PathBuf::from)
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(b as char),
+            b => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
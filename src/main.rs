@@ -1,6 +1,12 @@
-
-mod compiler;
+mod cli;
+mod harness;
+mod lsp;
+mod repl;
+use cli::{Backend, Command, Options};
+use nyaan::compiler;
 use compiler::*;
+#[cfg(feature = "llvm")]
+use inkwell::context::Context;
 use std::env;
 use std::fs;
 
@@ -8,32 +14,203 @@ use std::fs;
 fn main() {
 
     let args: Vec<String> = env::args().collect();
+    let options = cli::parse_args(&args);
+
+    match options.command {
+        Command::Help => {
+            print!("{}", cli::USAGE);
+        },
+        Command::Repl => {
+            repl::start(options.backend);
+        },
+        Command::Lsp => {
+            std::process::exit(lsp::start());
+        },
+        Command::Fmt => {
+            if !fmt(&options) {
+                std::process::exit(cli::EXIT_FAILURE);
+            }
+        },
+        Command::Test => {
+            let mut suites = Vec::new();
+            for input in options.inputs.iter() {
+                suites.push((input.clone(), harness::names(&parse(&options, input))));
+            }
+            if !harness::run(&options, &suites) {
+                std::process::exit(cli::EXIT_FAILURE);
+            }
+        },
+        Command::Build | Command::Run | Command::Check => {
+            // 入力ごとに別のモジュール, 別の出力ファイルを作る
+            for input in options.inputs.iter() {
+                let mut file_options = options.clone();
+                file_options.config.input = input.clone();
+                compile(&file_options, input);
+            }
+        }
+    }
+}
+
+fn compile(options: &Options, input: &str) {
+    let ast = parse(options, input);
+    let ast = match &options.test {
+        None => ast,
+        Some(name) => match harness::select(&ast, name) {
+            Ok(a) => a,
+            Err(err) => compile_error(input, err),
+        },
+    };
+
+    match options.backend {
+        Backend::Interp => interpret(options, input, &ast),
+        Backend::Llvm => codegen(options, input, &ast),
+    }
+}
+
+// 読み込みから定数の畳み込みまで. エラーならここで終了する
+fn parse(options: &Options, input: &str) -> Vec<Expr> {
+    let text = read(input);
+    let token_list = match tokenizer::tokenize(&mut text.chars()) {
+        Ok(t) => t,
+        Err(err) => compile_error(input, err),
+    };
+    if options.dump_tokens {
+        for token in token_list.iter() {
+            println!("{:?}", token);
+        }
+    }
+    let ast = match parser::token_to_expr(&token_list) {
+        Ok(a) => a,
+        Err(err) => compile_error(input, err),
+    };
+    let ast = match import::resolve(input, &ast) {
+        Ok(a) => a,
+        Err(err) => compile_error(input, err),
+    };
+    if let Err(err) = types::check(&ast) {
+        compile_error(input, err);
+    }
+    if options.dump_ast {
+        for expr in ast.iter() {
+            println!("{:?}", expr);
+        }
+    }
+    match fold::fold(&ast) {
+        Ok(a) => a,
+        Err(err) => compile_error(input, err),
+    }
+}
 
-    if args.len() == 1 {
-        println!("identify the filename to parse");
-        std::process::exit(1);
+// 整形して書き戻す. --checkなら書き換えずに整形されていないファイルを出力し, falseを返す
+fn fmt(options: &Options) -> bool {
+    let mut formatted_all = true;
+    for input in options.inputs.iter() {
+        let text = read(input);
+        let formatted = match format::format_source(&text) {
+            Ok(f) => f,
+            Err(err) => compile_error(input, err),
+        };
+        if formatted == text {
+            continue;
+        }
+        if options.check {
+            let line = text.lines().zip(formatted.lines()).take_while(|(a, b)| a == b).count() + 1;
+            println!("{}:{}: not formatted", input, line);
+            formatted_all = false;
+        } else if let Err(err) = fs::write(input, formatted) {
+            eprintln!("error: {}: {}", input, err);
+            std::process::exit(cli::EXIT_FAILURE);
+        }
     }
+    formatted_all
+}
 
+fn read(input: &str) -> String {
     // todo(入力ファイルが大きいと失敗する可能性がある)
-    let text = match fs::read_to_string(&args[1]) {
+    match fs::read_to_string(input) {
         Ok(n) => n,
         Err(err) => {
-            println!("error : {}", err);
-            std::process::exit(1);
+            eprintln!("error: {}: {}", input, err);
+            std::process::exit(cli::EXIT_FAILURE);
+        }
+    }
+}
+
+fn interpret(options: &Options, input: &str, ast: &[Expr]) {
+    match options.command {
+        Command::Build => compile_error(input, "`build` needs the llvm backend".to_string()),
+        Command::Run => {
+            let stdin = std::io::stdin();
+            let mut stdout = std::io::stdout();
+            match interp::run(ast, input, &mut stdin.lock(), &mut stdout) {
+                Ok(code) => std::process::exit(code),
+                Err(err) => compile_error(input, err),
+            }
+        },
+        _ => {}
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn codegen(options: &Options, input: &str, ast: &[Expr]) {
+    // 拡張子のない入力から実行ファイルを作るとソースを上書きしてしまう
+    if options.command == Command::Build && same_file(input, &options.config.output_path()) {
+        compile_error(input, format!("the output {} would overwrite the source file; use -o to choose another name", options.config.output_path().display()));
+    }
+    let context = Context::create();
+    let module = match compiler::generate(&context, ast, &options.config) {
+        Ok(m) => m,
+        Err(err) => compile_error(input, err),
+    };
+    if let Err(err) = optimize::verify(&module) {
+        eprintln!("error: internal compiler error: generated invalid LLVM IR for {}", input);
+        eprintln!("{}", err);
+        eprintln!("note: this is a bug in nyaan, please report it");
+        std::process::exit(cli::EXIT_ICE);
+    }
+    let passes = match optimize::optimize(&module, &options.config) {
+        Ok(p) => p,
+        Err(err) => {
+            eprintln!("error: internal compiler error: {}", err);
+            eprintln!("note: this is a bug in nyaan, please report it");
+            std::process::exit(cli::EXIT_ICE);
         }
     };
+    if options.print_passes {
+        for pass in passes.iter() {
+            eprintln!("pass: {}", pass);
+        }
+    }
+    if options.dump_ir {
+        print!("{}", module.print_to_string().to_string());
+    }
 
-    let token_list = tokenizer::tokenize(&mut text.chars());
-    for token in token_list.clone() {
-        println!("{:?}", token);
+    match options.command {
+        Command::Build => {
+            emit::emit(&module, &options.config);
+        },
+        Command::Run => {
+            let code = jit::run(&module, options.config.optimization_level());
+            std::process::exit(code);
+        },
+        _ => {}
     }
-    let ast = parser::token_to_expr(&token_list);
-    
-    
-    for expr in ast.clone() {
-        println!("{:?}", expr);
+}
+
+#[cfg(feature = "llvm")]
+fn same_file(input: &str, output: &std::path::Path) -> bool {
+    match (fs::canonicalize(input), fs::canonicalize(output)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
     }
-    
-    compiler::generate(&ast);
-    
+}
+
+#[cfg(not(feature = "llvm"))]
+fn codegen(_options: &Options, input: &str, _ast: &[Expr]) {
+    compile_error(input, "nyaan was built without LLVM support; use --backend=interp".to_string());
+}
+
+fn compile_error(input: &str, message: String) -> ! {
+    eprintln!("error: {}: {}", input, message);
+    std::process::exit(cli::EXIT_FAILURE);
 }
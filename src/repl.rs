@@ -0,0 +1,257 @@
+use crate::cli::Backend;
+use crate::compiler::*;
+use crate::compiler::interp::Interpreter;
+#[cfg(feature = "llvm")]
+use inkwell::context::Context;
+#[cfg(feature = "llvm")]
+use inkwell::OptimizationLevel;
+use std::io::{self, BufRead, Write};
+#[cfg(feature = "llvm")]
+use std::panic::{self, AssertUnwindSafe};
+
+const HELP: &str = "\
+:tokens   show the tokens of the last input
+:ast      show the statements kept in this session
+:ir       show the LLVM IR of this session
+:reset    forget every variable
+:help     show this help
+:quit     leave the repl
+
+With --backend=interp, input(), read_int() and read_char() read the next
+lines typed. The llvm backend runs each input again in a new process and
+cannot use them.
+";
+
+// JITでは入力ごとに, それまでの文(printを除く)と新しい文をまとめて子プロセスで実行する.
+// インタプリタは変数を持ち続けるので新しい文だけを実行する
+pub fn start(backend: Backend) {
+    let mut history: Vec<Expr> = Vec::new();
+    let mut last_tokens: Vec<Token> = Vec::new();
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut stdout = io::stdout();
+    let mut interpreter = Interpreter::new("", &mut input, &mut stdout);
+
+    loop {
+        let chunk = match read_chunk(interpreter.input()) {
+            None => break,
+            Some(c) => c
+        };
+        let input = chunk.trim();
+        if input.is_empty() {
+            continue;
+        }
+        if input.starts_with(':') {
+            match input {
+                ":tokens" => {
+                    for token in last_tokens.iter() {
+                        println!("{:?}", token);
+                    }
+                },
+                ":ast" => {
+                    for expr in history.iter().filter(|e| **e != Expr::Nope) {
+                        println!("{:?}", expr);
+                    }
+                },
+                ":ir" => {
+                    match show_ir(&history) {
+                        Ok(ir) => print!("{}", ir),
+                        Err(err) => eprintln!("error: {}", err),
+                    }
+                },
+                ":reset" => {
+                    history.clear();
+                    interpreter.reset();
+                },
+                ":help" => print!("{}", HELP),
+                ":quit" | ":q" => break,
+                _ => eprintln!("error: unknown command {} (try :help)", input),
+            }
+            continue;
+        }
+
+        let token_list = match tokenizer::tokenize(&mut chunk.chars()) {
+            Ok(t) => t,
+            Err(err) => { eprintln!("error: {}", err); continue; }
+        };
+        last_tokens = token_list.clone();
+        let stmts = match parse_input(&token_list).and_then(|s| fold::fold(&s)) {
+            Ok(s) if parser::find_return(&s).is_some() => {
+                eprintln!("error: return and exit end the program and cannot be used in the repl (try :quit)");
+                continue;
+            },
+            Ok(_) if backend == Backend::Llvm && reads_input(&token_list) => {
+                eprintln!("error: input(), read_int() and read_char() cannot be used in the repl with the llvm backend (try --backend=interp)");
+                continue;
+            },
+            Ok(s) => s,
+            Err(err) => { eprintln!("error: {}", err); continue; }
+        };
+
+        let result = match backend {
+            Backend::Interp => interpreter.exec(&stmts),
+            Backend::Llvm => run_jit(&history, &stmts),
+        };
+        match result {
+            Ok(_) => history.extend(stmts.iter().map(strip_print)),
+            Err(err) => eprintln!("error: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn run_jit(history: &[Expr], stmts: &[Expr]) -> Result<(), String> {
+    let mut program = history.to_vec();
+    program.extend(stmts.iter().cloned());
+    let context = Context::create();
+    // コード生成のバグでpanicしてもセッションは続ける
+    let module = match panic::catch_unwind(AssertUnwindSafe(|| generate(&context, &program, &emit::Config::new()))) {
+        Ok(module) => module?,
+        Err(_) => return Err("internal compiler error: code generation panicked".to_string()),
+    };
+    if let Err(err) = optimize::verify(&module) {
+        return Err(format!("internal compiler error: generated invalid LLVM IR\n{}", err));
+    }
+    match run_child(|| jit::run(&module, OptimizationLevel::None))? {
+        0 => Ok(()),
+        PANIC_EXIT_CODE => Err("the input panicked and was not kept".to_string()),
+        code => Err(format!("the input stopped with exit code {} and was not kept", code)),
+    }
+}
+
+// panicやexitはプロセスを終わらせるので, forkした子でJITを動かして終了コードだけを受け取る
+#[cfg(all(feature = "llvm", unix))]
+fn run_child(run: impl FnOnce() -> i32) -> Result<i32, String> {
+    extern "C" {
+        fn fork() -> i32;
+        fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+        fn _exit(code: i32) -> !;
+        fn fflush(stream: *mut std::ffi::c_void) -> i32;
+    }
+    // 書き出していない出力が子でもう一度出ないようにする
+    let _ = io::stdout().flush();
+    unsafe { fflush(std::ptr::null_mut()); }
+    let pid = unsafe { fork() };
+    if pid < 0 {
+        return Err(format!("could not start the program: {}", io::Error::last_os_error()));
+    }
+    if pid == 0 {
+        let code = panic::catch_unwind(AssertUnwindSafe(run)).unwrap_or(PANIC_EXIT_CODE);
+        unsafe { _exit(code) }
+    }
+    let mut status = 0;
+    if unsafe { waitpid(pid, &mut status, 0) } < 0 {
+        return Err(format!("could not wait for the program: {}", io::Error::last_os_error()));
+    }
+    if status & 0x7f != 0 {
+        return Err(format!("the input was killed by signal {} and was not kept", status & 0x7f));
+    }
+    Ok((status >> 8) & 0xff)
+}
+
+// forkがないので同じプロセスで動かす. panicやexitではreplも終わる
+#[cfg(all(feature = "llvm", not(unix)))]
+fn run_child(run: impl FnOnce() -> i32) -> Result<i32, String> {
+    Ok(run())
+}
+
+#[cfg(feature = "llvm")]
+fn show_ir(history: &[Expr]) -> Result<String, String> {
+    let context = Context::create();
+    let module = generate(&context, history, &emit::Config::new())?;
+    Ok(module.print_to_string().to_string())
+}
+
+#[cfg(not(feature = "llvm"))]
+fn run_jit(_history: &[Expr], _stmts: &[Expr]) -> Result<(), String> {
+    Err("nyaan was built without LLVM support; use --backend=interp".to_string())
+}
+
+#[cfg(not(feature = "llvm"))]
+fn show_ir(_history: &[Expr]) -> Result<String, String> {
+    Err("nyaan was built without LLVM support".to_string())
+}
+
+// `{` が閉じるまで行を読み続ける
+fn read_chunk(input: &mut dyn BufRead) -> Option<String> {
+    let mut chunk = String::new();
+    let mut depth = 0;
+    loop {
+        print!("{}", if chunk.is_empty() { "nyaan> " } else { "...    " });
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(n) if n > 0 => {},
+            _ => {
+                println!();
+                return None;
+            }
+        }
+        let line = line.trim_end_matches('\n');
+        depth += brace_depth(line);
+        chunk.push_str(line);
+        chunk.push('\n');
+        if depth <= 0 {
+            return Some(chunk);
+        }
+    }
+}
+
+fn brace_depth(line: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    for ch in line.chars() {
+        match quote {
+            Some(q) => if ch == q { quote = None; },
+            None => match ch {
+                '"' | '\'' => quote = Some(ch),
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+    depth
+}
+
+// 文として読めない入力は式とみなし, その値を表示する
+fn parse_input(token_list: &[Token]) -> Result<Vec<Expr>, String> {
+    let first = match token_list.first() {
+        None => return Err("input is empty".to_string()),
+        Some(t) => t,
+    };
+    match first.kind {
+        Kind::Else => Err("`else` must follow `}` on the same line in the repl".to_string()),
+        Kind::If | Kind::Print | Kind::Println => parser::token_to_expr(token_list),
+        Kind::Import => parser::token_to_expr(token_list).and_then(|s| import::resolve("", &s)),
+        Kind::Let | Kind::Struct | Kind::Enum | Kind::Match | Kind::Return => parser::token_to_expr(token_list),
+        Kind::Ident if ["push", "exit", "panic", "assert", "assert_eq"].contains(&first.text.as_str()) => parser::token_to_expr(token_list),
+        // `a[0] = 1;` のように=があれば代入文
+        Kind::Ident if token_list.iter().any(|t| t.kind == Kind::Assign) => parser::token_to_expr(token_list),
+        _ => {
+            let expr = parser::parse_expression(token_list)?;
+            Ok(vec![Expr::Print(Box::new(Print::new(vec![expr], true, first.pos))), Expr::Nope])
+        }
+    }
+}
+
+// JITでは入力のたびに前の文も実行し直すので, 読み込みがあると同じ入力をもう一度読もうとしてしまう
+fn reads_input(token_list: &[Token]) -> bool {
+    token_list.windows(2).any(|w| w[0].kind == Kind::Ident && ["input", "read_int", "read_char"].contains(&w[0].text.as_str()) && w[1].kind == Kind::Lparen)
+}
+
+// 再実行するときに以前の出力を繰り返さないようにする
+fn strip_print(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Print(_) => Expr::Nope,
+        Expr::If(e) => Expr::If(Box::new(If::new(e.condition.clone(), e.list.iter().map(strip_print).collect(), e.pos))),
+        Expr::ElseIf(e) => Expr::ElseIf(Box::new(ElseIf::new(e.condition.clone(), e.list.iter().map(strip_print).collect(), e.pos))),
+        Expr::Else(e) => Expr::Else(Box::new(Else::new(e.list.iter().map(strip_print).collect(), e.pos))),
+        Expr::Match(e) => {
+            let arms = e.arms.iter().map(|arm| Arm::new(arm.pattern.clone(), arm.bindings.clone(), arm.list.iter().map(strip_print).collect(), arm.pos)).collect();
+            Expr::Match(Box::new(Match::new(e.value.clone(), arms, e.pos)))
+        },
+        Expr::Test(e) => Expr::Test(Box::new(Test::new(e.name.clone(), e.list.iter().map(strip_print).collect(), e.pos))),
+        _ => expr.clone(),
+    }
+}
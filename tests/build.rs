@@ -0,0 +1,27 @@
+// `nyaan build` の出力先. 拡張子のない入力でもソースを上書きしない
+use std::env;
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn build_keeps_extensionless_source() {
+    let dir = env::temp_dir().join(format!("nyaan-build-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("prog");
+    let source = "println(1);\n";
+    fs::write(&input, source).unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_nyaan"))
+        .arg("build")
+        .arg(&input)
+        .output()
+        .expect("cannot start nyaan");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(1), "stderr: {}", stderr);
+    // LLVMなしでは`build`そのものができない
+    if cfg!(feature = "llvm") {
+        assert!(stderr.contains("would overwrite the source file; use -o"), "stderr: {}", stderr);
+    }
+    assert_eq!(fs::read_to_string(&input).unwrap(), source);
+    let _ = fs::remove_dir_all(&dir);
+}
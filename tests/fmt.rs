@@ -0,0 +1,71 @@
+// tests/fmt/foo.ny を `nyaan fmt` で整形し, foo.expected と比べる.
+// 整形したものを --check すると何も言われず, 元のものは整形されていないと言われること.
+// `UPDATE_EXPECT=1 cargo test` で foo.expected を今の出力で書き直す
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn nyaan(args: &[&str], file: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nyaan"))
+        .arg("fmt")
+        .args(args)
+        .arg(file)
+        .output()
+        .expect("cannot start nyaan")
+}
+
+// 元のファイルを書き換えないように一時ディレクトリに写して整形する
+fn scratch(name: &str, text: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("nyaan-fmt-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn fmt() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fmt");
+    let updating = env::var("UPDATE_EXPECT").as_deref() == Ok("1");
+    let mut list: Vec<PathBuf> = fs::read_dir(&dir).unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "ny"))
+        .collect();
+    list.sort();
+    assert!(!list.is_empty(), "no test programs in {}", dir.display());
+
+    for path in list.iter() {
+        let file = path.file_name().unwrap().to_string_lossy().to_string();
+        let source = fs::read_to_string(path).unwrap();
+        let copy = scratch(&file, &source);
+
+        let check = nyaan(&["--check"], &copy);
+        assert_eq!(check.status.code(), Some(1), "{}: --check should fail on the original", file);
+        assert!(String::from_utf8_lossy(&check.stdout).contains("not formatted"), "{}: --check printed {:?}", file, check.stdout);
+        assert_eq!(fs::read_to_string(&copy).unwrap(), source, "{}: --check must not rewrite the file", file);
+
+        let out = nyaan(&[], &copy);
+        assert!(out.status.success(), "{}: fmt failed: {}", file, String::from_utf8_lossy(&out.stderr));
+        let formatted = fs::read_to_string(&copy).unwrap();
+        if updating {
+            fs::write(path.with_extension("expected"), &formatted).unwrap();
+        }
+        let expected = fs::read_to_string(path.with_extension("expected")).unwrap_or_default();
+        assert_eq!(formatted, expected, "{}: formatted source differs (run with UPDATE_EXPECT=1 to accept it)", file);
+
+        let again = nyaan(&["--check"], &copy);
+        assert_eq!(again.status.code(), Some(0), "{}: formatted source is not stable: {}", file, String::from_utf8_lossy(&again.stdout));
+        assert!(again.stdout.is_empty());
+    }
+}
+
+#[test]
+fn fmt_parse_error() {
+    let source = "a = 1\nb = 2;\n";
+    let copy = scratch("broken.ny", source);
+    let out = nyaan(&[], &copy);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with("error: "), "stderr: {}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(fs::read_to_string(&copy).unwrap(), source);
+}
@@ -0,0 +1,38 @@
+// fuzz/regressions/parse/ に置いた, fuzz/fuzz_targets/parse.rs で一度落ちた入力を同じ手順で流す.
+// libFuzzerがなくても cargo test で確かめられる
+use nyaan::compiler::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn parse(data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    let tokens = match tokenizer::tokenize(&mut text.chars()) {
+        Ok(t) => t,
+        Err(_) => return,
+    };
+    let ast = match parser::token_to_expr(&tokens) {
+        Ok(a) => a,
+        Err(_) => return,
+    };
+    if let Err(err) = format::format_source(&text) {
+        panic!("{}\n{}", err, text);
+    }
+    if types::check(&ast).is_ok() {
+        let _ = fold::fold(&ast);
+    }
+}
+
+#[test]
+fn parse_regressions() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz").join("regressions").join("parse");
+    let mut list: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+    list.sort();
+    assert!(!list.is_empty(), "no inputs in {}", dir.display());
+    for path in list {
+        let file = path.file_name().unwrap().to_string_lossy().to_string();
+        let data = fs::read(&path).unwrap();
+        // 深い入れ子はテストのスレッドの小さいスタックでは足りないので, mainと同じ大きさにする
+        let handle = std::thread::Builder::new().stack_size(8 << 20).spawn(move || parse(&data)).unwrap();
+        assert!(handle.join().is_ok(), "{} panicked", file);
+    }
+}
@@ -0,0 +1,121 @@
+// tests/golden/*.ny をそれぞれのバックエンドで実行して, 隣に置いた期待する結果と比べる.
+//   foo.stdout  標準出力
+//   foo.stderr  標準エラー (コンパイルエラーやpanicのメッセージ). なければ空
+//   foo.status  終了コード. なければ0
+//   foo.stdin   あれば標準入力に渡す
+// `UPDATE_EXPECT=1 cargo test` で期待する結果を今の出力で書き直す.
+// importされるだけのファイルは tests/golden/lib に置く
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(PartialEq, Debug)]
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    status: i32,
+}
+
+fn backends() -> Vec<&'static str> {
+    let mut list = vec!["interp"];
+    if cfg!(feature = "llvm") {
+        list.push("llvm");
+    }
+    list
+}
+
+// メッセージのファイル名がどこで実行しても同じになるように, golden/の中で実行する
+fn run(dir: &Path, file: &str, backend: &str, stdin: &[u8]) -> Outcome {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nyaan"))
+        .arg("run")
+        .arg(format!("--backend={}", backend))
+        .arg(file)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("cannot start nyaan");
+    // 入力を読まずに終わるプログラムもあるので書き込みの失敗は無視する
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    let out = child.wait_with_output().unwrap();
+    Outcome {
+        stdout: String::from_utf8_lossy(&out.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&out.stderr).to_string(),
+        status: out.status.code().unwrap_or(-1),
+    }
+}
+
+fn expected(path: &Path) -> Outcome {
+    let read = |ext: &str| fs::read_to_string(path.with_extension(ext)).unwrap_or_default();
+    Outcome {
+        stdout: read("stdout"),
+        stderr: read("stderr"),
+        status: read("status").trim().parse().unwrap_or(0),
+    }
+}
+
+// 空のstderrと0のstatusはファイルを作らない
+fn update(path: &Path, outcome: &Outcome) {
+    let write = |ext: &str, text: String, keep: bool| {
+        let file = path.with_extension(ext);
+        if keep {
+            fs::write(&file, text).unwrap();
+        } else if file.exists() {
+            fs::remove_file(&file).unwrap();
+        }
+    };
+    write("stdout", outcome.stdout.clone(), true);
+    write("stderr", outcome.stderr.clone(), !outcome.stderr.is_empty());
+    write("status", format!("{}\n", outcome.status), outcome.status != 0);
+}
+
+fn cases(dir: &Path) -> Vec<PathBuf> {
+    let mut list: Vec<PathBuf> = fs::read_dir(dir).unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "ny"))
+        .collect();
+    list.sort();
+    list
+}
+
+fn report(name: &str, backend: &str, expected: &Outcome, actual: &Outcome) -> String {
+    let mut text = format!("---- {} ({}) ----\n", name, backend);
+    if expected.stdout != actual.stdout {
+        text.push_str(&format!("stdout\n  expected: {:?}\n    actual: {:?}\n", expected.stdout, actual.stdout));
+    }
+    if expected.stderr != actual.stderr {
+        text.push_str(&format!("stderr\n  expected: {:?}\n    actual: {:?}\n", expected.stderr, actual.stderr));
+    }
+    if expected.status != actual.status {
+        text.push_str(&format!("status\n  expected: {}\n    actual: {}\n", expected.status, actual.status));
+    }
+    text
+}
+
+#[test]
+fn golden() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    let updating = env::var("UPDATE_EXPECT").as_deref() == Ok("1");
+    let mut failures = Vec::new();
+    let list = cases(&dir);
+    for path in list.iter() {
+        let file = path.file_name().unwrap().to_string_lossy().to_string();
+        let stdin = fs::read(path.with_extension("stdin")).unwrap_or_default();
+        for (i, backend) in backends().iter().enumerate() {
+            let actual = run(&dir, &file, backend, &stdin);
+            // 書き直すのは最初のバックエンドの結果だけ. 他のバックエンドはそれと比べる
+            if updating && i == 0 {
+                update(path, &actual);
+            }
+            let expected = expected(path);
+            if expected != actual {
+                failures.push(report(&file, backend, &expected, &actual));
+            }
+        }
+    }
+    assert!(!list.is_empty(), "no test programs in {}", dir.display());
+    assert!(failures.is_empty(), "{} golden test(s) failed (run with UPDATE_EXPECT=1 to accept the new output)\n\n{}", failures.len(), failures.join("\n"));
+}
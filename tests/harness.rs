@@ -0,0 +1,30 @@
+// `nyaan test` の時間切れ. 長くかかるtestは止めて, 画面とJUnitの両方で失敗にする
+use std::env;
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn test_timeout() {
+    let dir = env::temp_dir().join(format!("nyaan-harness-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // ループがないので, 文をたくさん並べて時間のかかるtestにする
+    let source = format!("x = 0;\ntest \"slow\" {{\n{}}}\n", "    x = x + 1;\n".repeat(20000));
+    let input = dir.join("slow.ny");
+    fs::write(&input, source).unwrap();
+    let junit = dir.join("junit.xml");
+
+    let out = Command::new(env!("CARGO_BIN_EXE_nyaan"))
+        .args(["test", "--backend=interp", "--timeout", "0.001", "--junit"])
+        .arg(&junit)
+        .arg(&input)
+        .output()
+        .expect("cannot start nyaan");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(out.status.code(), Some(1), "stdout: {}", stdout);
+    assert!(stdout.contains("test slow ... FAILED"), "stdout: {}", stdout);
+    assert!(stdout.contains("the test timed out after 0.00s"), "stdout: {}", stdout);
+    let xml = fs::read_to_string(&junit).unwrap();
+    assert!(xml.contains("failures=\"1\""), "junit: {}", xml);
+    assert!(xml.contains("<failure message=\"the test timed out after 0.00s\">"), "junit: {}", xml);
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,248 @@
+// `nyaan lsp` を起動してJSON-RPCのメッセージを順に送り, 返事を確かめる
+use nyaan::json::Json;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+const MAIN: &str = "\
+import \"lib/math.ny\";
+struct Point { x: int, y: int }
+struct Line { start: Point, end: Point }
+enum Shape { Circle(int), Empty }
+p = Point { x: 1, y: 2 };
+l = Line { start: p, end: Point { x: 3, y: 4 } };
+s = Shape.Circle(math.pi);
+match s {
+    Shape.Circle(r) => { println(r + l.end.x); }
+    _ => { println(\"none\"); }
+}
+test \"origin\" {
+    assert_eq(p.x, 1);
+}
+";
+
+const MATH: &str = "\
+pi = 3;
+";
+
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i64,
+    // 返事を待つ間に届いた通知
+    notifications: Vec<Json>,
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_nyaan"))
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("cannot start nyaan lsp");
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Client { child, stdin, stdout, next_id: 1, notifications: Vec::new() }
+    }
+
+    fn send_raw(&mut self, body: &str) {
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn read(&mut self) -> Json {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            assert!(self.stdout.read_line(&mut line).unwrap() > 0, "nyaan lsp closed its output");
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(n) = line.strip_prefix("Content-Length: ") {
+                length = n.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        Json::parse(&String::from_utf8(body).unwrap()).unwrap()
+    }
+
+    fn request(&mut self, method: &str, params: Json) -> Json {
+        let id = self.next_id;
+        self.next_id += 1;
+        let message = Json::object(vec![("jsonrpc", Json::str("2.0")), ("id", Json::int(id)), ("method", Json::str(method)), ("params", params)]);
+        self.send_raw(&message.to_string());
+        loop {
+            let reply = self.read();
+            if reply.get("id").and_then(|i| i.as_i64()) == Some(id) {
+                return reply;
+            }
+            self.notifications.push(reply);
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        let message = Json::object(vec![("jsonrpc", Json::str("2.0")), ("method", Json::str(method)), ("params", params)]);
+        self.send_raw(&message.to_string());
+    }
+
+    // 次に届くpublishDiagnosticsの診断
+    fn diagnostics(&mut self) -> Vec<Json> {
+        let message = match self.notifications.is_empty() {
+            true => self.read(),
+            false => self.notifications.remove(0),
+        };
+        assert_eq!(message.get("method").and_then(|m| m.as_str()), Some("textDocument/publishDiagnostics"), "{}", message);
+        message.path("params.diagnostics").and_then(|d| d.as_array()).unwrap().clone()
+    }
+
+    fn exit(mut self) -> Option<i32> {
+        self.notify("exit", Json::Null);
+        self.child.wait().unwrap().code()
+    }
+}
+
+fn workspace() -> PathBuf {
+    let dir = env::temp_dir().join(format!("nyaan-lsp-{}", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("main.ny"), MAIN).unwrap();
+    fs::write(dir.join("lib").join("math.ny"), MATH).unwrap();
+    dir
+}
+
+fn uri(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
+
+fn document(uri: &str) -> Json {
+    Json::object(vec![("uri", Json::str(uri))])
+}
+
+// lineの行でneedleが始まる場所からoffset文字目
+fn cursor(uri: &str, line: usize, needle: &str, offset: usize) -> Json {
+    let character = MAIN.lines().nth(line).unwrap().find(needle).unwrap() + offset;
+    Json::object(vec![
+        ("textDocument", document(uri)),
+        ("position", Json::object(vec![("line", Json::int(line as i64)), ("character", Json::int(character as i64))])),
+    ])
+}
+
+fn hover(client: &mut Client, params: Json) -> String {
+    let reply = client.request("textDocument/hover", params);
+    let value = reply.path("result.contents.value").and_then(|v| v.as_str()).unwrap_or_else(|| panic!("no hover: {}", reply));
+    value.trim_start_matches("```nyaan\n").trim_end_matches("\n```").to_string()
+}
+
+// 定義の (URI, 行, 列)
+fn definition(client: &mut Client, params: Json) -> (String, i64, i64) {
+    let reply = client.request("textDocument/definition", params);
+    let location = reply.get("result").unwrap_or_else(|| panic!("no definition: {}", reply));
+    let start = |key: &str| location.path(&format!("range.start.{}", key)).and_then(|v| v.as_i64()).unwrap();
+    (location.get("uri").and_then(|u| u.as_str()).unwrap().to_string(), start("line"), start("character"))
+}
+
+#[test]
+fn lsp_session() {
+    let dir = workspace();
+    let main = uri(&dir.join("main.ny"));
+    let mut client = Client::start();
+
+    let init = client.request("initialize", Json::object(vec![("processId", Json::Null), ("capabilities", Json::object(vec![]))]));
+    for capability in ["hoverProvider", "definitionProvider", "documentSymbolProvider", "documentFormattingProvider"] {
+        assert_eq!(init.path(&format!("result.capabilities.{}", capability)), Some(&Json::Bool(true)), "{}", init);
+    }
+    client.notify("initialized", Json::object(vec![]));
+
+    let item = Json::object(vec![("uri", Json::str(&main)), ("languageId", Json::str("nyaan")), ("version", Json::int(1)), ("text", Json::str(MAIN))]);
+    client.notify("textDocument/didOpen", Json::object(vec![("textDocument", item)]));
+    assert_eq!(client.diagnostics(), Vec::<Json>::new());
+
+    // 型はhoverで, 定義の場所はdefinitionで
+    assert_eq!(hover(&mut client, cursor(&main, 5, "l =", 0)), "l: Line");
+    assert_eq!(hover(&mut client, cursor(&main, 8, "l.end.x", 2)), "l.end: Point");
+    assert_eq!(hover(&mut client, cursor(&main, 8, "l.end.x", 6)), "l.end.x: int");
+    assert_eq!(hover(&mut client, cursor(&main, 8, "r +", 0)), "r: int");
+    assert_eq!(hover(&mut client, cursor(&main, 6, "Circle", 0)), "Shape.Circle(int)");
+    assert_eq!(hover(&mut client, cursor(&main, 6, "math.pi", 5)), "math.pi: int");
+    assert_eq!(hover(&mut client, cursor(&main, 5, "start:", 0)), "Line.start: Point");
+    assert_eq!(hover(&mut client, cursor(&main, 5, "Point {", 0)), "struct Point { x: int, y: int }");
+    assert_eq!(client.request("textDocument/hover", cursor(&main, 8, "println", 0)).get("result"), Some(&Json::Null));
+
+    assert_eq!(definition(&mut client, cursor(&main, 12, "p.x", 0)), (main.clone(), 4, 0));
+    assert_eq!(definition(&mut client, cursor(&main, 12, "p.x", 2)), (main.clone(), 1, 15));
+    assert_eq!(definition(&mut client, cursor(&main, 8, "Circle", 0)), (main.clone(), 3, 13));
+    assert_eq!(definition(&mut client, cursor(&main, 8, "r +", 0)), (main.clone(), 8, 17));
+    let (math, line, _) = definition(&mut client, cursor(&main, 6, "math.pi", 5));
+    assert!(math.ends_with("/lib/math.ny"), "{}", math);
+    assert_eq!(line, 0);
+
+    let symbols = client.request("textDocument/documentSymbol", Json::object(vec![("textDocument", document(&main))]));
+    let names: Vec<&str> = symbols.get("result").and_then(|r| r.as_array()).unwrap().iter()
+        .map(|s| s.get("name").and_then(|n| n.as_str()).unwrap())
+        .collect();
+    assert_eq!(names, ["math", "Point", "Line", "Shape", "p", "l", "s", "origin"]);
+    assert_eq!(symbols.path("result.1.children.0.name").and_then(|n| n.as_str()), Some("x"));
+    assert_eq!(symbols.path("result.4.detail").and_then(|n| n.as_str()), Some("Point"));
+
+    // 変更のたびに診断を送り直す
+    let change = |text: &str| Json::object(vec![
+        ("textDocument", Json::object(vec![("uri", Json::str(&main)), ("version", Json::int(2))])),
+        ("contentChanges", Json::Array(vec![Json::object(vec![("text", Json::str(text))])])),
+    ]);
+    client.notify("textDocument/didChange", change("a = 1\nb = 2;\n"));
+    let list = client.diagnostics();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].get("message").and_then(|m| m.as_str()), Some("; is missing"));
+    assert_eq!(list[0].path("range.start.line").and_then(|l| l.as_i64()), Some(1));
+    client.notify("textDocument/didChange", change("a = \"abc;\n"));
+    assert!(client.diagnostics()[0].get("message").and_then(|m| m.as_str()).unwrap().contains("string literal"));
+    client.notify("textDocument/didChange", change("enum E { A, B }\ne = E.A;\nmatch e {\n    E.A => { println(1); }\n}\n"));
+    let list = client.diagnostics();
+    assert_eq!(list[0].get("message").and_then(|m| m.as_str()), Some("match is not exhaustive: E.B not covered"));
+    assert_eq!(list[0].path("range.start.line").and_then(|l| l.as_i64()), Some(2));
+    client.notify("textDocument/didChange", change("a = 1;\nb = 2147483647 + 1;\n"));
+    let list = client.diagnostics();
+    assert_eq!(list[0].get("message").and_then(|m| m.as_str()), Some("overflow in constant expression 2147483647 + 1"));
+    assert_eq!(list[0].path("range.start.line").and_then(|l| l.as_i64()), Some(1));
+    assert_eq!(list[0].path("range.start.character").and_then(|c| c.as_i64()), Some(15));
+
+    client.notify("textDocument/didChange", change("a =   1;\nif a == 1 { println(a); }\n"));
+    assert_eq!(client.diagnostics(), Vec::<Json>::new());
+    let edits = client.request("textDocument/formatting", Json::object(vec![("textDocument", document(&main))]));
+    assert_eq!(edits.path("result.0.newText").and_then(|t| t.as_str()), Some("a = 1;\nif a == 1 {\n    println(a);\n}\n"));
+    assert_eq!(edits.path("result.0.range.end.line").and_then(|l| l.as_i64()), Some(2));
+
+    let unknown = client.request("textDocument/unknown", Json::Null);
+    assert_eq!(unknown.path("error.code").and_then(|c| c.as_i64()), Some(-32601));
+
+    client.notify("textDocument/didClose", Json::object(vec![("textDocument", document(&main))]));
+    assert_eq!(client.diagnostics(), Vec::<Json>::new());
+    assert_eq!(client.request("shutdown", Json::Null).get("result"), Some(&Json::Null));
+    assert_eq!(client.exit(), Some(0));
+}
+
+#[test]
+fn lsp_protocol_errors() {
+    let mut client = Client::start();
+    client.send_raw("{not json");
+    let reply = client.read();
+    assert_eq!(reply.path("error.code").and_then(|c| c.as_i64()), Some(-32700));
+    assert_eq!(reply.get("id"), Some(&Json::Null));
+
+    let hover = client.request("textDocument/hover", Json::object(vec![("textDocument", document("file:///not/open.ny"))]));
+    assert_eq!(hover.path("error.code").and_then(|c| c.as_i64()), Some(-32602));
+
+    let formatting = |client: &mut Client| client.request("textDocument/formatting", Json::object(vec![("textDocument", document("untitled:broken"))]));
+    let item = Json::object(vec![("uri", Json::str("untitled:broken")), ("languageId", Json::str("nyaan")), ("version", Json::int(1)), ("text", Json::str("a = (1;\n"))]);
+    client.notify("textDocument/didOpen", Json::object(vec![("textDocument", item)]));
+    assert_eq!(client.diagnostics().len(), 1);
+    assert_eq!(formatting(&mut client).path("error.code").and_then(|c| c.as_i64()), Some(-32803));
+
+    // shutdownなしのexitは失敗として終わる
+    assert_eq!(client.exit(), Some(1));
+}
@@ -0,0 +1,31 @@
+// `nyaan repl --backend=interp` に標準入力から文を流す.
+// インタプリタのreplではinput()やread_int()がreplと同じ入力から次の行を読む
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn repl(input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nyaan"))
+        .args(["repl", "--backend=interp"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("cannot start nyaan");
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success(), "stderr: {}", String::from_utf8_lossy(&out.stderr));
+    // プロンプトを除いて出力だけを見る
+    String::from_utf8_lossy(&out.stdout).replace("nyaan> ", "").replace("...    ", "")
+}
+
+#[test]
+fn repl_reads_the_next_line() {
+    let out = repl("x = input();\nhello there\nn = read_int();\n42\nx\nn + 1\n");
+    assert_eq!(out.trim(), "hello there\n43");
+}
+
+#[test]
+fn repl_keeps_variables() {
+    let out = repl("a = 2;\nif a == 2 {\n    println(\"two\");\n}\na * 3\n");
+    assert_eq!(out.trim(), "two\n6");
+}